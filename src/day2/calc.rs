@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::ExitCode;
+
+use thiserror::Error;
+
+use day2::expr::{
+    eval_with, parse, parse_function, simplify, trace, typecheck_with, Env, EvalError, Expected,
+    Expression, Function, ParseError, Type, TypeError, KEYWORDS,
+};
+
+/// Why a line of input could not be handled.
+#[derive(Debug, Error)]
+enum LineError {
+    /// The line is malformed at byte `offset`.
+    #[error("{message}")]
+    Syntax { offset: usize, message: String },
+    #[error(transparent)]
+    Type(#[from] TypeError),
+    #[error(transparent)]
+    Eval(#[from] EvalError),
+}
+
+/// Variables bound with `let` and functions defined with `fn`, which stay
+/// visible to later lines.
+#[derive(Default)]
+struct Session {
+    env: Env,
+    types: HashMap<String, Type>,
+    /// In the order they were defined, so later ones shadow earlier ones.
+    functions: Vec<Function>,
+}
+
+impl Session {
+    /// Handle one line of input, returning the text to print, if any.
+    ///
+    /// A line is either empty, a command (`:ast <expr>`, `:simplify <expr>`
+    /// or `:trace <expr>`), a binding (`let <name> = <expr>`), a function
+    /// definition (`fn <name>(<params>) = <expr>`) or an expression to
+    /// evaluate. Lines starting with `let` or `fn` that parse as a whole are
+    /// expressions, such as `let x = 2 in x * x`.
+    fn run(&mut self, line: &str) -> Result<Option<String>, LineError> {
+        let trimmed = line.trim_start();
+        let start = line.len() - trimmed.len();
+        if trimmed.trim_end().is_empty() {
+            return Ok(None);
+        }
+        if let Some(command) = trimmed.strip_prefix(':') {
+            let name = command.split(char::is_whitespace).next().unwrap_or("");
+            let body = start + 1 + name.len();
+            return match name {
+                "ast" => Ok(Some(format!("{:#}", parse_from(line, body)?))),
+                "simplify" => Ok(Some(simplify(parse_from(line, body)?).to_string())),
+                "trace" => {
+                    let e = self.scoped(parse_from(line, body)?);
+                    // Leave out the definitions around the expression.
+                    let traced = trace(&e, &self.env);
+                    let explained = traced.explain(self.functions.len()).to_string();
+                    Ok(Some(explained.trim_end_matches('\n').to_string()))
+                }
+                _ => Err(LineError::Syntax {
+                    offset: start,
+                    message: format!("unknown command `:{name}`"),
+                }),
+            };
+        }
+        let expression = parse_from(line, 0);
+        if expression.is_err() && keyword(trimmed, "fn") {
+            return self.define(line, start).map(Some);
+        }
+        if expression.is_err() && keyword(trimmed, "let") {
+            // Report whichever way of reading the line got further.
+            return match (self.bind(line, start), expression) {
+                (
+                    Err(LineError::Syntax { offset, .. }),
+                    Err(err @ LineError::Syntax { offset: furthest, .. }),
+                ) if furthest > offset => Err(err),
+                (result, _) => result.map(Some),
+            };
+        }
+        let (value, ty) = self.evaluate(expression?)?;
+        Ok(Some(show(value, ty)))
+    }
+
+    /// Bind the variable of the `let` starting at byte `start` of `line`,
+    /// returning its value.
+    fn bind(&mut self, line: &str, start: usize) -> Result<String, LineError> {
+        let name_start = skip_spaces(line, start + 3);
+        let name_end = line[name_start..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .map_or(line.len(), |end| name_start + end);
+        let name = &line[name_start..name_end];
+        if !is_identifier(name) {
+            return Err(syntax(ParseError::Unexpected {
+                offset: name_start,
+                expected: Expected::Name,
+            }));
+        }
+        let equals = skip_spaces(line, name_end);
+        if !line[equals..].starts_with('=') {
+            return Err(syntax(ParseError::Unexpected {
+                offset: equals,
+                expected: Expected::Assign,
+            }));
+        }
+        let (value, ty) = self.evaluate(parse_from(line, equals + 1)?)?;
+        self.env.insert(name.to_string(), value);
+        self.types.insert(name.to_string(), ty);
+        Ok(format!("{name} = {}", show(value, ty)))
+    }
+
+    /// Define the function starting at byte `start` of `line`, returning
+    /// its definition.
+    fn define(&mut self, line: &str, start: usize) -> Result<String, LineError> {
+        let input = format!("{:start$}{}", "", &line[start..]);
+        let function = parse_function(&input).map_err(syntax)?;
+        // The body is checked along with the functions it may call.
+        let unused = Expression::Define {
+            function: Box::new(function.clone()),
+            body: Box::new(Expression::Value(0)),
+        };
+        typecheck_with(&self.scoped(unused), &self.types)?;
+        let definition = function.to_string();
+        self.functions.push(function);
+        Ok(definition)
+    }
+
+    /// `e` in the scope of the defined functions.
+    fn scoped(&self, e: Expression) -> Expression {
+        self.functions.iter().rev().fold(e, |body, function| Expression::Define {
+            function: Box::new(function.clone()),
+            body: Box::new(body),
+        })
+    }
+
+    /// Type check and evaluate `e` against the bound variables and defined
+    /// functions.
+    fn evaluate(&self, e: Expression) -> Result<(i64, Type), LineError> {
+        let e = self.scoped(e);
+        let ty = typecheck_with(&e, &self.types)?;
+        Ok((eval_with(&e, &self.env)?, ty))
+    }
+}
+
+/// Booleans are evaluated to `1` and `0`, but shown as `true` and `false`.
+fn show(value: i64, ty: Type) -> String {
+    match ty {
+        Type::Int => value.to_string(),
+        Type::Bool => (value != 0).to_string(),
+    }
+}
+
+/// Parse the expression starting at byte `start` of `line`.
+///
+/// Everything before `start` is blanked out rather than sliced off, so the
+/// offsets in parse errors point into the whole line.
+fn parse_from(line: &str, start: usize) -> Result<Expression, LineError> {
+    let input = format!("{:start$}{}", "", &line[start..]);
+    parse(&input).map_err(syntax)
+}
+
+/// A parse error of the line, pointing at where it went wrong.
+fn syntax(err: ParseError) -> LineError {
+    LineError::Syntax { offset: err.offset(), message: err.to_string() }
+}
+
+/// The offset of the first character at or after byte `start` of `line`
+/// that isn't whitespace.
+fn skip_spaces(line: &str, start: usize) -> usize {
+    line.len() - line[start..].trim_start().len()
+}
+
+/// Whether `text` starts with the word `keyword`.
+fn keyword(text: &str, keyword: &str) -> bool {
+    text.strip_prefix(keyword).is_some_and(|rest| rest.starts_with(char::is_whitespace))
+}
+
+/// Whether `name` can name a variable, which keywords can't.
+fn is_identifier(name: &str) -> bool {
+    if KEYWORDS.contains(&name) {
+        return false;
+    }
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Read lines from stdin until it is exhausted, printing the result of each.
+///
+/// A prompt is only shown when stdin is a terminal, so the output of piped
+/// input contains nothing but results and errors. The exit status reports
+/// whether any line failed.
+fn main() -> ExitCode {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut session = Session::default();
+    let mut failed = false;
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("> ");
+            if let Err(err) = io::stdout().flush() {
+                eprintln!("error: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("error: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        match session.run(line) {
+            Ok(Some(output)) => println!("{output}"),
+            Ok(None) => {}
+            Err(err) => {
+                failed = true;
+                if let LineError::Syntax { offset, .. } = &err {
+                    let width = line[..*offset].chars().count();
+                    println!("{line}\n{:>width$}^ error: {err}", "");
+                } else {
+                    println!("error: {err}");
+                }
+            }
+        }
+    }
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,460 @@
+use std::ops::RangeInclusive;
+
+use super::{Env, Expression, Function, Operation, Type, UnaryOperation};
+
+/// A small xorshift generator, so the tests need no extra dependencies.
+pub struct Rng(u64);
+
+impl Rng {
+    /// A generator whose sequence is fixed by `seed`.
+    pub fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero.
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// True one time in `n`.
+    pub fn one_in(&mut self, n: u64) -> bool {
+        self.below(n) == 0
+    }
+
+    /// A uniform float in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn range(&mut self, range: &RangeInclusive<i64>) -> i64 {
+        let span = range.end().wrapping_sub(*range.start()) as u64;
+        let offset = if span == u64::MAX { self.next() } else { self.below(span + 1) };
+        range.start().wrapping_add(offset as i64)
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// What kind of expressions a `Generator` makes.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How deeply nodes may nest below the root.
+    pub max_depth: u32,
+    /// Where literals and the values of variables are drawn from.
+    pub values: RangeInclusive<i64>,
+    /// Whether to mix in `i64::MIN` and `i64::MAX` literals now and then.
+    pub extremes: bool,
+    /// The names of the variables; all of them are numbers.
+    pub vars: Vec<&'static str>,
+    /// Whether every operand has the type its operation expects. Otherwise
+    /// numbers and booleans are mixed freely.
+    pub well_typed: bool,
+    /// Whether to generate `let`, which binds one of `vars`.
+    pub bindings: bool,
+    /// Whether to generate `fn`, whose parameters are some of `vars`, and
+    /// calls of the functions in scope. Functions never call themselves.
+    pub functions: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_depth: 5,
+            values: -10..=10,
+            extremes: false,
+            vars: vec!["x", "y"],
+            well_typed: true,
+            bindings: true,
+            functions: false,
+        }
+    }
+}
+
+const ARITHMETIC: [Operation; 8] = [
+    Operation::Add,
+    Operation::Sub,
+    Operation::Mul,
+    Operation::Div,
+    Operation::Rem,
+    Operation::Pow,
+    Operation::Min,
+    Operation::Max,
+];
+
+const FUNCTIONS: [&str; 2] = ["f", "g"];
+
+const COMPARISONS: [Operation; 6] =
+    [Operation::Lt, Operation::Le, Operation::Eq, Operation::Ne, Operation::Gt, Operation::Ge];
+
+/// Makes random expressions as described by a `Config`.
+pub struct Generator {
+    rng: Rng,
+    config: Config,
+    /// The names and arities of the functions in scope.
+    functions: Vec<(&'static str, usize)>,
+}
+
+impl Generator {
+    pub fn new(seed: u64, config: Config) -> Self {
+        Generator { rng: Rng::new(seed), config, functions: Vec::new() }
+    }
+
+    /// An expression of a random type.
+    pub fn any(&mut self) -> Expression {
+        let ty = if self.rng.one_in(3) { Type::Bool } else { Type::Int };
+        self.expression(ty)
+    }
+
+    /// An expression of type `ty`, if the config asks for well typed ones.
+    pub fn expression(&mut self, ty: Type) -> Expression {
+        self.node(ty, self.config.max_depth)
+    }
+
+    /// Values for all of the variables.
+    pub fn env(&mut self) -> Env {
+        let vars = self.config.vars.clone();
+        vars.into_iter().map(|var| (var.to_string(), self.rng.range(&self.config.values))).collect()
+    }
+
+    /// The type an operand expecting `ty` gets.
+    fn operand_type(&mut self, ty: Type) -> Type {
+        match (self.config.well_typed, self.rng.below(2)) {
+            (true, _) => ty,
+            (false, 0) => Type::Int,
+            (false, _) => Type::Bool,
+        }
+    }
+
+    fn child(&mut self, ty: Type, depth: u32) -> Box<Expression> {
+        let ty = self.operand_type(ty);
+        Box::new(self.node(ty, depth - 1))
+    }
+
+    fn node(&mut self, ty: Type, depth: u32) -> Expression {
+        if depth == 0 || self.rng.one_in(4) {
+            return self.leaf(ty);
+        }
+        match self.rng.below(10) {
+            0 => Expression::If {
+                cond: self.child(Type::Bool, depth),
+                then: self.child(ty, depth),
+                otherwise: self.child(ty, depth),
+            },
+            1 if self.config.bindings && !self.config.vars.is_empty() => Expression::Let {
+                name: self.rng.pick(&self.config.vars).to_string(),
+                value: self.child(Type::Int, depth),
+                body: self.child(ty, depth),
+            },
+            2 => {
+                let (op, operand) = match ty {
+                    Type::Int => {
+                        let op = [UnaryOperation::Neg, UnaryOperation::Abs]
+                            [self.rng.below(2) as usize]
+                            .clone();
+                        (op, Type::Int)
+                    }
+                    Type::Bool => (UnaryOperation::Not, Type::Bool),
+                };
+                Expression::Unary { op, operand: self.child(operand, depth) }
+            }
+            3 if self.config.functions && !self.config.vars.is_empty() => {
+                let name = *self.rng.pick(&FUNCTIONS);
+                let mut params = vec![self.rng.pick(&self.config.vars).to_string()];
+                let other = self.rng.pick(&self.config.vars).to_string();
+                if self.rng.one_in(2) && !params.contains(&other) {
+                    params.push(other);
+                }
+                let result = self.operand_type(Type::Int);
+                let body = self.node(result, depth - 1);
+                // Calls only pick from the functions that are not shadowed.
+                let shadowed = self.functions.iter().position(|&(f, _)| f == name);
+                let shadowed = shadowed.map(|i| self.functions.remove(i));
+                self.functions.push((name, params.len()));
+                let e = Expression::Define {
+                    function: Box::new(Function { name: name.to_string(), params, body }),
+                    body: self.child(ty, depth),
+                };
+                self.functions.pop();
+                self.functions.extend(shadowed);
+                e
+            }
+            4 if !self.functions.is_empty() && (ty == Type::Int || !self.config.well_typed) => {
+                let &(name, arity) = self.rng.pick(&self.functions);
+                let args = (0..arity).map(|_| *self.child(Type::Int, depth)).collect();
+                Expression::Call { name: name.to_string(), args }
+            }
+            _ => {
+                let (op, operands) = match ty {
+                    Type::Int => (self.rng.pick(&ARITHMETIC).clone(), Type::Int),
+                    Type::Bool => match self.rng.below(4) {
+                        0 => (Operation::And, Type::Bool),
+                        1 => (Operation::Or, Type::Bool),
+                        2 => {
+                            let op =
+                                [Operation::Eq, Operation::Ne][self.rng.below(2) as usize].clone();
+                            (op, if self.rng.one_in(2) { Type::Bool } else { Type::Int })
+                        }
+                        _ => (self.rng.pick(&COMPARISONS).clone(), Type::Int),
+                    },
+                };
+                Expression::Op {
+                    op,
+                    left: self.child(operands, depth),
+                    right: self.child(operands, depth),
+                }
+            }
+        }
+    }
+
+    fn leaf(&mut self, ty: Type) -> Expression {
+        match ty {
+            Type::Bool => Expression::Bool(self.rng.one_in(2)),
+            Type::Int if self.config.extremes && self.rng.one_in(10) => {
+                Expression::Value(*self.rng.pick(&[i64::MIN, i64::MAX]))
+            }
+            Type::Int if !self.config.vars.is_empty() && self.rng.one_in(3) => {
+                Expression::Var(self.rng.pick(&self.config.vars).to_string())
+            }
+            Type::Int => Expression::Value(self.rng.range(&self.config.values)),
+        }
+    }
+}
+
+/// Simpler variations of `e`, simplest first: a literal, one of its
+/// operands, or `e` with one operand simplified. Every candidate is smaller
+/// than `e`, counting nodes, then variables, then the size of literals, so
+/// shrinking repeatedly ends.
+pub fn shrink(e: &Expression) -> Vec<Expression> {
+    let mut candidates = Vec::new();
+    let boolean = e.is_boolean();
+    let literal = if boolean { Expression::Bool(false) } else { Expression::Value(0) };
+    match e {
+        Expression::Value(0) | Expression::Bool(false) => {}
+        Expression::Value(v) => {
+            candidates.push(literal);
+            if v / 2 != 0 {
+                candidates.push(Expression::Value(v / 2));
+            }
+        }
+        _ => candidates.push(literal),
+    }
+    let children = children(e);
+    candidates.extend(children.iter().filter(|&&c| c.is_boolean() == boolean).map(|&c| c.clone()));
+    for (i, child) in children.iter().enumerate() {
+        for smaller in shrink(child) {
+            let mut e = e.clone();
+            *children_mut(&mut e).swap_remove(i) = smaller;
+            candidates.push(e);
+        }
+    }
+    candidates
+}
+
+/// The operands of `e`, in order.
+fn children(e: &Expression) -> Vec<&Expression> {
+    match e {
+        Expression::Op { left, right, .. } => vec![left, right],
+        Expression::Unary { operand, .. } => vec![operand],
+        Expression::If { cond, then, otherwise } => vec![cond, then, otherwise],
+        Expression::Let { value, body, .. } => vec![value, body],
+        Expression::Define { function, body } => vec![&function.body, body],
+        Expression::Call { args, .. } => args.iter().collect(),
+        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => vec![],
+    }
+}
+
+fn children_mut(e: &mut Expression) -> Vec<&mut Expression> {
+    match e {
+        Expression::Op { left, right, .. } => vec![left, right],
+        Expression::Unary { operand, .. } => vec![operand],
+        Expression::If { cond, then, otherwise } => vec![cond, then, otherwise],
+        Expression::Let { value, body, .. } => vec![value, body],
+        Expression::Define { function, body } => vec![&mut function.body, body],
+        Expression::Call { args, .. } => args.iter_mut().collect(),
+        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => vec![],
+    }
+}
+
+/// Shrink `e` for as long as some simpler variation still `fails`.
+pub fn minimize(mut e: Expression, fails: impl Fn(&Expression) -> bool) -> Expression {
+    while let Some(smaller) = shrink(&e).into_iter().find(&fails) {
+        e = smaller;
+    }
+    e
+}
+
+/// Check `property` for `cases` expressions made by a `Generator` with
+/// `config`, each with its own environment.
+///
+/// On the first failure, panics with the failing expression shrunk as far
+/// as it keeps failing in that environment, along with the seed and case
+/// that reproduce it.
+pub fn check(
+    seed: u64,
+    cases: usize,
+    config: Config,
+    property: impl Fn(&Expression, &Env) -> Result<(), String>,
+) {
+    let mut generator = Generator::new(seed, config);
+    for case in 0..cases {
+        let e = generator.any();
+        let env = generator.env();
+        if property(&e, &env).is_ok() {
+            continue;
+        }
+        let minimal = minimize(e.clone(), |e| property(e, &env).is_err());
+        let err = property(&minimal, &env).unwrap_err();
+        let mut vars: Vec<_> =
+            env.iter().map(|(name, value)| format!("{name} = {value}")).collect();
+        vars.sort();
+        let vars = vars.join(", ");
+        panic!(
+            "case {case} of seed {seed:#x} failed for `{minimal}` with {vars}: {err}\n\
+             (shrunk from `{e}`)"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{self, Discriminant};
+
+    use super::*;
+    use crate::expr::{eval_with, parse, simplify, typecheck_with, EvalError};
+
+    /// The value, or the kind of error without the sub-expression it
+    /// carries.
+    fn outcome(e: &Expression, env: &Env) -> Result<i64, Discriminant<EvalError>> {
+        eval_with(e, env).map_err(|err| mem::discriminant(&err))
+    }
+
+    /// `e` with the operands of every `+` and `*` swapped.
+    fn commuted(e: &Expression) -> Expression {
+        let mut e = e.clone();
+        let mut pending = vec![&mut e];
+        while let Some(e) = pending.pop() {
+            if let Expression::Op { op: Operation::Add | Operation::Mul, left, right } = e {
+                std::mem::swap(left, right);
+            }
+            pending.extend(children_mut(e));
+        }
+        e
+    }
+
+    #[test]
+    fn generates_what_is_asked_for() {
+        let config = Config { max_depth: 3, values: 100..=105, ..Config::default() };
+        let mut generator = Generator::new(7, config);
+        let types = ["x", "y"].map(|var| (var.to_string(), Type::Int)).into();
+        for _ in 0..500 {
+            let ty = if generator.rng.one_in(2) { Type::Bool } else { Type::Int };
+            let e = generator.expression(ty);
+            assert_eq!(typecheck_with(&e, &types), Ok(ty), "{e}");
+            assert!(depth(&e) <= 3, "{e}");
+            let mut pending = vec![&e];
+            while let Some(e) = pending.pop() {
+                if let Expression::Value(v) = e {
+                    assert!((100..=105).contains(v), "{e}");
+                }
+                pending.extend(children(e));
+            }
+        }
+        assert!(generator.env().values().all(|v| (100..=105).contains(v)));
+        // Calls are only made to functions in scope, with their arity.
+        let mut generator = Generator::new(7, Config { functions: true, ..Config::default() });
+        let mut calls = 0;
+        for _ in 0..500 {
+            let e = generator.any();
+            assert_eq!(typecheck_with(&e, &types).err(), None, "{e}");
+            let mut pending = vec![&e];
+            while let Some(e) = pending.pop() {
+                calls += usize::from(matches!(e, Expression::Call { .. }));
+                pending.extend(children(e));
+            }
+        }
+        assert!(calls > 0);
+        // The same seed makes the same expressions.
+        let mut a = Generator::new(42, Config::default());
+        let mut b = Generator::new(42, Config::default());
+        for _ in 0..20 {
+            assert_eq!(a.any(), b.any());
+        }
+    }
+
+    fn depth(e: &Expression) -> u32 {
+        children(e).into_iter().map(|c| depth(c) + 1).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn shrinks_to_minimal_trees() {
+        let e = parse("(x + 7) * (if y > 3 then 10 / (y - y) else 2)").unwrap();
+        let env = Env::from([(String::from("x"), 1), (String::from("y"), 5)]);
+        let fails = |e: &Expression| eval_with(e, &env).is_err();
+        assert_eq!(minimize(e, fails), parse("0 / 0").unwrap());
+        let e = parse("a + (b - 40) * 3 > 9 && !(c == 4)").unwrap();
+        let mentions_b = |e: &Expression| e.to_string().contains('b');
+        // Shrinking keeps the type, so the comparison stays.
+        assert_eq!(minimize(e, mentions_b), parse("b > 0").unwrap());
+        let e = parse("1000 * (3 - 1)").unwrap();
+        let large = |e: &Expression| eval_with(e, &Env::new()).is_ok_and(|v| v >= 100);
+        assert_eq!(minimize(e, large), Expression::Value(125));
+    }
+
+    #[test]
+    #[should_panic(expected = "failed for `0 ^ y` with x = 9, y = -2: negative exponent")]
+    fn reports_the_shrunk_case() {
+        let config = Config { extremes: false, ..Config::default() };
+        check(1, 1000, config, |e, env| eval_with(e, env).map(|_| ()).map_err(|e| e.to_string()));
+    }
+
+    #[test]
+    fn addition_and_multiplication_commute() {
+        let config = Config { extremes: true, ..Config::default() };
+        check(0x5eed, 2000, config, |e, env| {
+            let swapped = commuted(e);
+            // Swapping two failing operands changes which error is reported
+            // first, so only whether it fails is compared.
+            match (outcome(e, env).ok(), outcome(&swapped, env).ok()) {
+                (a, b) if a == b => Ok(()),
+                (a, b) => Err(format!("{a:?} but {b:?} for `{swapped}`")),
+            }
+        });
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let config = Config {
+            extremes: true,
+            values: i64::MIN..=i64::MAX,
+            well_typed: false,
+            ..Config::default()
+        };
+        check(0xd15b1a7, 2000, config, |e, _| match parse(&e.to_string()) {
+            Ok(parsed) if parsed == *e => Ok(()),
+            Ok(parsed) => Err(format!("parsed back as {parsed:?}")),
+            Err(err) => Err(err.to_string()),
+        });
+    }
+
+    #[test]
+    fn simplify_preserves_results() {
+        // `simplify` relies on booleans being 0 or 1, so mixing isn't allowed.
+        let config = Config { extremes: true, ..Config::default() };
+        check(0x51e9, 3000, config, |e, env| {
+            let simplified = simplify(e.clone());
+            match (outcome(e, env), outcome(&simplified, env)) {
+                (a, b) if a == b => Ok(()),
+                (a, b) => Err(format!("{a:?} but {b:?} after simplifying to `{simplified}`")),
+            }
+        });
+    }
+}
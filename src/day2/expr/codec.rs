@@ -0,0 +1,981 @@
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use super::{Expression, Function, Operation, UnaryOperation};
+
+/// How deeply expressions may nest before decoding gives up. The decoders
+/// recurse, so this keeps hostile input from exhausting the stack.
+pub const MAX_DEPTH: usize = 256;
+
+/// Names of the operations in JSON; their positions are the binary tags.
+const OPERATIONS: [(Operation, &str); 16] = [
+    (Operation::Add, "add"),
+    (Operation::Sub, "sub"),
+    (Operation::Mul, "mul"),
+    (Operation::Div, "div"),
+    (Operation::Rem, "rem"),
+    (Operation::Pow, "pow"),
+    (Operation::Min, "min"),
+    (Operation::Max, "max"),
+    (Operation::Lt, "lt"),
+    (Operation::Le, "le"),
+    (Operation::Eq, "eq"),
+    (Operation::Ne, "ne"),
+    (Operation::Gt, "gt"),
+    (Operation::Ge, "ge"),
+    (Operation::And, "and"),
+    (Operation::Or, "or"),
+];
+
+const UNARY_OPERATIONS: [UnaryOperation; 3] =
+    [UnaryOperation::Neg, UnaryOperation::Abs, UnaryOperation::Not];
+
+// Binary tags other than the operations.
+const UNARY: u8 = 16;
+const VALUE: u8 = 19;
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+const IF: u8 = 22;
+const VAR: u8 = 23;
+const LET: u8 = 24;
+const FN: u8 = 25;
+const CALL: u8 = 26;
+
+/// Why an encoded expression could not be decoded. Offsets are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    #[error("expected {expected} at offset {offset}")]
+    Expected { offset: usize, expected: &'static str },
+    #[error("unknown tag {tag} at offset {offset}")]
+    UnknownTag { offset: usize, tag: u8 },
+    #[error("unknown operation `{name}` at offset {offset}")]
+    UnknownOperation { offset: usize, name: String },
+    #[error("unknown field `{name}` at offset {offset}")]
+    UnknownField { offset: usize, name: String },
+    #[error("duplicate field `{name}` at offset {offset}")]
+    DuplicateField { offset: usize, name: String },
+    #[error("the fields of the object at offset {offset} don't make an expression")]
+    NotAnExpression { offset: usize },
+    #[error("integer out of range at offset {offset}")]
+    IntegerOutOfRange { offset: usize },
+    #[error("invalid UTF-8 at offset {offset}")]
+    InvalidUtf8 { offset: usize },
+    #[error("expression nested more than {MAX_DEPTH} deep at offset {offset}")]
+    TooDeep { offset: usize },
+    #[error("empty function or parameter name at offset {offset}")]
+    EmptyName { offset: usize },
+    #[error("duplicate parameter `{name}` at offset {offset}")]
+    DuplicateParameter { offset: usize, name: String },
+    #[error("trailing input at offset {offset}")]
+    TrailingInput { offset: usize },
+}
+
+fn operation_name(op: &Operation) -> &'static str {
+    OPERATIONS.iter().find(|(o, _)| o == op).map(|(_, name)| *name).expect("unnamed operation")
+}
+
+fn operation_tag(op: &Operation) -> u8 {
+    OPERATIONS.iter().position(|(o, _)| o == op).expect("untagged operation") as u8
+}
+
+fn unary_tag(op: &UnaryOperation) -> u8 {
+    UNARY + UNARY_OPERATIONS.iter().position(|o| o == op).expect("untagged operation") as u8
+}
+
+/// Encode an expression as JSON, one object per node:
+///
+/// ```text
+/// {"op": "add", "left": {"var": "x"}, "right": {"value": 1}}
+/// {"unary": "neg", "operand": {"bool": true}}
+/// {"if": {"var": "c"}, "then": {"value": 1}, "else": {"value": 2}}
+/// {"let": "x", "equals": {"value": 1}, "in": {"var": "x"}}
+/// {"fn": "f", "params": ["x"], "body": {"var": "x"}, "in": {"call": "f", "args": []}}
+/// ```
+///
+/// Operations are named `add`, `sub`, `mul`, `div`, `rem`, `pow`, `min`,
+/// `max`, `lt`, `le`, `eq`, `ne`, `gt`, `ge`, `and` and `or`; unary ones
+/// `neg`, `abs` and `not`.
+pub fn to_json(e: &Expression) -> String {
+    enum Piece<'a> {
+        Expr(&'a Expression),
+        Text(&'static str),
+    }
+    let mut out = String::new();
+    let mut pieces = vec![Piece::Expr(e)];
+    while let Some(piece) = pieces.pop() {
+        let e = match piece {
+            Piece::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Piece::Expr(e) => e,
+        };
+        match e {
+            Expression::Op { op, left, right } => {
+                write!(out, "{{\"op\": \"{}\", \"left\": ", operation_name(op)).unwrap();
+                pieces.extend([
+                    Piece::Text("}"),
+                    Piece::Expr(right),
+                    Piece::Text(", \"right\": "),
+                    Piece::Expr(left),
+                ]);
+            }
+            Expression::Unary { op, operand } => {
+                write!(out, "{{\"unary\": \"{}\", \"operand\": ", op.name()).unwrap();
+                pieces.extend([Piece::Text("}"), Piece::Expr(operand)]);
+            }
+            Expression::Value(v) => write!(out, "{{\"value\": {v}}}").unwrap(),
+            Expression::Bool(b) => write!(out, "{{\"bool\": {b}}}").unwrap(),
+            Expression::If { cond, then, otherwise } => {
+                out.push_str("{\"if\": ");
+                pieces.extend([
+                    Piece::Text("}"),
+                    Piece::Expr(otherwise),
+                    Piece::Text(", \"else\": "),
+                    Piece::Expr(then),
+                    Piece::Text(", \"then\": "),
+                    Piece::Expr(cond),
+                ]);
+            }
+            Expression::Var(name) => {
+                out.push_str("{\"var\": ");
+                write_string(&mut out, name);
+                out.push('}');
+            }
+            Expression::Let { name, value, body } => {
+                out.push_str("{\"let\": ");
+                write_string(&mut out, name);
+                out.push_str(", \"equals\": ");
+                pieces.extend([
+                    Piece::Text("}"),
+                    Piece::Expr(body),
+                    Piece::Text(", \"in\": "),
+                    Piece::Expr(value),
+                ]);
+            }
+            Expression::Define { function, body } => {
+                out.push_str("{\"fn\": ");
+                write_string(&mut out, &function.name);
+                out.push_str(", \"params\": [");
+                for (i, param) in function.params.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    write_string(&mut out, param);
+                }
+                out.push_str("], \"body\": ");
+                pieces.extend([
+                    Piece::Text("}"),
+                    Piece::Expr(body),
+                    Piece::Text(", \"in\": "),
+                    Piece::Expr(&function.body),
+                ]);
+            }
+            Expression::Call { name, args } => {
+                out.push_str("{\"call\": ");
+                write_string(&mut out, name);
+                out.push_str(", \"args\": [");
+                pieces.push(Piece::Text("]}"));
+                for (i, arg) in args.iter().enumerate().rev() {
+                    pieces.push(Piece::Expr(arg));
+                    if i > 0 {
+                        pieces.push(Piece::Text(", "));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Decode an expression from the JSON produced by `to_json`. Fields may come
+/// in any order and whitespace is free, but every field must be known. Like
+/// the parser, this rejects functions with empty names or with two parameters
+/// of the same name.
+pub fn from_json(input: &str) -> Result<Expression, DecodeError> {
+    let mut decoder = JsonDecoder { input: input.as_bytes(), pos: 0 };
+    let e = decoder.expression(1)?;
+    decoder.skip_whitespace();
+    if decoder.pos < input.len() {
+        return Err(DecodeError::TrailingInput { offset: decoder.pos });
+    }
+    Ok(e)
+}
+
+/// The fields of a JSON object seen so far.
+#[derive(Default)]
+struct Fields {
+    count: usize,
+    op: Option<Operation>,
+    unary: Option<UnaryOperation>,
+    value: Option<i64>,
+    boolean: Option<bool>,
+    var: Option<String>,
+    left: Option<Expression>,
+    right: Option<Expression>,
+    operand: Option<Expression>,
+    cond: Option<Expression>,
+    then: Option<Expression>,
+    otherwise: Option<Expression>,
+    bound: Option<String>,
+    equals: Option<Expression>,
+    function: Option<String>,
+    params: Option<Vec<String>>,
+    body: Option<Expression>,
+    rest: Option<Expression>,
+    call: Option<String>,
+    args: Option<Vec<Expression>>,
+}
+
+struct JsonDecoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl JsonDecoder<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    /// The next byte after any whitespace, without consuming it.
+    fn peek(&mut self) -> Result<u8, DecodeError> {
+        self.skip_whitespace();
+        self.input.get(self.pos).copied().ok_or(DecodeError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), DecodeError> {
+        if self.peek()? != byte {
+            return Err(DecodeError::Expected { offset: self.pos, expected });
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expression(&mut self, depth: usize) -> Result<Expression, DecodeError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.expect(b'{', "an object")?;
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::TooDeep { offset: start });
+        }
+        let mut fields = Fields::default();
+        if self.peek()? == b'}' {
+            self.pos += 1;
+            return Err(DecodeError::NotAnExpression { offset: start });
+        }
+        loop {
+            self.field(&mut fields, depth)?;
+            match self.peek()? {
+                b',' => self.pos += 1,
+                b'}' => break,
+                _ => {
+                    return Err(DecodeError::Expected { offset: self.pos, expected: "`,` or `}`" })
+                }
+            }
+        }
+        self.pos += 1;
+        let boxed = Box::new;
+        Ok(match fields {
+            Fields { count: 3, op: Some(op), left: Some(left), right: Some(right), .. } => {
+                Expression::Op { op, left: boxed(left), right: boxed(right) }
+            }
+            Fields { count: 2, unary: Some(op), operand: Some(operand), .. } => {
+                Expression::Unary { op, operand: boxed(operand) }
+            }
+            Fields { count: 1, value: Some(v), .. } => Expression::Value(v),
+            Fields { count: 1, boolean: Some(b), .. } => Expression::Bool(b),
+            Fields { count: 3, cond: Some(cond), then: Some(then), otherwise: Some(o), .. } => {
+                Expression::If { cond: boxed(cond), then: boxed(then), otherwise: boxed(o) }
+            }
+            Fields { count: 1, var: Some(name), .. } => Expression::Var(name),
+            Fields {
+                count: 3, bound: Some(name), equals: Some(value), rest: Some(body), ..
+            } => Expression::Let { name, value: boxed(value), body: boxed(body) },
+            Fields {
+                count: 4,
+                function: Some(name),
+                params: Some(params),
+                body: Some(definition),
+                rest: Some(body),
+                ..
+            } => Expression::Define {
+                function: Box::new(Function { name, params, body: definition }),
+                body: boxed(body),
+            },
+            Fields { count: 2, call: Some(name), args: Some(args), .. } => {
+                Expression::Call { name, args }
+            }
+            _ => return Err(DecodeError::NotAnExpression { offset: start }),
+        })
+    }
+
+    /// Decode one `"key": value` pair into `fields`.
+    fn field(&mut self, fields: &mut Fields, depth: usize) -> Result<(), DecodeError> {
+        self.skip_whitespace();
+        let offset = self.pos;
+        let name = self.string()?;
+        self.expect(b':', "`:`")?;
+        let duplicate = match name.as_str() {
+            "op" => {
+                let op = self.name(&OPERATIONS.map(|(_, name)| name))?;
+                fields.op.replace(OPERATIONS[op].0.clone()).is_some()
+            }
+            "unary" => {
+                let op = self.name(&UNARY_OPERATIONS.map(|op| op.name()))?;
+                fields.unary.replace(UNARY_OPERATIONS[op].clone()).is_some()
+            }
+            "value" => {
+                let value = self.integer()?;
+                fields.value.replace(value).is_some()
+            }
+            "bool" => {
+                let b = self.boolean()?;
+                fields.boolean.replace(b).is_some()
+            }
+            key @ ("var" | "let" | "fn" | "call") => {
+                let slot = match key {
+                    "var" => &mut fields.var,
+                    "let" => &mut fields.bound,
+                    "fn" => &mut fields.function,
+                    _ => &mut fields.call,
+                };
+                self.skip_whitespace();
+                let offset = self.pos;
+                let name = self.string()?;
+                if key == "fn" && name.is_empty() {
+                    return Err(DecodeError::EmptyName { offset });
+                }
+                slot.replace(name).is_some()
+            }
+            "params" => {
+                let params = self.list(|decoder| {
+                    decoder.skip_whitespace();
+                    Ok((decoder.pos, decoder.string()?))
+                })?;
+                fields.params.replace(parameters(params)?).is_some()
+            }
+            "args" => {
+                let args = self.list(|decoder| decoder.expression(depth + 1))?;
+                fields.args.replace(args).is_some()
+            }
+            key => {
+                let slot = match key {
+                    "left" => &mut fields.left,
+                    "right" => &mut fields.right,
+                    "operand" => &mut fields.operand,
+                    "if" => &mut fields.cond,
+                    "then" => &mut fields.then,
+                    "else" => &mut fields.otherwise,
+                    "equals" => &mut fields.equals,
+                    "body" => &mut fields.body,
+                    "in" => &mut fields.rest,
+                    _ => return Err(DecodeError::UnknownField { offset, name }),
+                };
+                slot.replace(self.expression(depth + 1)?).is_some()
+            }
+        };
+        if duplicate {
+            return Err(DecodeError::DuplicateField { offset, name });
+        }
+        fields.count += 1;
+        Ok(())
+    }
+
+    /// A JSON array, decoding each element with `element`.
+    fn list<T>(
+        &mut self,
+        mut element: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        self.expect(b'[', "an array")?;
+        let mut list = Vec::new();
+        if self.peek()? == b']' {
+            self.pos += 1;
+            return Ok(list);
+        }
+        loop {
+            list.push(element(self)?);
+            match self.peek()? {
+                b',' => self.pos += 1,
+                b']' => break,
+                _ => {
+                    return Err(DecodeError::Expected { offset: self.pos, expected: "`,` or `]`" })
+                }
+            }
+        }
+        self.pos += 1;
+        Ok(list)
+    }
+
+    /// A string that must be one of `names`, returned as its index.
+    fn name(&mut self, names: &[&str]) -> Result<usize, DecodeError> {
+        self.skip_whitespace();
+        let offset = self.pos;
+        let name = self.string()?;
+        names.iter().position(|n| *n == name).ok_or(DecodeError::UnknownOperation { offset, name })
+    }
+
+    fn integer(&mut self) -> Result<i64, DecodeError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        if self.input.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        let digits = self.pos;
+        while self.input.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        if self.pos == digits {
+            return match self.input.get(self.pos) {
+                Some(_) => Err(DecodeError::Expected { offset: start, expected: "an integer" }),
+                None => Err(DecodeError::UnexpectedEnd),
+            };
+        }
+        // The bytes are ASCII, so this can't fail.
+        let text = std::str::from_utf8(&self.input[start..self.pos]).unwrap();
+        text.parse().map_err(|_| DecodeError::IntegerOutOfRange { offset: start })
+    }
+
+    fn boolean(&mut self) -> Result<bool, DecodeError> {
+        self.skip_whitespace();
+        for (word, b) in [("true", true), ("false", false)] {
+            if self.input[self.pos..].starts_with(word.as_bytes()) {
+                self.pos += word.len();
+                return Ok(b);
+            }
+        }
+        if word_prefix(&self.input[self.pos..]) {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        Err(DecodeError::Expected { offset: self.pos, expected: "`true` or `false`" })
+    }
+
+    /// A string literal, starting at the current position.
+    fn string(&mut self) -> Result<String, DecodeError> {
+        self.expect(b'"', "a string")?;
+        let mut s = String::new();
+        loop {
+            let start = self.pos;
+            // Copy everything up to the next quote or escape in one go.
+            while self.input.get(self.pos).is_some_and(|&b| b != b'"' && b != b'\\' && b >= 0x20) {
+                self.pos += 1;
+            }
+            let run = std::str::from_utf8(&self.input[start..self.pos]);
+            s.push_str(
+                run.map_err(|err| DecodeError::InvalidUtf8 { offset: start + err.valid_up_to() })?,
+            );
+            let offset = self.pos;
+            match self.input.get(self.pos) {
+                None => return Err(DecodeError::UnexpectedEnd),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    s.push(self.escape(offset)?);
+                }
+                Some(_) => {
+                    return Err(DecodeError::Expected { offset, expected: "an escaped character" })
+                }
+            }
+        }
+    }
+
+    /// The character of the escape sequence after the backslash at `offset`.
+    fn escape(&mut self, offset: usize) -> Result<char, DecodeError> {
+        let byte = *self.input.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(match byte {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let high = self.hex4()?;
+                let code = if (0xd800..0xdc00).contains(&high) {
+                    // A surrogate pair: the low half must follow.
+                    if !self.input[self.pos..].starts_with(b"\\u") {
+                        return Err(self.input.get(self.pos + 1).map_or(
+                            DecodeError::UnexpectedEnd,
+                            |_| DecodeError::Expected { offset, expected: "a surrogate pair" },
+                        ));
+                    }
+                    self.pos += 2;
+                    let low = self.hex4()?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(DecodeError::Expected { offset, expected: "a surrogate pair" });
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code)
+                    .ok_or(DecodeError::Expected { offset, expected: "a surrogate pair" })?
+            }
+            _ => return Err(DecodeError::Expected { offset, expected: "an escape sequence" }),
+        })
+    }
+
+    fn hex4(&mut self) -> Result<u32, DecodeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let offset = self.pos;
+            let byte = *self.input.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+            let digit = (byte as char)
+                .to_digit(16)
+                .ok_or(DecodeError::Expected { offset, expected: "a hex digit" })?;
+            code = code * 16 + digit;
+            self.pos += 1;
+        }
+        Ok(code)
+    }
+}
+
+/// The names of the parameters of a function, each decoded at its offset,
+/// which must be distinct and not empty.
+fn parameters(params: Vec<(usize, String)>) -> Result<Vec<String>, DecodeError> {
+    let mut names = Vec::with_capacity(params.len());
+    for (offset, name) in params {
+        if name.is_empty() {
+            return Err(DecodeError::EmptyName { offset });
+        }
+        if names.contains(&name) {
+            return Err(DecodeError::DuplicateParameter { offset, name });
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Whether `rest` is a prefix of `true` or `false`, i.e. the input was cut
+/// off before or in the middle of one.
+fn word_prefix(rest: &[u8]) -> bool {
+    [&b"true"[..], b"false"].iter().any(|w| w.starts_with(rest))
+}
+
+/// Encode an expression compactly, in prefix order: each node is a tag byte
+/// followed by its children. Operations are tagged 0 to 15 in the order of
+/// `Operation`, `neg`, `abs` and `not` 16 to 18; values are 19 followed by a
+/// zigzag LEB128 integer, `false` and `true` 20 and 21, `if` 22, variables
+/// 23 followed by their name, `let` 24 followed by its name, function
+/// definitions 25 followed by the function's name, the LEB128 number of
+/// parameters and their names, and calls 26 followed by the function's name
+/// and the number of arguments. Names are written as the LEB128 length of
+/// their UTF-8 encoding followed by the bytes.
+pub fn to_bytes(e: &Expression) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pending = vec![e];
+    while let Some(e) = pending.pop() {
+        match e {
+            Expression::Op { op, left, right } => {
+                out.push(operation_tag(op));
+                pending.extend([&**right, left]);
+            }
+            Expression::Unary { op, operand } => {
+                out.push(unary_tag(op));
+                pending.push(operand);
+            }
+            Expression::Value(v) => {
+                out.push(VALUE);
+                write_varint(&mut out, ((v << 1) ^ (v >> 63)) as u64);
+            }
+            Expression::Bool(b) => out.push(if *b { TRUE } else { FALSE }),
+            Expression::If { cond, then, otherwise } => {
+                out.push(IF);
+                pending.extend([&**otherwise, then, cond]);
+            }
+            Expression::Var(name) => {
+                out.push(VAR);
+                write_name(&mut out, name);
+            }
+            Expression::Let { name, value, body } => {
+                out.push(LET);
+                write_name(&mut out, name);
+                pending.extend([&**body, value]);
+            }
+            Expression::Define { function, body } => {
+                out.push(FN);
+                write_name(&mut out, &function.name);
+                write_varint(&mut out, function.params.len() as u64);
+                for param in &function.params {
+                    write_name(&mut out, param);
+                }
+                pending.extend([&**body, &function.body]);
+            }
+            Expression::Call { name, args } => {
+                out.push(CALL);
+                write_name(&mut out, name);
+                write_varint(&mut out, args.len() as u64);
+                pending.extend(args.iter().rev());
+            }
+        }
+    }
+    out
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_varint(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Decode an expression from the bytes produced by `to_bytes`, with the same
+/// checks on function names as `from_json`.
+pub fn from_bytes(bytes: &[u8]) -> Result<Expression, DecodeError> {
+    let mut decoder = BinaryDecoder { input: bytes, pos: 0 };
+    let e = decoder.expression(1)?;
+    if decoder.pos < bytes.len() {
+        return Err(DecodeError::TrailingInput { offset: decoder.pos });
+    }
+    Ok(e)
+}
+
+struct BinaryDecoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl BinaryDecoder<'_> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.input.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn expression(&mut self, depth: usize) -> Result<Expression, DecodeError> {
+        let offset = self.pos;
+        let tag = self.byte()?;
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::TooDeep { offset });
+        }
+        let child = |decoder: &mut Self| decoder.expression(depth + 1).map(Box::new);
+        Ok(match tag {
+            _ if usize::from(tag) < OPERATIONS.len() => {
+                let op = OPERATIONS[usize::from(tag)].0.clone();
+                Expression::Op { op, left: child(self)?, right: child(self)? }
+            }
+            _ if (UNARY..VALUE).contains(&tag) => {
+                let op = UNARY_OPERATIONS[usize::from(tag - UNARY)].clone();
+                Expression::Unary { op, operand: child(self)? }
+            }
+            VALUE => {
+                let n = self.varint()?;
+                Expression::Value((n >> 1) as i64 ^ -((n & 1) as i64))
+            }
+            FALSE => Expression::Bool(false),
+            TRUE => Expression::Bool(true),
+            IF => {
+                Expression::If { cond: child(self)?, then: child(self)?, otherwise: child(self)? }
+            }
+            VAR => Expression::Var(self.name()?),
+            LET => {
+                let name = self.name()?;
+                Expression::Let { name, value: child(self)?, body: child(self)? }
+            }
+            FN => {
+                let name_offset = self.pos;
+                let name = self.name()?;
+                if name.is_empty() {
+                    return Err(DecodeError::EmptyName { offset: name_offset });
+                }
+                // Counts are not trusted with an allocation either; every
+                // parameter takes at least a byte, so running out of input
+                // stops the loop.
+                let mut params = Vec::new();
+                for _ in 0..self.varint()? {
+                    params.push((self.pos, self.name()?));
+                }
+                let params = parameters(params)?;
+                let body = self.expression(depth + 1)?;
+                let function = Box::new(Function { name, params, body });
+                Expression::Define { function, body: child(self)? }
+            }
+            CALL => {
+                let name = self.name()?;
+                let mut args = Vec::new();
+                for _ in 0..self.varint()? {
+                    args.push(self.expression(depth + 1)?);
+                }
+                Expression::Call { name, args }
+            }
+            _ => return Err(DecodeError::UnknownTag { offset, tag }),
+        })
+    }
+
+    fn name(&mut self) -> Result<String, DecodeError> {
+        let len = self.varint()?;
+        let start = self.pos;
+        let rest = &self.input[start..];
+        // Check the length before trusting it with an allocation.
+        let bytes = rest
+            .get(..usize::try_from(len).unwrap_or(usize::MAX))
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let name = std::str::from_utf8(bytes)
+            .map_err(|err| DecodeError::InvalidUtf8 { offset: start + err.valid_up_to() })?;
+        self.pos += bytes.len();
+        Ok(name.to_string())
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let offset = self.pos;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            // The tenth byte may only carry the top bit.
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::IntegerOutOfRange { offset });
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::IntegerOutOfRange { offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::parse;
+
+    const FORMULAS: [&str; 7] = [
+        "(3 - 4) * 5 + 10 * 9",
+        "-9223372036854775808 % max(x, -abs(y)) ^ 2",
+        "if price > 100 && !member then price * 9 / 10 else min(price, 50)",
+        "a <= b || a >= c && (a != 0) == false",
+        "x < 1 == (y > -1)",
+        "-(x - 9223372036854775807)",
+        "fn f(a, b) = a * g() - b in let x = f(1, 2) in f(x, -x) + x",
+    ];
+
+    /// An expression nested `depth` deep.
+    fn nested(depth: usize) -> Expression {
+        let mut e = Expression::Var(String::from("x"));
+        for _ in 1..depth {
+            e = Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(e) };
+        }
+        e
+    }
+
+    #[test]
+    fn json_round_trip() {
+        for input in FORMULAS {
+            let e = parse(input).unwrap();
+            assert_eq!(from_json(&to_json(&e)), Ok(e), "{input}");
+        }
+        let e = parse("if c then -x else y % 2").unwrap();
+        assert_eq!(
+            to_json(&e),
+            r#"{"if": {"var": "c"}, "then": {"unary": "neg", "operand": {"var": "x"}}, "#
+                .to_owned()
+                + r#""else": {"op": "rem", "left": {"var": "y"}, "right": {"value": 2}}}"#
+        );
+        let spaced =
+            " {\n \"right\" :{\"bool\":false},\"op\":\"or\",\t\"left\": {\"bool\": true}} ";
+        assert_eq!(from_json(spaced), Ok(parse("true || false").unwrap()));
+        let name = "quote\" back\\ tab\t nul\0 é 🦀";
+        let e = Expression::Var(name.to_string());
+        assert_eq!(from_json(&to_json(&e)), Ok(e.clone()));
+        let escaped = r#"{"var": "quote\" back\\ tab\t nul\u0000 \u00e9 \ud83e\udd80"}"#;
+        assert_eq!(from_json(escaped), Ok(e));
+        let e = parse("fn f(a, b) = a in let x = 1 in f(x, 2) + g()").unwrap();
+        assert_eq!(
+            to_json(&e),
+            r#"{"fn": "f", "params": ["a", "b"], "body": {"var": "a"}, "in": "#.to_owned()
+                + r#"{"let": "x", "equals": {"value": 1}, "in": {"op": "add", "left": "#
+                + r#"{"call": "f", "args": [{"var": "x"}, {"value": 2}]}, "#
+                + r#""right": {"call": "g", "args": []}}}}"#
+        );
+        let spaced = r#"{"args": [ ], "call": "g"}"#;
+        assert_eq!(from_json(spaced), Ok(parse("g()").unwrap()));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        for input in FORMULAS {
+            let e = parse(input).unwrap();
+            assert_eq!(from_bytes(&to_bytes(&e)), Ok(e), "{input}");
+        }
+        let e = parse("if c then -x else y % 2").unwrap();
+        assert_eq!(to_bytes(&e), [IF, VAR, 1, b'c', 16, VAR, 1, b'x', 4, VAR, 1, b'y', VALUE, 4]);
+        for v in [0, 1, -1, 63, -64, 64, i64::MAX, i64::MIN] {
+            let e = Expression::Value(v);
+            assert_eq!(from_bytes(&to_bytes(&e)), Ok(e));
+        }
+        assert_eq!(to_bytes(&Expression::Value(-64)), [VALUE, 127]);
+        assert_eq!(to_bytes(&Expression::Value(64)), [VALUE, 128, 1]);
+        let e = parse("fn f(a) = a in let x = 1 in f(x)").unwrap();
+        assert_eq!(
+            to_bytes(&e),
+            [FN, 1, b'f', 1, 1, b'a', VAR, 1, b'a', LET, 1, b'x', VALUE, 2, CALL, 1, b'f', 1, VAR]
+                .into_iter()
+                .chain([1, b'x'])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn malformed_json() {
+        let expected = |offset, expected| Err(DecodeError::Expected { offset, expected });
+        assert_eq!(from_json(""), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(from_json("[1]"), expected(0, "an object"));
+        assert_eq!(from_json(r#"{"value" 1}"#), expected(9, "`:`"));
+        assert_eq!(from_json(r#"{"value": 1.5}"#), expected(11, "`,` or `}`"));
+        assert_eq!(from_json(r#"{"value": x}"#), expected(10, "an integer"));
+        assert_eq!(from_json(r#"{"bool": 1}"#), expected(9, "`true` or `false`"));
+        assert_eq!(from_json(r#"{"var": "\q"}"#), expected(9, "an escape sequence"));
+        assert_eq!(from_json(r#"{"var": "\ud83e"}"#), expected(9, "a surrogate pair"));
+        assert_eq!(from_json("{\"var\": \"a\nb\"}"), expected(10, "an escaped character"));
+        assert_eq!(
+            from_json(r#"{"value": 9223372036854775808}"#),
+            Err(DecodeError::IntegerOutOfRange { offset: 10 })
+        );
+        assert_eq!(
+            from_json(r#"{"op": "xor", "left": {"value": 1}, "right": {"value": 2}}"#),
+            Err(DecodeError::UnknownOperation { offset: 7, name: String::from("xor") })
+        );
+        assert_eq!(
+            from_json(r#"{"value": 1, "value": 2}"#),
+            Err(DecodeError::DuplicateField { offset: 13, name: String::from("value") })
+        );
+        assert_eq!(
+            from_json(r#"{"value": 1, "units": 2}"#),
+            Err(DecodeError::UnknownField { offset: 13, name: String::from("units") })
+        );
+        let not_an_expression = |offset| Err(DecodeError::NotAnExpression { offset });
+        assert_eq!(from_json("{}"), not_an_expression(0));
+        assert_eq!(from_json(r#"{"value": 1, "bool": true}"#), not_an_expression(0));
+        assert_eq!(from_json(r#"{"op": "add", "left": {"value": 1}}"#), not_an_expression(0));
+        assert_eq!(from_json(r#"{"value": 1} {}"#), Err(DecodeError::TrailingInput { offset: 13 }));
+        assert_eq!(from_json(r#"{"call": "f", "args": {}}"#), expected(22, "an array"));
+        assert_eq!(
+            from_json(r#"{"call": "f", "args": [{"value": 1} {"value": 2}]}"#),
+            expected(36, "`,` or `]`")
+        );
+        assert_eq!(from_json(r#"{"let": "x", "in": {"var": "x"}}"#), not_an_expression(0));
+        let function = |name, params| {
+            format!(
+                r#"{{"fn": "{name}", "params": {params}, "body": {{"value": 1}}, "in": {{"value": 2}}}}"#
+            )
+        };
+        assert_eq!(
+            from_json(&function("f", r#"["x", "y", "x"]"#)),
+            Err(DecodeError::DuplicateParameter { offset: 33, name: String::from("x") })
+        );
+        assert_eq!(
+            from_json(&function("f", r#"["x", ""]"#)),
+            Err(DecodeError::EmptyName { offset: 28 })
+        );
+        assert_eq!(from_json(&function("", "[]")), Err(DecodeError::EmptyName { offset: 7 }));
+        assert_eq!(from_bytes(&[VAR, 2, 0xc3, 0x28]), Err(DecodeError::InvalidUtf8 { offset: 2 }));
+    }
+
+    #[test]
+    fn truncated_input() {
+        for input in FORMULAS {
+            let e = parse(input).unwrap();
+            let json = to_json(&e);
+            for end in 0..json.len() {
+                if let Some(prefix) = json.get(..end) {
+                    assert_eq!(from_json(prefix), Err(DecodeError::UnexpectedEnd), "{prefix}");
+                }
+            }
+            let bytes = to_bytes(&e);
+            for end in 0..bytes.len() {
+                assert_eq!(from_bytes(&bytes[..end]), Err(DecodeError::UnexpectedEnd), "{input}");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_bytes() {
+        assert_eq!(from_bytes(&[27]), Err(DecodeError::UnknownTag { offset: 0, tag: 27 }));
+        assert_eq!(
+            from_bytes(&[0, VALUE, 1, 255]),
+            Err(DecodeError::UnknownTag { offset: 3, tag: 255 })
+        );
+        assert_eq!(from_bytes(&[TRUE, TRUE]), Err(DecodeError::TrailingInput { offset: 1 }));
+        // `fn f(x, x) = 1 in 2` and `fn (x) = 1 in 2`.
+        let define = |name: &[u8], params: &[&[u8]]| {
+            let mut bytes = vec![FN, name.len() as u8];
+            bytes.extend(name);
+            bytes.push(params.len() as u8);
+            for param in params {
+                bytes.push(param.len() as u8);
+                bytes.extend(*param);
+            }
+            bytes.extend([VALUE, 2, VALUE, 4]);
+            from_bytes(&bytes)
+        };
+        assert_eq!(
+            define(b"f", &[b"x", b"x"]),
+            Err(DecodeError::DuplicateParameter { offset: 6, name: String::from("x") })
+        );
+        assert_eq!(define(b"f", &[b"x", b""]), Err(DecodeError::EmptyName { offset: 6 }));
+        assert_eq!(define(b"", &[b"x"]), Err(DecodeError::EmptyName { offset: 1 }));
+        assert_eq!(define(b"f", &[b"x", b"y"]), Ok(parse("fn f(x, y) = 1 in 2").unwrap()));
+        let mut huge = vec![VALUE];
+        huge.extend([0xff; 9]);
+        huge.push(0x02);
+        assert_eq!(from_bytes(&huge), Err(DecodeError::IntegerOutOfRange { offset: 1 }));
+        // A name claiming to be far longer than the input.
+        let mut long = vec![VAR];
+        long.extend([0xff; 9]);
+        long.push(0x01);
+        assert_eq!(from_bytes(&long), Err(DecodeError::UnexpectedEnd));
+        // So is a call claiming far more arguments.
+        let mut many = vec![CALL, 1, b'f'];
+        many.extend([0xff; 9]);
+        many.extend([0x01, TRUE]);
+        assert_eq!(from_bytes(&many), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let e = nested(MAX_DEPTH);
+        assert_eq!(from_json(&to_json(&e)), Ok(e.clone()));
+        assert_eq!(from_bytes(&to_bytes(&e)), Ok(e));
+        // Encoding doesn't recurse, so it copes with much deeper trees.
+        let e = nested(100_000);
+        let json = to_json(&e);
+        let offset = json.match_indices('{').nth(MAX_DEPTH).unwrap().0;
+        assert_eq!(from_json(&json), Err(DecodeError::TooDeep { offset }));
+        assert_eq!(from_bytes(&to_bytes(&e)), Err(DecodeError::TooDeep { offset: MAX_DEPTH }));
+        let open = "{\"unary\": \"not\", \"operand\": ".repeat(1_000_000);
+        assert!(matches!(from_json(&open), Err(DecodeError::TooDeep { .. })));
+        assert!(matches!(from_bytes(&[UNARY; 1_000_000]), Err(DecodeError::TooDeep { .. })));
+    }
+}
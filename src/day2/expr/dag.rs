@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::{
+    Env, EvalError, Expression, Function, Number, Operation, UnaryOperation, MAX_CALL_DEPTH,
+};
+
+/// The index of a node in a `Dag`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+/// A node of a `Dag`, like an `Expression` whose children are IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node<N = i64> {
+    Op {
+        op: Operation,
+        left: NodeId,
+        right: NodeId,
+    },
+    Unary {
+        op: UnaryOperation,
+        operand: NodeId,
+    },
+    If {
+        cond: NodeId,
+        then: NodeId,
+        otherwise: NodeId,
+    },
+    Value(N),
+    Bool(bool),
+    Var(String),
+    Let {
+        name: String,
+        value: NodeId,
+        body: NodeId,
+    },
+    /// A function definition, whose body is `definition`, in scope in `body`.
+    Define {
+        name: String,
+        params: Vec<String>,
+        definition: NodeId,
+        body: NodeId,
+    },
+    Call {
+        name: String,
+        args: Vec<NodeId>,
+    },
+}
+
+/// Expressions stored as a directed acyclic graph in an arena.
+///
+/// Nodes are hash-consed: adding a node that is already in the arena
+/// returns the existing ID, so identical subexpressions, even of different
+/// expressions, are stored once. Children are always added before their
+/// parents, so a node's ID is greater than those of its children.
+///
+/// Values must be `Eq` and `Hash` to be compared, which rules out `f64`.
+#[derive(Debug, Clone)]
+pub struct Dag<N = i64> {
+    nodes: Vec<Node<N>>,
+    ids: HashMap<Node<N>, NodeId>,
+}
+
+/// Written out so that `N` need not be `Default`.
+impl<N> Default for Dag<N> {
+    fn default() -> Self {
+        Dag { nodes: Vec::new(), ids: HashMap::new() }
+    }
+}
+
+impl<N: Number + Eq + Hash> Dag<N> {
+    pub fn new() -> Dag<N> {
+        Dag::default()
+    }
+
+    /// The number of distinct nodes stored.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node<N> {
+        &self.nodes[id.0]
+    }
+
+    /// Add a node whose children are already in the arena, or find it if it
+    /// is there already.
+    pub fn add(&mut self, node: Node<N>) -> NodeId {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    /// Add every node of `e`, returning the ID of its root.
+    pub fn insert(&mut self, e: &Expression<N>) -> NodeId {
+        // Each expression is visited twice: first to queue its children,
+        // then to add it once the IDs of the children are on `ids`.
+        let mut tasks = vec![(e, false)];
+        let mut ids = Vec::new();
+        while let Some((e, children_added)) = tasks.pop() {
+            let node = match e {
+                Expression::Value(v) => Node::Value(v.clone()),
+                Expression::Bool(b) => Node::Bool(*b),
+                Expression::Var(name) => Node::Var(name.clone()),
+                _ if !children_added => {
+                    tasks.push((e, true));
+                    match e {
+                        Expression::Op { left, right, .. } => {
+                            tasks.extend([(&**right, false), (left, false)])
+                        }
+                        Expression::Unary { operand, .. } => tasks.push((operand, false)),
+                        Expression::If { cond, then, otherwise } => {
+                            tasks.extend([(&**otherwise, false), (then, false), (cond, false)])
+                        }
+                        Expression::Let { value, body, .. } => {
+                            tasks.extend([(&**body, false), (value, false)])
+                        }
+                        Expression::Define { function, body } => {
+                            tasks.extend([(&**body, false), (&function.body, false)])
+                        }
+                        Expression::Call { args, .. } => {
+                            tasks.extend(args.iter().rev().map(|arg| (arg, false)))
+                        }
+                        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => {
+                            unreachable!("leaves have no children")
+                        }
+                    }
+                    continue;
+                }
+                Expression::Op { op, .. } => {
+                    let right = ids.pop().expect("missing right operand");
+                    let left = ids.pop().expect("missing left operand");
+                    Node::Op { op: op.clone(), left, right }
+                }
+                Expression::Unary { op, .. } => {
+                    Node::Unary { op: op.clone(), operand: ids.pop().expect("missing operand") }
+                }
+                Expression::If { .. } => {
+                    let otherwise = ids.pop().expect("missing else branch");
+                    let then = ids.pop().expect("missing then branch");
+                    let cond = ids.pop().expect("missing condition");
+                    Node::If { cond, then, otherwise }
+                }
+                Expression::Let { name, .. } => {
+                    let body = ids.pop().expect("missing body");
+                    let value = ids.pop().expect("missing bound value");
+                    Node::Let { name: name.clone(), value, body }
+                }
+                Expression::Define { function, .. } => {
+                    let body = ids.pop().expect("missing body");
+                    let definition = ids.pop().expect("missing function body");
+                    let (name, params) = (function.name.clone(), function.params.clone());
+                    Node::Define { name, params, definition, body }
+                }
+                Expression::Call { name, args } => {
+                    let args = ids.split_off(ids.len() - args.len());
+                    Node::Call { name: name.clone(), args }
+                }
+            };
+            ids.push(self.add(node));
+        }
+        ids.pop().expect("no node added")
+    }
+
+    /// The expression rooted at `id`, with shared nodes copied everywhere
+    /// they are used.
+    pub fn expression(&self, id: NodeId) -> Expression<N> {
+        let mut tasks = vec![(id, false)];
+        let mut built = Vec::new();
+        while let Some((id, children_built)) = tasks.pop() {
+            let e = match self.node(id) {
+                Node::Value(v) => Expression::Value(v.clone()),
+                Node::Bool(b) => Expression::Bool(*b),
+                Node::Var(name) => Expression::Var(name.clone()),
+                node if !children_built => {
+                    tasks.push((id, true));
+                    match node {
+                        Node::Op { left, right, .. } => {
+                            tasks.extend([(*right, false), (*left, false)])
+                        }
+                        Node::Unary { operand, .. } => tasks.push((*operand, false)),
+                        Node::If { cond, then, otherwise } => {
+                            tasks.extend([(*otherwise, false), (*then, false), (*cond, false)])
+                        }
+                        Node::Let { value, body, .. } => {
+                            tasks.extend([(*body, false), (*value, false)])
+                        }
+                        Node::Define { definition, body, .. } => {
+                            tasks.extend([(*body, false), (*definition, false)])
+                        }
+                        Node::Call { args, .. } => {
+                            tasks.extend(args.iter().rev().map(|&arg| (arg, false)))
+                        }
+                        Node::Value(_) | Node::Bool(_) | Node::Var(_) => {
+                            unreachable!("leaves have no children")
+                        }
+                    }
+                    continue;
+                }
+                Node::Op { op, .. } => {
+                    let right = built.pop().expect("missing right operand");
+                    let left = built.pop().expect("missing left operand");
+                    Expression::Op { op: op.clone(), left: Box::new(left), right: Box::new(right) }
+                }
+                Node::Unary { op, .. } => Expression::Unary {
+                    op: op.clone(),
+                    operand: Box::new(built.pop().expect("missing operand")),
+                },
+                Node::If { .. } => {
+                    let otherwise = built.pop().expect("missing else branch");
+                    let then = built.pop().expect("missing then branch");
+                    let cond = built.pop().expect("missing condition");
+                    Expression::If {
+                        cond: Box::new(cond),
+                        then: Box::new(then),
+                        otherwise: Box::new(otherwise),
+                    }
+                }
+                Node::Let { name, .. } => {
+                    let body = built.pop().expect("missing body");
+                    let value = built.pop().expect("missing bound value");
+                    Expression::Let {
+                        name: name.clone(),
+                        value: Box::new(value),
+                        body: Box::new(body),
+                    }
+                }
+                Node::Define { name, params, .. } => {
+                    let body = built.pop().expect("missing body");
+                    let definition = built.pop().expect("missing function body");
+                    let function =
+                        Function { name: name.clone(), params: params.clone(), body: definition };
+                    Expression::Define { function: Box::new(function), body: Box::new(body) }
+                }
+                Node::Call { name, args } => {
+                    let args = built.split_off(built.len() - args.len());
+                    Expression::Call { name: name.clone(), args }
+                }
+            };
+            built.push(e);
+        }
+        built.pop().expect("no expression built")
+    }
+
+    /// An evaluator taking variable values from `env`.
+    pub fn evaluator<'a>(&'a self, env: &'a Env<N>) -> Evaluator<'a, N> {
+        Evaluator {
+            dag: self,
+            env,
+            memo: vec![None; self.nodes.len()],
+            scopes: Vec::new(),
+            scope_ids: HashMap::new(),
+            scoped_memo: HashMap::new(),
+            evaluated: 0,
+        }
+    }
+}
+
+/// Evaluates the nodes of a `Dag`, remembering the value of every node it
+/// has evaluated so that shared nodes are only evaluated once, even across
+/// calls to `eval`.
+///
+/// Under a `let`, `fn` or call, a node can have a different value in each
+/// scope it is evaluated in, so values are remembered per scope. Scopes
+/// binding the same names to the same values are the same scope, so calling
+/// a function again with the same arguments reuses the value of the first
+/// call. Along with each value is how deeply calls nested to compute it, and
+/// it is only reused where evaluating it again would stay within
+/// `MAX_CALL_DEPTH`, so deep recursion fails just as it does for
+/// `eval_with`.
+pub struct Evaluator<'a, N = i64> {
+    dag: &'a Dag<N>,
+    env: &'a Env<N>,
+    /// The values of nodes outside of any binding.
+    memo: Vec<Option<Memo<N>>>,
+    /// The scope with the ID `i + 1` binds `scopes[i]`. Scope 0 binds
+    /// nothing.
+    scopes: Vec<Scope<'a, N>>,
+    scope_ids: HashMap<Scope<'a, N>, ScopeId>,
+    /// The values of nodes inside a scope other than 0.
+    scoped_memo: HashMap<(NodeId, ScopeId), Memo<N>>,
+    evaluated: usize,
+}
+
+/// A value, and the depth of the calls nested in the evaluation that
+/// produced it.
+type Memo<N> = (N, usize);
+
+/// The index of a scope made by an `Evaluator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ScopeId(usize);
+
+/// The innermost name bound by a `let`, `fn` or call, on top of the scope
+/// it was bound in.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Scope<'a, N> {
+    parent: ScopeId,
+    name: &'a str,
+    binding: Binding<N>,
+}
+
+/// Values and functions have separate names.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Binding<N> {
+    Value(N),
+    /// The `Define` node of a function.
+    Function(NodeId),
+}
+
+/// A pending step of `Evaluator::eval`, which keeps the values it has
+/// computed on a stack along with the depth of the calls nested in them.
+enum Task {
+    /// Push the value of the node, evaluating it unless it is memoized.
+    Eval(NodeId, ScopeId),
+    /// Pop the values of the node's operands and push its value.
+    Apply(NodeId, ScopeId),
+    /// Pop the value of the condition of an `If`, or of the left operand of
+    /// `&&` or `||`, and decide what to evaluate next.
+    Branch(NodeId, ScopeId),
+    /// Pop the value of a `Let` and evaluate its body with it bound.
+    Bind(NodeId, ScopeId),
+    /// Pop the arguments of a call to the function defined by the given
+    /// `Define` node, and evaluate its body with them bound on top of the
+    /// scope binding the function.
+    Call(NodeId, ScopeId, NodeId, ScopeId),
+    /// Remember the value on top of the stack as the node's, with calls
+    /// nested at least as deep as given.
+    Finish(NodeId, ScopeId, usize),
+    /// Like `Finish`, for a call that has returned.
+    Return(NodeId, ScopeId, usize),
+}
+
+impl<'a, N: Number + Eq + Hash> Evaluator<'a, N> {
+    /// The number of nodes evaluated so far, not counting memoized ones.
+    pub fn evaluated(&self) -> usize {
+        self.evaluated
+    }
+
+    /// Evaluate the expression rooted at `id`, with the same results and
+    /// errors as `eval_with` on the corresponding `Expression`.
+    pub fn eval(&mut self, id: NodeId) -> Result<N, EvalError<N>> {
+        let mut tasks = vec![Task::Eval(id, ScopeId(0))];
+        let mut values = Vec::new();
+        let mut calls = 0;
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Eval(id, scope) => {
+                    // Any deeper, evaluating the node again fails as it does
+                    // for `eval_with`.
+                    if let Some(memo) = self.recall(id, scope) {
+                        if calls + memo.1 <= MAX_CALL_DEPTH {
+                            values.push(memo.clone());
+                            continue;
+                        }
+                    }
+                    let value = match self.dag.node(id) {
+                        Node::Value(v) => v.clone(),
+                        Node::Bool(b) => N::from_bool(*b),
+                        Node::Var(name) => self.lookup_value(scope, name)?,
+                        Node::Op { op: Operation::And | Operation::Or, left, .. } => {
+                            tasks.extend([Task::Branch(id, scope), Task::Eval(*left, scope)]);
+                            continue;
+                        }
+                        Node::If { cond, .. } => {
+                            tasks.extend([Task::Branch(id, scope), Task::Eval(*cond, scope)]);
+                            continue;
+                        }
+                        Node::Op { left, right, .. } => {
+                            tasks.extend([
+                                Task::Apply(id, scope),
+                                Task::Eval(*right, scope),
+                                Task::Eval(*left, scope),
+                            ]);
+                            continue;
+                        }
+                        Node::Unary { operand, .. } => {
+                            tasks.extend([Task::Apply(id, scope), Task::Eval(*operand, scope)]);
+                            continue;
+                        }
+                        Node::Let { value, .. } => {
+                            tasks.extend([Task::Bind(id, scope), Task::Eval(*value, scope)]);
+                            continue;
+                        }
+                        Node::Define { name, body, .. } => {
+                            let inner = self.bind(scope, name, Binding::Function(id));
+                            tasks.extend([Task::Finish(id, scope, 0), Task::Eval(*body, inner)]);
+                            continue;
+                        }
+                        Node::Call { name, args } => {
+                            let (function, defined) =
+                                self.lookup_function(scope, name, args.len())?;
+                            tasks.push(Task::Call(id, scope, function, defined));
+                            tasks.extend(args.iter().rev().map(|&arg| Task::Eval(arg, scope)));
+                            continue;
+                        }
+                    };
+                    values.push(self.remember(id, scope, (value, 0)));
+                }
+                Task::Apply(id, scope) => {
+                    let (value, depth) = match self.dag.node(id) {
+                        Node::Op { op, .. } => {
+                            let (right, right_depth) = values.pop().expect("missing right operand");
+                            let (left, left_depth) = values.pop().expect("missing left operand");
+                            (op.apply(&left, &right), left_depth.max(right_depth))
+                        }
+                        Node::Unary { op, .. } => {
+                            let (operand, depth) = values.pop().expect("missing operand");
+                            (op.apply(&operand), depth)
+                        }
+                        _ => unreachable!("no operation to apply"),
+                    };
+                    let value = value.map_err(|err| err.at(self.dag.expression(id)))?;
+                    values.push(self.remember(id, scope, (value, depth)));
+                }
+                Task::Branch(id, scope) => match self.dag.node(id) {
+                    Node::If { then, otherwise, .. } => {
+                        let (cond, depth) = values.pop().expect("missing condition");
+                        let branch = if cond.is_true() { *then } else { *otherwise };
+                        tasks.extend([Task::Finish(id, scope, depth), Task::Eval(branch, scope)]);
+                    }
+                    Node::Op { op, right, .. } => {
+                        let (left, depth) = values.pop().expect("missing left operand");
+                        if let Some(value) = op.short_circuit(&left) {
+                            values.push(self.remember(id, scope, (value, depth)));
+                        } else {
+                            values.push((left, depth));
+                            tasks.extend([Task::Apply(id, scope), Task::Eval(*right, scope)]);
+                        }
+                    }
+                    _ => unreachable!("no branch to take"),
+                },
+                Task::Bind(id, scope) => {
+                    let Node::Let { name, body, .. } = self.dag.node(id) else {
+                        unreachable!("no value to bind")
+                    };
+                    let (value, depth) = values.pop().expect("missing bound value");
+                    let inner = self.bind(scope, name, Binding::Value(value));
+                    tasks.extend([Task::Finish(id, scope, depth), Task::Eval(*body, inner)]);
+                }
+                Task::Call(id, scope, function, defined) => {
+                    let Node::Define { params, definition, .. } = self.dag.node(function) else {
+                        unreachable!("functions are bound to their definitions")
+                    };
+                    if calls == MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit(self.dag.expression(id)));
+                    }
+                    calls += 1;
+                    let args = values.split_off(values.len() - params.len());
+                    let mut depth = 0;
+                    let mut inner = defined;
+                    for (param, (arg, arg_depth)) in params.iter().zip(args) {
+                        depth = depth.max(arg_depth);
+                        inner = self.bind(inner, param, Binding::Value(arg));
+                    }
+                    tasks.extend([Task::Return(id, scope, depth), Task::Eval(*definition, inner)]);
+                }
+                Task::Finish(id, scope, depth) => {
+                    let (value, inner) = values.pop().expect("missing branch value");
+                    values.push(self.remember(id, scope, (value, depth.max(inner))));
+                }
+                Task::Return(id, scope, depth) => {
+                    calls -= 1;
+                    let (value, inner) = values.pop().expect("missing return value");
+                    values.push(self.remember(id, scope, (value, depth.max(inner + 1))));
+                }
+            }
+        }
+        Ok(values.pop().expect("no value left").0)
+    }
+
+    /// The scope binding `name` on top of `parent`.
+    fn bind(&mut self, parent: ScopeId, name: &'a str, binding: Binding<N>) -> ScopeId {
+        let scope = Scope { parent, name, binding };
+        if let Some(&id) = self.scope_ids.get(&scope) {
+            return id;
+        }
+        self.scopes.push(scope.clone());
+        let id = ScopeId(self.scopes.len());
+        self.scope_ids.insert(scope, id);
+        id
+    }
+
+    /// The bindings of `scope` and the scopes around it, innermost first.
+    fn bindings(&self, mut scope: ScopeId) -> impl Iterator<Item = (ScopeId, &Scope<'a, N>)> {
+        std::iter::from_fn(move || {
+            let id = scope;
+            let bound = self.scopes.get(id.0.checked_sub(1)?)?;
+            scope = bound.parent;
+            Some((id, bound))
+        })
+    }
+
+    fn lookup_value(&self, scope: ScopeId, name: &str) -> Result<N, EvalError<N>> {
+        let bound = self.bindings(scope).find_map(|(_, bound)| match &bound.binding {
+            Binding::Value(value) if bound.name == name => Some(value),
+            _ => None,
+        });
+        bound
+            .or_else(|| self.env.get(name))
+            .cloned()
+            .ok_or_else(|| EvalError::UnboundVariable(name.to_string()))
+    }
+
+    /// The `Define` node of the function `name`, after checking that it
+    /// takes `arity` arguments, and the scope binding it.
+    fn lookup_function(
+        &self,
+        scope: ScopeId,
+        name: &str,
+        arity: usize,
+    ) -> Result<(NodeId, ScopeId), EvalError<N>> {
+        let found = self.bindings(scope).find_map(|(id, bound)| match bound.binding {
+            Binding::Function(function) if bound.name == name => Some((function, id)),
+            _ => None,
+        });
+        let Some((function, defined)) = found else {
+            return Err(EvalError::UnknownFunction(name.to_string()));
+        };
+        let Node::Define { params, .. } = self.dag.node(function) else {
+            unreachable!("functions are bound to their definitions")
+        };
+        if params.len() != arity {
+            return Err(EvalError::Arity {
+                name: name.to_string(),
+                expected: params.len(),
+                found: arity,
+            });
+        }
+        Ok((function, defined))
+    }
+
+    fn recall(&self, id: NodeId, scope: ScopeId) -> Option<&Memo<N>> {
+        match scope {
+            ScopeId(0) => self.memo[id.0].as_ref(),
+            scope => self.scoped_memo.get(&(id, scope)),
+        }
+    }
+
+    fn remember(&mut self, id: NodeId, scope: ScopeId, memo: Memo<N>) -> Memo<N> {
+        match scope {
+            ScopeId(0) => self.memo[id.0] = Some(memo.clone()),
+            scope => {
+                self.scoped_memo.insert((id, scope), memo.clone());
+            }
+        }
+        self.evaluated += 1;
+        memo
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{eval_with, parse, Rational};
+
+    #[test]
+    fn shares_identical_subtrees() {
+        let mut dag = Dag::new();
+        let e = parse("(a * b + 1) * (a * b + 1) - a * b").unwrap();
+        let root = dag.insert(&e);
+        // a, b, a * b, 1, a * b + 1, the product and the difference.
+        assert_eq!(dag.node_count(), 7);
+        let &Node::Op { left, right, .. } = dag.node(root) else { panic!("not an operation") };
+        let Node::Op { left: square_left, right: square_right, .. } = dag.node(left) else {
+            panic!("not an operation")
+        };
+        assert_eq!(square_left, square_right);
+        assert_eq!(dag.insert(&parse("a * b").unwrap()), right);
+        assert_eq!(dag.node_count(), 7);
+        assert_eq!(dag.expression(root), e);
+    }
+
+    #[test]
+    fn evaluates_shared_nodes_once() {
+        let mut dag = Dag::new();
+        let e = parse("(a * b + 1) * (a * b + 1) - a * b").unwrap();
+        let root = dag.insert(&e);
+        let other = dag.insert(&parse("a * b + 1").unwrap());
+        let env = Env::from([(String::from("a"), 3), (String::from("b"), 4)]);
+        let mut evaluator = dag.evaluator(&env);
+        assert_eq!(evaluator.eval(root), Ok(157));
+        assert_eq!(evaluator.evaluated(), 7);
+        // Later evaluations reuse what is already known.
+        assert_eq!(evaluator.eval(other), Ok(13));
+        assert_eq!(evaluator.evaluated(), 7);
+
+        // Doubling 100 times would be a tree of 2^101 nodes.
+        let mut dag = Dag::<Rational>::new();
+        let mut id = dag.add(Node::Var(String::from("x")));
+        for _ in 0..100 {
+            id = dag.add(Node::Op { op: Operation::Div, left: id, right: id });
+        }
+        let env = Env::from([(String::from("x"), Rational::new(1, 2).unwrap())]);
+        let mut evaluator = dag.evaluator(&env);
+        assert_eq!(evaluator.eval(id), Ok(Rational::new(1, 1).unwrap()));
+        assert_eq!(evaluator.evaluated(), 101);
+    }
+
+    #[test]
+    fn remembers_values_per_scope() {
+        let mut dag = Dag::new();
+        let root = dag.insert(&parse("fn f(v) = v * v in f(3) + f(1 + 2)").unwrap());
+        let env = Env::new();
+        let mut evaluator = dag.evaluator(&env);
+        assert_eq!(evaluator.eval(root), Ok(18));
+        // 3, v, v * v, f(3), 1, 2, 1 + 2, f(1 + 2), the sum and the
+        // definition: the body is evaluated once, since both calls bind `v`
+        // to 3.
+        assert_eq!(evaluator.evaluated(), 10);
+
+        // The same node can have different values in different scopes.
+        let root = dag.insert(&parse("let x = 1 in x + (let x = 2 in x)").unwrap());
+        assert_eq!(dag.evaluator(&env).eval(root), Ok(3));
+
+        // Values need not be `Default` for an empty `Dag`.
+        let dag: Dag<f64> = Dag::default();
+        assert!(format!("{dag:?}").contains("nodes: []"));
+    }
+
+    #[test]
+    fn matches_eval() {
+        let env = Env::from([(String::from("x"), 0), (String::from("y"), 7)]);
+        let mut dag = Dag::new();
+        for input in [
+            "x != 0 && 10 / x > 1 || if x == 0 then y > 1 else 10 / x == y",
+            "if y > 1 then abs(-y) ^ 2 % 5 else 1 / x",
+            "max(y, 10 / x)",
+            "y * 9223372036854775807",
+            "z + 1",
+            "fn f(v) = v * y in let z = f(x + 1) in z + f(z) + f(x + 1)",
+            "let y = 1 / x in y",
+            "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1) in fact(20)",
+            "fn loop(n) = loop(n + 1) in loop(0)",
+            "let k = 2 in fn f(v) = v * k in let k = 100 in f(3) + k",
+            "fn f(v) = v in f(1, 2)",
+            "fn f(v) = v in g(1)",
+            "fn f(v) = v in let f = 1 in f(f)",
+        ] {
+            let e = parse(input).unwrap();
+            let root = dag.insert(&e);
+            assert_eq!(dag.evaluator(&env).eval(root), eval_with(&e, &env), "{input}");
+        }
+    }
+
+    #[test]
+    fn keeps_the_call_depth_limit() {
+        // `f(990)` is remembered from the first call, but calling it again
+        // from 20 calls deep nests too deep, and from 5 calls deep does not.
+        let env = Env::new();
+        let mut dag = Dag::new();
+        for depth in [5, 20] {
+            let input = format!(
+                "fn f(n) = if n == 0 then 0 else f(n - 1) in \
+                 fn g(n) = if n == 0 then f(990) else g(n - 1) in f(990) + g({depth})"
+            );
+            let e = parse(&input).unwrap();
+            let root = dag.insert(&e);
+            assert_eq!(dag.evaluator(&env).eval(root), eval_with(&e, &env), "{input}");
+        }
+    }
+
+    #[test]
+    fn deep_expressions() {
+        let mut e = Expression::Value(1);
+        for _ in 0..100_000 {
+            e = Expression::Op {
+                op: Operation::Add,
+                left: Box::new(e),
+                right: Box::new(Expression::Value(1)),
+            };
+        }
+        let mut dag = Dag::new();
+        let root = dag.insert(&e);
+        assert_eq!(dag.node_count(), 100_001);
+        assert_eq!(dag.evaluator(&Env::new()).eval(root), Ok(100_001));
+        // Comparing the trees directly would recurse, but equal trees are
+        // hash-consed to the same node.
+        let back = dag.expression(root);
+        assert_eq!(dag.insert(&back), root);
+    }
+}
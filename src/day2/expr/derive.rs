@@ -0,0 +1,492 @@
+use thiserror::Error;
+
+use super::simplify::simplify;
+use super::{Expression, Number, Operation, UnaryOperation};
+
+/// How deeply the expressions `derive` works on may nest. Differentiating
+/// recurses, so deeper trees are an error rather than a stack overflow.
+pub const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DeriveError {
+    /// `a ^ b` where `b` depends on the variable would need logarithms.
+    #[error("cannot differentiate a power whose exponent depends on `{0}`")]
+    VariableExponent(String),
+    /// A function depending on the variable would have to be inlined, or
+    /// the value of a `let` would be captured by a name bound in its body.
+    #[error("cannot differentiate through functions of the variable or captured `let` values")]
+    Binding,
+    #[error("expression nested more than {MAX_DEPTH} deep")]
+    TooDeep,
+}
+
+/// The derivative of `e` with respect to `var`, simplified.
+///
+/// Besides the sum, product and quotient rules, this handles powers with
+/// exponents that do not depend on `var`, and uses `a % b = a - b * trunc(a /
+/// b)`, `abs(u)' = u' * u / abs(u)` and `min(a, b) = (a + b - abs(a - b)) / 2`
+/// for the piecewise operations. Those are only valid away from the points
+/// where the operation has a kink, where the derivative does not exist.
+/// Likewise, booleans are piecewise constant and the derivative of a
+/// conditional is a conditional between the derivatives of its branches.
+///
+/// `let` is differentiated with its value substituted for the name. Calls
+/// are constant as long as their arguments and the functions they call don't
+/// depend on `var`.
+pub fn derive<N: Number>(e: &Expression<N>, var: &str) -> Result<Expression<N>, DeriveError> {
+    check_depth(e)?;
+    Ok(simplify(differentiate(e, var)?))
+}
+
+/// Fails if `e` nests more than `MAX_DEPTH` deep, without recursing.
+fn check_depth<N>(e: &Expression<N>) -> Result<(), DeriveError> {
+    let mut pending = vec![(e, 0)];
+    while let Some((e, depth)) = pending.pop() {
+        if depth > MAX_DEPTH {
+            return Err(DeriveError::TooDeep);
+        }
+        let depth = depth + 1;
+        match e {
+            Expression::Op { left, right, .. } => {
+                pending.extend([(&**left, depth), (right, depth)])
+            }
+            Expression::Unary { operand, .. } => pending.push((operand, depth)),
+            Expression::If { cond, then, otherwise } => {
+                pending.extend([(&**cond, depth), (then, depth), (otherwise, depth)])
+            }
+            Expression::Let { value, body, .. } => {
+                pending.extend([(&**value, depth), (body, depth)])
+            }
+            Expression::Define { function, body } => {
+                pending.extend([(&function.body, depth), (body, depth)])
+            }
+            Expression::Call { args, .. } => pending.extend(args.iter().map(|arg| (arg, depth))),
+            Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn differentiate<N: Number>(e: &Expression<N>, var: &str) -> Result<Expression<N>, DeriveError> {
+    Ok(match e {
+        Expression::Value(_) | Expression::Bool(_) => constant(0),
+        Expression::Var(name) => constant(if name == var { 1 } else { 0 }),
+        Expression::Unary { op, operand } => {
+            let du = differentiate(operand, var)?;
+            match op {
+                UnaryOperation::Neg => neg(du),
+                UnaryOperation::Abs => mul(du, sign(operand)),
+                UnaryOperation::Not => constant(0),
+            }
+        }
+        Expression::Op { op, left, right } => {
+            let da = differentiate(left, var)?;
+            let db = differentiate(right, var)?;
+            return combine(op, left, right, da, db, var);
+        }
+        Expression::Let { .. } | Expression::Define { .. } | Expression::Call { .. }
+            if !mentions(e, var) =>
+        {
+            constant(0)
+        }
+        Expression::Let { name, value, body } => {
+            // Substituting can make the tree deeper.
+            let substituted = substitute(body, name, value)?;
+            check_depth(&substituted)?;
+            return differentiate(&substituted, var);
+        }
+        Expression::Define { function, body }
+            if !function.params.iter().any(|p| p == var) && mentions(&function.body, var) =>
+        {
+            return Err(DeriveError::Binding)
+        }
+        Expression::Define { function, body } => match differentiate(body, var)? {
+            body @ Expression::Value(_) => body,
+            body => Expression::Define { function: function.clone(), body: Box::new(body) },
+        },
+        Expression::Call { .. } => return Err(DeriveError::Binding),
+        Expression::If { cond, then, otherwise } => {
+            let then = differentiate(then, var)?;
+            let otherwise = differentiate(otherwise, var)?;
+            if then == otherwise {
+                then
+            } else {
+                Expression::If {
+                    cond: cond.clone(),
+                    then: Box::new(then),
+                    otherwise: Box::new(otherwise),
+                }
+            }
+        }
+    })
+}
+
+/// Combines the derivatives `da` and `db` of the operands of `a op b`. Kept
+/// out of `differentiate` so that its recursion stays cheap on the stack.
+fn combine<N: Number>(
+    op: &Operation,
+    a: &Expression<N>,
+    b: &Expression<N>,
+    da: Expression<N>,
+    db: Expression<N>,
+    var: &str,
+) -> Result<Expression<N>, DeriveError> {
+    Ok(match op {
+        Operation::Add => add(da, db),
+        Operation::Sub => sub(da, db),
+        Operation::Mul => add(mul(da, b.clone()), mul(a.clone(), db)),
+        Operation::Div => {
+            let numerator = sub(mul(da, b.clone()), mul(a.clone(), db));
+            div(numerator, mul(b.clone(), b.clone()))
+        }
+        Operation::Rem => {
+            // trunc(a / b) is piecewise constant, so only a and b
+            // contribute; it equals (a - a % b) / b.
+            let quotient = div(sub(a.clone(), binary(Operation::Rem, a, b)), b.clone());
+            sub(da, mul(db, quotient))
+        }
+        Operation::Pow => {
+            if mentions(b, var) {
+                return Err(DeriveError::VariableExponent(var.to_string()));
+            }
+            if is_constant(b, 0) {
+                return Ok(constant(0));
+            }
+            let lowered = pow(a.clone(), sub(b.clone(), constant(1)));
+            let derivative = mul(mul(b.clone(), lowered), da);
+            if matches!(b, Expression::Value(_)) || is_constant(&derivative, 0) {
+                derivative
+            } else {
+                // `a ^ 0` is constant even where `a ^ -1` fails.
+                Expression::If {
+                    cond: Box::new(binary(Operation::Eq, b, &constant(0))),
+                    then: Box::new(constant(0)),
+                    otherwise: Box::new(derivative),
+                }
+            }
+        }
+        Operation::Min | Operation::Max => {
+            let difference = sub(a.clone(), b.clone());
+            let d_abs = mul(sub(da.clone(), db.clone()), sign(&difference));
+            let sum = add(da, db);
+            let twice = if *op == Operation::Min { sub(sum, d_abs) } else { add(sum, d_abs) };
+            div(twice, constant(2))
+        }
+        Operation::Lt
+        | Operation::Le
+        | Operation::Eq
+        | Operation::Ne
+        | Operation::Gt
+        | Operation::Ge
+        | Operation::And
+        | Operation::Or => constant(0),
+    })
+}
+
+/// Whether `e` refers to the variable `var` anywhere it isn't shadowed.
+fn mentions<N>(e: &Expression<N>, var: &str) -> bool {
+    match e {
+        Expression::Let { name, value, body } => {
+            mentions(value, var) || (name != var && mentions(body, var))
+        }
+        Expression::Define { function, body } => {
+            (!function.params.iter().any(|p| p == var) && mentions(&function.body, var))
+                || mentions(body, var)
+        }
+        Expression::Call { args, .. } => args.iter().any(|arg| mentions(arg, var)),
+        Expression::Value(_) | Expression::Bool(_) => false,
+        Expression::Var(name) => name == var,
+        Expression::If { cond, then, otherwise } => {
+            mentions(cond, var) || mentions(then, var) || mentions(otherwise, var)
+        }
+        Expression::Unary { operand, .. } => mentions(operand, var),
+        Expression::Op { left, right, .. } => mentions(left, var) || mentions(right, var),
+    }
+}
+
+/// `e` with `value` in place of the variable `name` wherever it isn't
+/// shadowed. Fails if a name that `value` refers to is bound in between.
+fn substitute<N: Number>(
+    e: &Expression<N>,
+    name: &str,
+    value: &Expression<N>,
+) -> Result<Expression<N>, DeriveError> {
+    let sub = |e: &Expression<N>| substitute(e, name, value).map(Box::new);
+    Ok(match e {
+        Expression::Var(var) if var == name => value.clone(),
+        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => e.clone(),
+        Expression::Let { name: inner, value: bound, body } => {
+            let body = if inner == name {
+                body.clone()
+            } else if mentions(value, inner) && mentions(body, name) {
+                return Err(DeriveError::Binding);
+            } else {
+                sub(body)?
+            };
+            Expression::Let { name: inner.clone(), value: sub(bound)?, body }
+        }
+        Expression::Define { function, body } => {
+            let mut function = function.clone();
+            if !function.params.iter().any(|p| p == name) {
+                if function.params.iter().any(|p| mentions(value, p))
+                    && mentions(&function.body, name)
+                {
+                    return Err(DeriveError::Binding);
+                }
+                function.body = *sub(&function.body)?;
+            }
+            Expression::Define { function, body: sub(body)? }
+        }
+        Expression::Call { name: function, args } => Expression::Call {
+            name: function.clone(),
+            args: args.iter().map(|arg| substitute(arg, name, value)).collect::<Result<_, _>>()?,
+        },
+        Expression::If { cond, then, otherwise } => {
+            Expression::If { cond: sub(cond)?, then: sub(then)?, otherwise: sub(otherwise)? }
+        }
+        Expression::Unary { op, operand } => {
+            Expression::Unary { op: op.clone(), operand: sub(operand)? }
+        }
+        Expression::Op { op, left, right } => {
+            Expression::Op { op: op.clone(), left: sub(left)?, right: sub(right)? }
+        }
+    })
+}
+
+fn is_constant<N: Number>(e: &Expression<N>, value: i64) -> bool {
+    matches!(e, Expression::Value(v) if *v == N::from_i64(value))
+}
+
+fn constant<N: Number>(value: i64) -> Expression<N> {
+    Expression::Value(N::from_i64(value))
+}
+
+/// `left op right`, folded if both sides are constants and the operation
+/// succeeds.
+fn binary<N: Number>(op: Operation, left: &Expression<N>, right: &Expression<N>) -> Expression<N> {
+    if let (Expression::Value(l), Expression::Value(r)) = (left, right) {
+        if let Ok(v) = op.apply(l, r) {
+            return Expression::Value(v);
+        }
+    }
+    Expression::Op { op, left: Box::new(left.clone()), right: Box::new(right.clone()) }
+}
+
+/// `u / abs(u)`, the derivative of `abs` at `u`.
+fn sign<N: Number>(u: &Expression<N>) -> Expression<N> {
+    let abs = Expression::Unary { op: UnaryOperation::Abs, operand: Box::new(u.clone()) };
+    div(u.clone(), abs)
+}
+
+// The constructors below drop terms that are zero because a derivative
+// vanished, keeping the result small before `simplify` runs.
+
+fn add<N: Number>(left: Expression<N>, right: Expression<N>) -> Expression<N> {
+    if is_constant(&left, 0) {
+        right
+    } else if is_constant(&right, 0) {
+        left
+    } else {
+        binary(Operation::Add, &left, &right)
+    }
+}
+
+fn sub<N: Number>(left: Expression<N>, right: Expression<N>) -> Expression<N> {
+    if is_constant(&right, 0) {
+        left
+    } else if is_constant(&left, 0) {
+        neg(right)
+    } else {
+        binary(Operation::Sub, &left, &right)
+    }
+}
+
+fn mul<N: Number>(left: Expression<N>, right: Expression<N>) -> Expression<N> {
+    if is_constant(&left, 0) || is_constant(&right, 0) {
+        constant(0)
+    } else if is_constant(&left, 1) {
+        right
+    } else if is_constant(&right, 1) {
+        left
+    } else {
+        binary(Operation::Mul, &left, &right)
+    }
+}
+
+fn div<N: Number>(left: Expression<N>, right: Expression<N>) -> Expression<N> {
+    if is_constant(&left, 0) {
+        constant(0)
+    } else {
+        binary(Operation::Div, &left, &right)
+    }
+}
+
+fn pow<N: Number>(base: Expression<N>, exponent: Expression<N>) -> Expression<N> {
+    if is_constant(&exponent, 1) {
+        base
+    } else {
+        binary(Operation::Pow, &base, &exponent)
+    }
+}
+
+fn neg<N: Number>(operand: Expression<N>) -> Expression<N> {
+    if is_constant(&operand, 0) {
+        operand
+    } else {
+        Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(operand) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::arbitrary::Rng;
+    use crate::expr::{eval_with, parse, Env};
+
+    fn derived(input: &str) -> String {
+        derive(&parse(input).unwrap(), "x").unwrap().to_string()
+    }
+
+    #[test]
+    fn rules() {
+        assert_eq!(derived("7"), "0");
+        assert_eq!(derived("y"), "0");
+        assert_eq!(derived("x + y * 3"), "1");
+        assert_eq!(derived("x * x"), "x + x");
+        assert_eq!(derived("3 * x ^ 2"), "x * 2 * 3");
+        assert_eq!(derived("x ^ 3"), "x ^ 2 * 3");
+        assert_eq!(derived("x ^ 0"), "0");
+        assert_eq!(derived("y / x"), "-y / (x * x)");
+        assert_eq!(derived("-(x * y)"), "-y");
+        assert_eq!(derived("if x > 0 then x * x else -x"), "if x > 0 then x + x else -1");
+        assert_eq!(derived("if x > 0 then x + y else x"), "1");
+    }
+
+    #[test]
+    fn variable_exponent() {
+        assert_eq!(
+            derive(&parse("2 ^ x").unwrap(), "x"),
+            Err(DeriveError::VariableExponent(String::from("x")))
+        );
+        assert_eq!(derived("x ^ y"), "if y == 0 then 0 else y * x ^ (y - 1)");
+        let env = Env::from([(String::from("x"), 0), (String::from("y"), 0)]);
+        assert_eq!(eval_with(&derive(&parse("x ^ y").unwrap(), "x").unwrap(), &env), Ok(0));
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(derived("let y = x * x in y * 3"), "(x + x) * 3");
+        assert_eq!(derived("let x = 2 in x * y"), "0");
+        assert_eq!(derived("let y = x in let z = 5 in y * z"), "5");
+        assert_eq!(derived("let y = x in y + (fn f(v) = v in f(3))"), "1");
+        assert_eq!(derived("x * g(y)"), "g(y)");
+        assert_eq!(derived("fn f(v) = v in f(3) * x"), "fn f(v) = v in f(3)");
+        for input in [
+            "fn f(v) = v in f(x)",
+            "fn f(v) = x in f(1)",
+            "let y = x in fn f(v) = v * y in f(1)",
+            "let y = z * x in let z = 2 in y",
+            "let y = x in let x = 5 in y * x",
+            "let y = v * x in fn f(v) = y in f(1)",
+        ] {
+            assert_eq!(derive(&parse(input).unwrap(), "x"), Err(DeriveError::Binding), "{input}");
+        }
+    }
+
+    #[test]
+    fn deep_trees() {
+        // `leaf + 1 + 1 + ...`, `n` deep.
+        let chain = |leaf: &str, n: usize| {
+            let mut e = Expression::Var(leaf.to_string());
+            for _ in 0..n {
+                e = Expression::Op {
+                    op: Operation::Add,
+                    left: Box::new(e),
+                    right: Box::new(Expression::Value(1)),
+                };
+            }
+            e
+        };
+        assert_eq!(derive(&chain("x", MAX_DEPTH), "x"), Ok(Expression::Value(1)));
+        assert_eq!(derive(&chain("x", MAX_DEPTH + 1), "x"), Err(DeriveError::TooDeep));
+        assert_eq!(derive(&chain("x", 1_000_000), "x"), Err(DeriveError::TooDeep));
+        // Substituting the value of a `let` can make a tree too deep.
+        let binding = |n: usize| Expression::Let {
+            name: String::from("y"),
+            value: Box::new(chain("x", MAX_DEPTH - 10)),
+            body: Box::new(chain("y", n)),
+        };
+        assert_eq!(derive(&binding(5), "x"), Ok(Expression::Value(1)));
+        assert_eq!(derive(&binding(20), "x"), Err(DeriveError::TooDeep));
+    }
+
+    fn random_expression(rng: &mut Rng, depth: u32) -> Expression<f64> {
+        if depth == 0 || rng.below(4) == 0 {
+            return match rng.below(3) {
+                0 => Expression::Var(String::from("x")),
+                1 => Expression::Var(String::from("y")),
+                _ => Expression::Value(rng.below(7) as f64 - 3.0),
+            };
+        }
+        let choice = rng.below(10);
+        let operand = Box::new(random_expression(rng, depth - 1));
+        match choice {
+            0 => Expression::Unary { op: UnaryOperation::Neg, operand },
+            1 => Expression::Unary { op: UnaryOperation::Abs, operand },
+            2 => Expression::Op {
+                op: Operation::Pow,
+                left: operand,
+                right: Box::new(Expression::Value(rng.below(4) as f64)),
+            },
+            n => {
+                let op = [
+                    Operation::Add,
+                    Operation::Sub,
+                    Operation::Mul,
+                    Operation::Div,
+                    Operation::Rem,
+                    Operation::Min,
+                    Operation::Max,
+                ][n as usize - 3]
+                    .clone();
+                Expression::Op {
+                    op,
+                    left: operand,
+                    right: Box::new(random_expression(rng, depth - 1)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_finite_differences() {
+        const H: f64 = 1e-6;
+        let mut rng = Rng::new(0x9e37_79b9_7f4a_7c15);
+        let mut checked = 0;
+        for _ in 0..500 {
+            let e = random_expression(&mut rng, 4);
+            let de = derive(&e, "x").unwrap();
+            for _ in 0..4 {
+                let (x, y) = (rng.unit() * 6.0 - 3.0, rng.unit() * 6.0 - 3.0);
+                let at = |x: f64| {
+                    let env = Env::from([(String::from("x"), x), (String::from("y"), y)]);
+                    (eval_with(&e, &env).ok(), eval_with(&de, &env).ok())
+                };
+                let (Some(f), Some(slope)) = at(x) else { continue };
+                let (Some(below), _) = at(x - H) else { continue };
+                let (Some(above), _) = at(x + H) else { continue };
+                // Skip points next to a kink or a pole, where the one-sided
+                // slopes disagree.
+                let (left, right) = ((f - below) / H, (above - f) / H);
+                let scale = 1.0 + left.abs().max(right.abs());
+                if (left - right).abs() > 1e-3 * scale || scale > 1e6 {
+                    continue;
+                }
+                let central = (above - below) / (2.0 * H);
+                assert!((slope - central).abs() <= 1e-4 * scale, "d/dx {e} = {de} at x={x}, y={y}");
+                checked += 1;
+            }
+        }
+        assert!(checked > 1000, "only {checked} points checked");
+    }
+}
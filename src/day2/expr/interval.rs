@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use super::{
+    bind, lookup_function, lookup_value, Binding, EvalError, Expression, Function, Number,
+    Operation, Scope, UnaryOperation,
+};
+
+/// The integers from `lo` to `hi`, inclusive. The fields are private so that
+/// an interval is never empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    lo: i64,
+    hi: i64,
+}
+
+impl Interval {
+    /// The interval `[lo, hi]`; panics if it is empty.
+    pub fn new(lo: i64, hi: i64) -> Interval {
+        assert!(lo <= hi, "empty interval [{lo}, {hi}]");
+        Interval { lo, hi }
+    }
+
+    /// The interval containing only `value`.
+    pub fn point(value: i64) -> Interval {
+        Interval { lo: value, hi: value }
+    }
+
+    /// The smallest integer in the interval.
+    pub fn lo(&self) -> i64 {
+        self.lo
+    }
+
+    /// The largest integer in the interval.
+    pub fn hi(&self) -> i64 {
+        self.hi
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    /// The smallest interval containing all of `values`.
+    fn hull(values: impl IntoIterator<Item = i64>) -> Interval {
+        let mut values = values.into_iter();
+        let first = values.next().expect("hull of no values");
+        values.fold(Interval::point(first), |i, v| Interval { lo: i.lo.min(v), hi: i.hi.max(v) })
+    }
+
+    fn boolean(can_be_false: bool, can_be_true: bool) -> Interval {
+        Interval::new(i64::from(!can_be_false), i64::from(can_be_true))
+    }
+
+    fn can_be_false(&self) -> bool {
+        self.contains(0)
+    }
+
+    fn can_be_true(&self) -> bool {
+        *self != Interval::point(0)
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// Ranges of the variables an expression is evaluated over.
+pub type Domain = HashMap<String, Interval>;
+
+/// A way evaluation might fail for some values of the variables in the
+/// domain, carrying the offending sub-expression.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RangeError {
+    #[error("possible division by zero in `{0}`")]
+    PossibleDivisionByZero(Expression),
+    #[error("possible overflow in `{0}`")]
+    PossibleOverflow(Expression),
+    #[error("possible negative exponent in `{0}`")]
+    PossibleNegativeExponent(Expression),
+    #[error("unbound variable `{0}`")]
+    UnboundVariable(String),
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+    #[error("`{name}` takes {expected} arguments but was given {found}")]
+    Arity { name: String, expected: usize, found: usize },
+    #[error("cannot bound the recursive call `{0}`")]
+    Recursion(Expression),
+}
+
+/// Evaluate an integer expression over intervals: the result contains the
+/// value of `e` for every choice of variable values within `domain`.
+///
+/// If this returns `Ok`, `eval_with` succeeds for all of those choices, so a
+/// formula is proven not to overflow or divide by zero anywhere in the
+/// domain. An error only means that it might: the bounds are sound but not
+/// always tight, since every occurrence of a variable is treated as
+/// independent. Booleans are intervals within `[0, 1]`.
+///
+/// Calls are bounded by evaluating the function's body over the intervals
+/// of the arguments. There is no telling how deep recursion goes, so
+/// recursive calls are an error. Like `eval_with`, this uses an explicit
+/// stack, so arbitrarily deep trees can be bounded.
+pub fn eval_interval(e: &Expression, domain: &Domain) -> Result<Interval, RangeError> {
+    let mut tasks = vec![Task::Bound(e)];
+    let mut intervals = Vec::new();
+    let mut scope = None;
+    // The functions being called, innermost last.
+    let mut active: Vec<&Function> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Bound(e) => {
+                let interval = match e {
+                    Expression::Value(v) => Interval::point(*v),
+                    Expression::Bool(b) => Interval::point(i64::from(*b)),
+                    Expression::Var(name) => {
+                        lookup_value(&scope, domain, name).map_err(RangeError::from_lookup)?
+                    }
+                    Expression::Let { value, .. } => {
+                        tasks.push(Task::Bind(e));
+                        tasks.push(Task::Bound(value));
+                        continue;
+                    }
+                    Expression::Define { function, body } => {
+                        let inner = bind(&scope, &function.name, Binding::Function(function));
+                        tasks.push(Task::Leave(mem::replace(&mut scope, inner), false));
+                        tasks.push(Task::Bound(body));
+                        continue;
+                    }
+                    Expression::Call { name, args } => {
+                        let (function, defined) = lookup_function(&scope, name, args.len())
+                            .map_err(RangeError::from_lookup)?;
+                        tasks.push(Task::Call(e, function, defined));
+                        tasks.extend(args.iter().rev().map(Task::Bound));
+                        continue;
+                    }
+                    Expression::Op { op: Operation::And | Operation::Or, left, .. } => {
+                        tasks.push(Task::Branch(e));
+                        tasks.push(Task::Bound(left));
+                        continue;
+                    }
+                    Expression::If { cond, .. } => {
+                        tasks.push(Task::Branch(e));
+                        tasks.push(Task::Bound(cond));
+                        continue;
+                    }
+                    Expression::Op { left, right, .. } => {
+                        tasks.push(Task::Apply(e));
+                        tasks.push(Task::Bound(right));
+                        tasks.push(Task::Bound(left));
+                        continue;
+                    }
+                    Expression::Unary { operand, .. } => {
+                        tasks.push(Task::Apply(e));
+                        tasks.push(Task::Bound(operand));
+                        continue;
+                    }
+                };
+                intervals.push(interval);
+            }
+            Task::Apply(Expression::Op { op: op @ (Operation::And | Operation::Or), .. }) => {
+                let right = intervals.pop().expect("missing right operand");
+                let left = intervals.pop().expect("missing left operand");
+                intervals.push(if *op == Operation::And {
+                    Interval::boolean(
+                        left.can_be_false() || right.can_be_false(),
+                        right.can_be_true() && left.can_be_true(),
+                    )
+                } else {
+                    Interval::boolean(
+                        left.can_be_false() && right.can_be_false(),
+                        left.can_be_true() || right.can_be_true(),
+                    )
+                });
+            }
+            Task::Apply(e @ Expression::Op { op, .. }) => {
+                let b = intervals.pop().expect("missing right operand");
+                let a = intervals.pop().expect("missing left operand");
+                intervals.push(apply(op, a, b).map_err(|err| err.at(e))?);
+            }
+            Task::Apply(e @ Expression::Unary { op, .. }) => {
+                let a = intervals.pop().expect("missing operand");
+                intervals.push(match op {
+                    // Only `i64::MIN` has no negation.
+                    UnaryOperation::Neg | UnaryOperation::Abs if a.lo == i64::MIN => {
+                        return Err(RangeError::PossibleOverflow(e.clone()))
+                    }
+                    UnaryOperation::Neg => Interval::new(-a.hi, -a.lo),
+                    UnaryOperation::Abs if a.lo >= 0 => a,
+                    UnaryOperation::Abs if a.hi <= 0 => Interval::new(-a.hi, -a.lo),
+                    UnaryOperation::Abs => Interval::new(0, a.hi.max(-a.lo)),
+                    UnaryOperation::Not => Interval::boolean(a.can_be_true(), a.can_be_false()),
+                });
+            }
+            Task::Branch(Expression::If { then, otherwise, .. }) => {
+                let cond = intervals.pop().expect("missing condition");
+                match (cond.can_be_true(), cond.can_be_false()) {
+                    (true, false) => tasks.push(Task::Bound(then)),
+                    (false, true) => tasks.push(Task::Bound(otherwise)),
+                    _ => {
+                        tasks.push(Task::Hull);
+                        tasks.push(Task::Bound(otherwise));
+                        tasks.push(Task::Bound(then));
+                    }
+                }
+            }
+            Task::Branch(e @ Expression::Op { op, right, .. }) => {
+                let left = *intervals.last().expect("missing left operand");
+                // The right operand only matters when the left one can't
+                // decide.
+                let undecided =
+                    if *op == Operation::And { left.can_be_true() } else { left.can_be_false() };
+                if undecided {
+                    tasks.push(Task::Apply(e));
+                    tasks.push(Task::Bound(right));
+                } else {
+                    *intervals.last_mut().expect("missing left operand") =
+                        Interval::point(i64::from(*op == Operation::Or));
+                }
+            }
+            Task::Hull => {
+                let otherwise = intervals.pop().expect("missing else branch");
+                let then = intervals.pop().expect("missing then branch");
+                intervals.push(Interval::hull([then.lo, then.hi, otherwise.lo, otherwise.hi]));
+            }
+            Task::Bind(Expression::Let { name, body, .. }) => {
+                let value = intervals.pop().expect("missing bound value");
+                let inner = bind(&scope, name, Binding::Value(value));
+                tasks.push(Task::Leave(mem::replace(&mut scope, inner), false));
+                tasks.push(Task::Bound(body));
+            }
+            Task::Call(e, function, defined) => {
+                if active.iter().any(|&f| std::ptr::eq(f, function)) {
+                    return Err(RangeError::Recursion(e.clone()));
+                }
+                active.push(function);
+                let args = intervals.split_off(intervals.len() - function.params.len());
+                let mut inner = defined;
+                for (param, arg) in function.params.iter().zip(args) {
+                    inner = bind(&inner, param, Binding::Value(arg));
+                }
+                tasks.push(Task::Leave(mem::replace(&mut scope, inner), true));
+                tasks.push(Task::Bound(&function.body));
+            }
+            Task::Leave(outer, call) => {
+                scope = outer;
+                if call {
+                    active.pop();
+                }
+            }
+            Task::Apply(_) | Task::Branch(_) | Task::Bind(_) => {
+                unreachable!("no such step for this expression")
+            }
+        }
+    }
+    Ok(intervals.pop().expect("no interval left"))
+}
+
+/// A pending step of `eval_interval`.
+enum Task<'a> {
+    /// Bound the expression and push its interval.
+    Bound(&'a Expression),
+    /// Pop the intervals of the expression's operands and push its own.
+    Apply(&'a Expression),
+    /// Pop the interval of the condition of an `If`, or of the left operand
+    /// of `&&` or `||`, and decide what to bound next.
+    Branch(&'a Expression),
+    /// Pop the intervals of both branches of an `If` and push their hull.
+    Hull,
+    /// Pop the interval of a `Let` and bound its body with it bound.
+    Bind(&'a Expression),
+    /// Pop the intervals of the arguments of a call and bound the function's
+    /// body with them bound on top of the given scope.
+    Call(&'a Expression, &'a Function, Option<Rc<Scope<'a, Interval, i64>>>),
+    /// Go back to the scope outside of a `let`, `fn` or call body, and
+    /// whether it was a call.
+    Leave(Option<Rc<Scope<'a, Interval, i64>>>, bool),
+}
+
+impl RangeError {
+    /// The same error as one from looking up a name for `eval_with`.
+    fn from_lookup(err: EvalError) -> RangeError {
+        match err {
+            EvalError::UnboundVariable(name) => RangeError::UnboundVariable(name),
+            EvalError::UnknownFunction(name) => RangeError::UnknownFunction(name),
+            EvalError::Arity { name, expected, found } => {
+                RangeError::Arity { name, expected, found }
+            }
+            _ => unreachable!("not an error from looking up a name"),
+        }
+    }
+}
+
+/// Why a binary operation on intervals might fail.
+enum Possible {
+    DivisionByZero,
+    Overflow,
+    NegativeExponent,
+}
+
+impl Possible {
+    fn at(self, e: &Expression) -> RangeError {
+        match self {
+            Possible::DivisionByZero => RangeError::PossibleDivisionByZero(e.clone()),
+            Possible::Overflow => RangeError::PossibleOverflow(e.clone()),
+            Possible::NegativeExponent => RangeError::PossibleNegativeExponent(e.clone()),
+        }
+    }
+}
+
+/// The interval of `x op y` for all `x` in `a` and `y` in `b`.
+fn apply(op: &Operation, a: Interval, b: Interval) -> Result<Interval, Possible> {
+    // Apply a checked operation to every pair of the given values.
+    let corners = |xs: &[i64], ys: &[i64], f: fn(i64, i64) -> Option<i64>| {
+        let mut results = Vec::new();
+        for &x in xs {
+            for &y in ys {
+                results.push(f(x, y).ok_or(Possible::Overflow)?);
+            }
+        }
+        Ok(Interval::hull(results))
+    };
+    let compare = |always: bool, never: bool| Ok(Interval::boolean(!always, !never));
+    match op {
+        Operation::Add => Ok(Interval::new(
+            a.lo.checked_add(b.lo).ok_or(Possible::Overflow)?,
+            a.hi.checked_add(b.hi).ok_or(Possible::Overflow)?,
+        )),
+        Operation::Sub => Ok(Interval::new(
+            a.lo.checked_sub(b.hi).ok_or(Possible::Overflow)?,
+            a.hi.checked_sub(b.lo).ok_or(Possible::Overflow)?,
+        )),
+        // Depending on the signs, any of the four corners can be the
+        // smallest or the largest product.
+        Operation::Mul => corners(&[a.lo, a.hi], &[b.lo, b.hi], i64::checked_mul),
+        // With a divisor of constant sign, truncating division is monotonic
+        // in both operands.
+        Operation::Div if b.contains(0) => Err(Possible::DivisionByZero),
+        Operation::Div => corners(&[a.lo, a.hi], &[b.lo, b.hi], i64::checked_div),
+        Operation::Rem if b.contains(0) => Err(Possible::DivisionByZero),
+        // `i64::MIN % -1` overflows.
+        Operation::Rem if a.lo == i64::MIN && b.contains(-1) => Err(Possible::Overflow),
+        Operation::Rem => {
+            // The remainder has the sign of the dividend, is smaller than
+            // the divisor in magnitude and no larger than the dividend.
+            let largest = (i128::from(b.lo).abs().max(i128::from(b.hi).abs()) - 1) as i64;
+            Ok(Interval::new(
+                if a.lo >= 0 { 0 } else { a.lo.max(-largest) },
+                if a.hi <= 0 { 0 } else { a.hi.min(largest) },
+            ))
+        }
+        Operation::Pow if b.lo < 0 => Err(Possible::NegativeExponent),
+        Operation::Pow => {
+            // For a fixed exponent, the extremes are at the ends of the base
+            // or at -1, 0 or 1; for a fixed base, they are at the smallest
+            // and largest exponents of either parity.
+            let mut bases = vec![a.lo, a.hi];
+            bases.extend([-1, 0, 1].into_iter().filter(|&x| a.contains(x)));
+            let mut exponents = vec![b.lo, b.hi];
+            exponents
+                .extend([b.lo.saturating_add(1), b.hi - 1].into_iter().filter(|&e| b.contains(e)));
+            corners(&bases, &exponents, |x, e| x.try_pow(&e).ok())
+        }
+        Operation::Min => Ok(Interval::new(a.lo.min(b.lo), a.hi.min(b.hi))),
+        Operation::Max => Ok(Interval::new(a.lo.max(b.lo), a.hi.max(b.hi))),
+        Operation::Lt => compare(a.hi < b.lo, a.lo >= b.hi),
+        Operation::Le => compare(a.hi <= b.lo, a.lo > b.hi),
+        Operation::Gt => compare(a.lo > b.hi, a.hi <= b.lo),
+        Operation::Ge => compare(a.lo >= b.hi, a.hi < b.lo),
+        Operation::Eq => compare(a == b && a.lo == a.hi, a.hi < b.lo || b.hi < a.lo),
+        Operation::Ne => compare(a.hi < b.lo || b.hi < a.lo, a == b && a.lo == a.hi),
+        Operation::And | Operation::Or => unreachable!("evaluated lazily"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{eval_with, parse, Env};
+
+    fn domain(vars: &[(&str, i64, i64)]) -> Domain {
+        vars.iter().map(|&(name, lo, hi)| (name.to_string(), Interval::new(lo, hi))).collect()
+    }
+
+    fn range(input: &str, vars: &[(&str, i64, i64)]) -> Result<Interval, RangeError> {
+        eval_interval(&parse(input).unwrap(), &domain(vars))
+    }
+
+    #[test]
+    fn arithmetic() {
+        let vars = [("x", 2, 5), ("y", -3, 4)];
+        assert_eq!(range("x + y", &vars), Ok(Interval::new(-1, 9)));
+        assert_eq!(range("x - y", &vars), Ok(Interval::new(-2, 8)));
+        assert_eq!(range("-y", &vars), Ok(Interval::new(-4, 3)));
+        assert_eq!(range("abs(y)", &vars), Ok(Interval::new(0, 4)));
+        assert_eq!(range("abs(y - 10)", &vars), Ok(Interval::new(6, 13)));
+        assert_eq!(range("min(x, y)", &vars), Ok(Interval::new(-3, 4)));
+        assert_eq!(range("max(x, y)", &vars), Ok(Interval::new(2, 5)));
+        assert_eq!(range("y / x", &vars), Ok(Interval::new(-1, 2)));
+        assert_eq!(range("y % x", &vars), Ok(Interval::new(-3, 4)));
+        assert_eq!(range("x % 3", &vars), Ok(Interval::new(0, 2)));
+        assert_eq!(range("y ^ 2", &vars), Ok(Interval::new(0, 16)));
+        assert_eq!(range("y ^ 3", &vars), Ok(Interval::new(-27, 64)));
+        assert_eq!(range("-2 ^ x", &vars), Ok(Interval::new(-32, 16)));
+        // Each occurrence of a variable is independent, so this isn't [0, 0].
+        assert_eq!(range("x - x", &vars), Ok(Interval::new(-3, 3)));
+        let sum = range("x + 1", &vars).unwrap();
+        assert_eq!((sum.lo(), sum.hi()), (3, 6));
+    }
+
+    #[test]
+    fn multiplication_signs() {
+        let product = |x: (i64, i64), y: (i64, i64)| {
+            range("x * y", &[("x", x.0, x.1), ("y", y.0, y.1)]).unwrap()
+        };
+        assert_eq!(product((2, 3), (4, 5)), Interval::new(8, 15));
+        assert_eq!(product((-3, -2), (4, 5)), Interval::new(-15, -8));
+        assert_eq!(product((-3, -2), (-5, -4)), Interval::new(8, 15));
+        assert_eq!(product((-3, 2), (4, 5)), Interval::new(-15, 10));
+        assert_eq!(product((-3, 2), (-5, -4)), Interval::new(-10, 15));
+        assert_eq!(product((-3, 2), (-5, 4)), Interval::new(-12, 15));
+        assert_eq!(product((-1, 6), (-5, 4)), Interval::new(-30, 24));
+    }
+
+    #[test]
+    fn possible_failures() {
+        let vars = [("x", 0, 1_000_000), ("y", -3, 4)];
+        let at = |input: &str| parse(input).unwrap();
+        assert_eq!(
+            range("x / (y + 1)", &vars),
+            Err(RangeError::PossibleDivisionByZero(at("x / (y + 1)")))
+        );
+        assert_eq!(range("x % y", &vars), Err(RangeError::PossibleDivisionByZero(at("x % y"))));
+        assert_eq!(
+            range("x / (y + 3)", &vars),
+            Err(RangeError::PossibleDivisionByZero(at("x / (y + 3)")))
+        );
+        assert_eq!(range("x / (y + 5)", &vars), Ok(Interval::new(0, 500_000)));
+        assert_eq!(range("2 ^ y", &vars), Err(RangeError::PossibleNegativeExponent(at("2 ^ y"))));
+        assert_eq!(range("z + 1", &vars), Err(RangeError::UnboundVariable(String::from("z"))));
+        // A million cubed fits, to the fourth power it doesn't.
+        assert_eq!(range("x * x * x", &vars), Ok(Interval::new(0, 1_000_000_000_000_000_000)));
+        assert_eq!(
+            range("x * x * x * x", &vars),
+            Err(RangeError::PossibleOverflow(at("x * x * x * x")))
+        );
+        assert_eq!(
+            range("x ^ (y + 3)", &vars),
+            Err(RangeError::PossibleOverflow(at("x ^ (y + 3)")))
+        );
+        let vars = [("x", i64::MIN, 0), ("y", -2, -1)];
+        assert_eq!(range("x % y", &vars), Err(RangeError::PossibleOverflow(at("x % y"))));
+        assert_eq!(range("-x", &vars), Err(RangeError::PossibleOverflow(at("-x"))));
+    }
+
+    #[test]
+    fn conditions() {
+        let vars = [("x", 2, 5), ("y", -3, 4)];
+        assert_eq!(range("x > y", &vars), Ok(Interval::new(0, 1)));
+        assert_eq!(range("x > y - 10", &vars), Ok(Interval::point(1)));
+        assert_eq!(range("x == y + 10", &vars), Ok(Interval::point(0)));
+        assert_eq!(range("!(x < 0)", &vars), Ok(Interval::point(1)));
+        assert_eq!(range("x > 9 && y / 0 > 1", &vars), Ok(Interval::point(0)));
+        assert_eq!(range("x > 1 || y / 0 > 1", &vars), Ok(Interval::point(1)));
+        assert_eq!(range("if x > y then x else y", &vars), Ok(Interval::new(-3, 5)));
+        assert_eq!(range("if x > 1 then x else 1 / 0", &vars), Ok(Interval::new(2, 5)));
+        assert!(range("if x > 3 then x else 1 / 0", &vars).is_err());
+    }
+
+    #[test]
+    fn bindings() {
+        let vars = [("x", 2, 5)];
+        assert_eq!(range("let y = x * 2 in y - x", &vars), Ok(Interval::new(-1, 8)));
+        assert_eq!(
+            range("fn tax(p) = p * 8 / 100 in tax(x * 100)", &vars),
+            Ok(Interval::new(16, 40))
+        );
+        // Bodies are bounded afresh for each call, in the scope they were
+        // defined in.
+        let nested = "let k = 1 in fn f(v) = v + k in let k = 100 in f(x) * f(-x)";
+        assert_eq!(range(nested, &vars), Ok(Interval::new(-24, -3)));
+        let at = |input: &str| parse(input).unwrap();
+        assert_eq!(
+            range("fn inv(v) = 100 / v in inv(x) + inv(x - 3)", &vars),
+            Err(RangeError::PossibleDivisionByZero(at("100 / v")))
+        );
+        let fact = "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1) in fact(x)";
+        assert_eq!(range(fact, &vars), Err(RangeError::Recursion(at("fact(n - 1)"))));
+        assert_eq!(range("f(x)", &vars), Err(RangeError::UnknownFunction(String::from("f"))));
+        assert_eq!(
+            range("fn f(a, b) = a in f(x)", &vars),
+            Err(RangeError::Arity { name: String::from("f"), expected: 2, found: 1 })
+        );
+    }
+
+    #[test]
+    fn deep_expressions() {
+        let mut e = Expression::Var(String::from("x"));
+        for _ in 0..100_000 {
+            e = Expression::Op {
+                op: Operation::Add,
+                left: Box::new(e),
+                right: Box::new(Expression::Value(1)),
+            };
+        }
+        assert_eq!(eval_interval(&e, &domain(&[("x", 0, 5)])), Ok(Interval::new(100_000, 100_005)));
+        // As many nested bindings, each visible to the next.
+        let mut e = Expression::Var(String::from("x"));
+        for _ in 0..100_000 {
+            e = Expression::Let {
+                name: String::from("x"),
+                value: Box::new(Expression::Value(2)),
+                body: Box::new(e),
+            };
+        }
+        assert_eq!(eval_interval(&e, &domain(&[])), Ok(Interval::point(2)));
+    }
+
+    #[test]
+    fn contains_every_value() {
+        let formulas = [
+            "x * y - x / (y + 4) % 3",
+            "x ^ 3 - abs(y) ^ (x + 3)",
+            "min(x * -y, max(y - x, x % (y + 3)))",
+            "if x > y && x != 0 then x * x else -(y * x)",
+            "(x < 2 || y >= 1) == !(x * y > 3)",
+            "fn sq(v) = v * v in let d = x - y in sq(d) - sq(x) + d",
+        ];
+        let vars = [("x", -3, 4), ("y", -2, 6)];
+        for input in formulas {
+            let e = parse(input).unwrap();
+            let interval = eval_interval(&e, &domain(&vars)).unwrap();
+            for x in -3..=4 {
+                for y in -2..=6 {
+                    let env = Env::from([(String::from("x"), x), (String::from("y"), y)]);
+                    match eval_with(&e, &env) {
+                        Ok(value) => assert!(interval.contains(value), "{input} at {x}, {y}"),
+                        Err(err) => panic!("{input} at {x}, {y}: {err}"),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1344 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use thiserror::Error;
+
+#[cfg(test)]
+mod arbitrary;
+mod codec;
+mod dag;
+mod derive;
+mod interval;
+mod number;
+mod parser;
+mod simplify;
+mod trace;
+mod typecheck;
+mod vm;
+pub use codec::{
+    from_bytes, from_json, to_bytes, to_json, DecodeError, MAX_DEPTH as MAX_DECODE_DEPTH,
+};
+pub use dag::{Dag, Evaluator, Node, NodeId};
+pub use derive::{derive, DeriveError};
+pub use interval::{eval_interval, Domain, Interval, RangeError};
+pub use number::{Number, Rational};
+pub use parser::{parse, parse_function, Expected, ParseError, KEYWORDS};
+pub use simplify::simplify;
+pub use trace::{trace, Explanation, Step, Trace};
+pub use typecheck::{typecheck, typecheck_with, Type, TypeError};
+pub use vm::{compile, Instruction, Program};
+
+/// An operation to perform on two subexpressions.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    /// Remainder of truncating division, like Rust's `%`.
+    Rem,
+    /// Exponentiation; which exponents are allowed depends on the backend.
+    Pow,
+    Min,
+    Max,
+    /// Comparisons of two integers, or equality of two booleans.
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    /// Logical operations; the right operand is only evaluated if the left
+    /// one does not decide the result.
+    And,
+    Or,
+}
+
+/// An operation to perform on a single subexpression.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnaryOperation {
+    Neg,
+    Abs,
+    /// Logical negation.
+    Not,
+}
+
+/// An expression, in tree form, over numbers of type `N`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression<N = i64> {
+    /// An operation on two subexpressions.
+    Op { op: Operation, left: Box<Expression<N>>, right: Box<Expression<N>> },
+
+    /// An operation on one subexpression.
+    Unary { op: UnaryOperation, operand: Box<Expression<N>> },
+
+    /// A literal value
+    Value(N),
+
+    /// A literal boolean.
+    Bool(bool),
+
+    /// `then` if `cond` holds, `otherwise` if it does not. Only the chosen
+    /// branch is evaluated.
+    If { cond: Box<Expression<N>>, then: Box<Expression<N>>, otherwise: Box<Expression<N>> },
+
+    /// A named variable, looked up in the `Env` at evaluation time.
+    Var(String),
+
+    /// `body` with `name` bound to the value of `value`.
+    Let { name: String, value: Box<Expression<N>>, body: Box<Expression<N>> },
+
+    /// `body` with `function` in scope.
+    Define { function: Box<Function<N>>, body: Box<Expression<N>> },
+
+    /// A call of a function defined by an enclosing `Define`.
+    Call { name: String, args: Vec<Expression<N>> },
+}
+
+/// A user-defined function, such as `fn tax(x) = x * 8 / 100`.
+///
+/// The body sees the parameters, the function itself and whatever was in
+/// scope where it was defined, but not the variables of its callers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function<N = i64> {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expression<N>,
+}
+
+/// How deeply calls may nest before evaluation gives up, so that runaway
+/// recursion is reported instead of running forever.
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// Values of the variables an expression is evaluated against.
+///
+/// Booleans are evaluated to `1` and `0`; `typecheck` makes sure they are
+/// never mixed up with numbers.
+pub type Env<N = i64> = HashMap<String, N>;
+
+/// Why applying an operation to two values failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticError {
+    DivisionByZero,
+    Overflow,
+    NegativeExponent,
+    NonIntegerExponent,
+}
+
+impl ArithmeticError {
+    /// Attach the sub-expression whose evaluation failed.
+    fn at<N>(self, expr: Expression<N>) -> EvalError<N> {
+        match self {
+            ArithmeticError::DivisionByZero => EvalError::DivisionByZero(expr),
+            ArithmeticError::Overflow => EvalError::Overflow(expr),
+            ArithmeticError::NegativeExponent => EvalError::NegativeExponent(expr),
+            ArithmeticError::NonIntegerExponent => EvalError::NonIntegerExponent(expr),
+        }
+    }
+}
+
+/// An error from evaluating an expression, carrying the offending
+/// sub-expression.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EvalError<N = i64> {
+    #[error("division by zero in `{0}`")]
+    DivisionByZero(Expression<N>),
+    #[error("arithmetic overflow in `{0}`")]
+    Overflow(Expression<N>),
+    #[error("negative exponent in `{0}`")]
+    NegativeExponent(Expression<N>),
+    #[error("non-integer exponent in `{0}`")]
+    NonIntegerExponent(Expression<N>),
+    #[error("unbound variable `{0}`")]
+    UnboundVariable(String),
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+    #[error("`{name}` takes {expected} arguments but was given {found}")]
+    Arity { name: String, expected: usize, found: usize },
+    #[error("calls nested more than {MAX_CALL_DEPTH} deep in `{0}`")]
+    RecursionLimit(Expression<N>),
+}
+
+impl Operation {
+    /// Apply the operation to two values using checked arithmetic.
+    fn apply<N: Number>(&self, left: &N, right: &N) -> Result<N, ArithmeticError> {
+        match self {
+            Operation::Add => left.try_add(right),
+            Operation::Sub => left.try_sub(right),
+            Operation::Mul => left.try_mul(right),
+            Operation::Div => left.try_div(right),
+            Operation::Rem => left.try_rem(right),
+            Operation::Pow => left.try_pow(right),
+            Operation::Min => Ok(if right < left { right.clone() } else { left.clone() }),
+            Operation::Max => Ok(if right > left { right.clone() } else { left.clone() }),
+            Operation::Lt => Ok(N::from_bool(left < right)),
+            Operation::Le => Ok(N::from_bool(left <= right)),
+            Operation::Eq => Ok(N::from_bool(left == right)),
+            Operation::Ne => Ok(N::from_bool(left != right)),
+            Operation::Gt => Ok(N::from_bool(left > right)),
+            Operation::Ge => Ok(N::from_bool(left >= right)),
+            Operation::And => Ok(N::from_bool(left.is_true() && right.is_true())),
+            Operation::Or => Ok(N::from_bool(left.is_true() || right.is_true())),
+        }
+    }
+
+    /// The result of `left op` alone, if the left operand decides it.
+    fn short_circuit<N: Number>(&self, left: &N) -> Option<N> {
+        match self {
+            Operation::And if !left.is_true() => Some(N::from_bool(false)),
+            Operation::Or if left.is_true() => Some(N::from_bool(true)),
+            _ => None,
+        }
+    }
+
+    /// Whether the operation produces a boolean.
+    fn is_boolean(&self) -> bool {
+        matches!(
+            self,
+            Operation::Lt
+                | Operation::Le
+                | Operation::Eq
+                | Operation::Ne
+                | Operation::Gt
+                | Operation::Ge
+                | Operation::And
+                | Operation::Or
+        )
+    }
+
+    /// Binding strength of the operator in infix notation; higher binds
+    /// tighter. `min` and `max` are written like function calls, so they
+    /// never need parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Or => 1,
+            Operation::And => 2,
+            Operation::Lt
+            | Operation::Le
+            | Operation::Eq
+            | Operation::Ne
+            | Operation::Gt
+            | Operation::Ge => 3,
+            Operation::Add | Operation::Sub => 4,
+            Operation::Mul | Operation::Div | Operation::Rem => 5,
+            Operation::Pow => 6,
+            Operation::Min | Operation::Max => u8::MAX,
+        }
+    }
+
+    /// Whether `a op b op c` means `a op (b op c)`.
+    fn is_right_associative(&self) -> bool {
+        matches!(self, Operation::Pow)
+    }
+
+    /// The operator's symbol in infix notation.
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operation::Add => "+",
+            Operation::Sub => "-",
+            Operation::Mul => "*",
+            Operation::Div => "/",
+            Operation::Rem => "%",
+            Operation::Pow => "^",
+            Operation::Min => "min",
+            Operation::Max => "max",
+            Operation::Lt => "<",
+            Operation::Le => "<=",
+            Operation::Eq => "==",
+            Operation::Ne => "!=",
+            Operation::Gt => ">",
+            Operation::Ge => ">=",
+            Operation::And => "&&",
+            Operation::Or => "||",
+        }
+    }
+}
+
+/// Binding strength of prefix `-` and `!`: tighter than any binary operator,
+/// so `-x ^ 2` is `(-x) ^ 2`, just like the literal `-2 ^ 2`.
+const NEG_PRECEDENCE: u8 = 7;
+
+impl UnaryOperation {
+    /// Apply the operation to a value using checked arithmetic.
+    fn apply<N: Number>(&self, value: &N) -> Result<N, ArithmeticError> {
+        match self {
+            UnaryOperation::Neg => value.try_neg(),
+            UnaryOperation::Abs => value.try_abs(),
+            UnaryOperation::Not => Ok(N::from_bool(!value.is_true())),
+        }
+    }
+
+    /// The operator's name in S-expressions.
+    fn name(&self) -> &'static str {
+        match self {
+            UnaryOperation::Neg => "neg",
+            UnaryOperation::Abs => "abs",
+            UnaryOperation::Not => "not",
+        }
+    }
+}
+
+impl<N> Expression<N> {
+    /// Precedence of the expression as an operand; atoms never need
+    /// parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Op { op, .. } => op.precedence(),
+            Expression::Unary { op: UnaryOperation::Neg | UnaryOperation::Not, .. } => {
+                NEG_PRECEDENCE
+            }
+            Expression::Unary { op: UnaryOperation::Abs, .. } => u8::MAX,
+            // `else` and `in` extend as far as possible, so these are always
+            // parenthesized as operands.
+            Expression::If { .. } | Expression::Let { .. } | Expression::Define { .. } => 0,
+            Expression::Value(_)
+            | Expression::Bool(_)
+            | Expression::Var(_)
+            | Expression::Call { .. } => u8::MAX,
+        }
+    }
+
+    /// Whether the expression evaluates to a boolean, assuming it is well
+    /// typed. Variables and calls are taken to be numbers.
+    fn is_boolean(&self) -> bool {
+        let mut e = self;
+        loop {
+            match e {
+                Expression::Op { op, .. } => return op.is_boolean(),
+                Expression::Unary { op, .. } => return *op == UnaryOperation::Not,
+                Expression::If { then: body, .. }
+                | Expression::Let { body, .. }
+                | Expression::Define { body, .. } => e = body,
+                Expression::Bool(_) => return true,
+                Expression::Value(_) | Expression::Var(_) | Expression::Call { .. } => {
+                    return false
+                }
+            }
+        }
+    }
+
+    /// Move the expression out, leaving a placeholder that owns no heap
+    /// memory. `Expression` implements `Drop`, so this is how its children
+    /// are taken apart.
+    fn take(&mut self) -> Expression<N> {
+        std::mem::replace(self, Expression::Var(String::new()))
+    }
+
+    fn detach_children(&mut self, pending: &mut Vec<Expression<N>>) {
+        match self {
+            Expression::Op { left, right, .. } => {
+                pending.push(left.take());
+                pending.push(right.take());
+            }
+            Expression::Unary { operand, .. } => pending.push(operand.take()),
+            Expression::If { cond, then, otherwise } => {
+                pending.push(cond.take());
+                pending.push(then.take());
+                pending.push(otherwise.take());
+            }
+            Expression::Let { value, body, .. } => {
+                pending.push(value.take());
+                pending.push(body.take());
+            }
+            Expression::Define { function, body } => {
+                pending.push(function.body.take());
+                pending.push(body.take());
+            }
+            Expression::Call { args, .. } => pending.append(args),
+            Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => {}
+        }
+    }
+}
+
+/// The compiler-generated drop glue recurses once per level, which overflows
+/// the stack for very deep trees. Instead, detach the children onto a heap
+/// allocated stack so that every node is dropped with leaves for children.
+impl<N> Drop for Expression<N> {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.detach_children(&mut pending);
+        while let Some(mut e) = pending.pop() {
+            e.detach_children(&mut pending);
+        }
+    }
+}
+
+/// Whether `literal` reads back as a single literal, i.e. is an integer.
+fn is_integer(literal: &str) -> bool {
+    let digits = literal.strip_prefix('-').unwrap_or(literal);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Infix notation with only the parentheses needed to parse back to the same
+/// tree, e.g. `a - (b - c)` or `a * b + c`. The alternate form (`{:#}`)
+/// prints an S-expression such as `(+ (* a b) c)` instead.
+///
+/// Only integer trees read back exactly, as the parser only has integer
+/// literals. Other literals are parenthesized to keep their grouping, so the
+/// rational `x * (7/2)` reads back as a division with the same value, but a
+/// float such as `(0.5)` does not read back at all.
+impl<N: Number> fmt::Display for Expression<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_pieces(f, Piece::Expr(self))
+    }
+}
+
+/// `fn name(a, b) = body`. The alternate form is the start of the
+/// S-expression of a `Define`, `(fn name (a b) body`, which it closes after
+/// the scope of the definition.
+impl<N: Number> fmt::Display for Function<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_pieces(f, Piece::Function(self))
+    }
+}
+
+/// A part of the text of an expression.
+enum Piece<'a, N> {
+    Expr(&'a Expression<N>),
+    Function(&'a Function<N>),
+    Text(&'a str),
+}
+
+/// `e`, in parentheses if `parens`.
+fn operand<'a, N>(pieces: &mut Vec<Piece<'a, N>>, e: &'a Expression<N>, parens: bool) {
+    if parens {
+        pieces.extend([Piece::Text("("), Piece::Expr(e), Piece::Text(")")]);
+    } else {
+        pieces.push(Piece::Expr(e));
+    }
+}
+
+/// Write `first` in the form `f` asks for, using an explicit stack rather
+/// than recursion so that arbitrarily deep trees can be printed.
+fn write_pieces<N: Number>(f: &mut fmt::Formatter, first: Piece<'_, N>) -> fmt::Result {
+    let alternate = f.alternate();
+    let mut stack = vec![first];
+    // The parts of the current piece, in the order they are written.
+    let mut parts = Vec::new();
+    while let Some(piece) = stack.pop() {
+        match piece {
+            Piece::Text(text) => f.write_str(text)?,
+            Piece::Function(Function { name, params, body }) => {
+                let (open, separator, close) =
+                    if alternate { ("(fn ", " ", ") ") } else { ("fn ", ", ", ") = ") };
+                parts.extend([Piece::Text(open), Piece::Text(name)]);
+                parts.push(Piece::Text(if alternate { " (" } else { "(" }));
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        parts.push(Piece::Text(separator));
+                    }
+                    parts.push(Piece::Text(param));
+                }
+                parts.extend([Piece::Text(close), Piece::Expr(body)]);
+            }
+            Piece::Expr(e) => match e {
+                Expression::Value(v) if alternate => write!(f, "{v}")?,
+                Expression::Value(v) => {
+                    let literal = v.to_string();
+                    if is_integer(&literal) {
+                        f.write_str(&literal)?;
+                    } else {
+                        write!(f, "({literal})")?;
+                    }
+                }
+                Expression::Bool(b) => write!(f, "{b}")?,
+                Expression::Var(name) => f.write_str(name)?,
+                Expression::If { cond, then, otherwise } if alternate => parts.extend([
+                    Piece::Text("(if "),
+                    Piece::Expr(cond),
+                    Piece::Text(" "),
+                    Piece::Expr(then),
+                    Piece::Text(" "),
+                    Piece::Expr(otherwise),
+                    Piece::Text(")"),
+                ]),
+                Expression::If { cond, then, otherwise } => parts.extend([
+                    Piece::Text("if "),
+                    Piece::Expr(cond),
+                    Piece::Text(" then "),
+                    Piece::Expr(then),
+                    Piece::Text(" else "),
+                    Piece::Expr(otherwise),
+                ]),
+                Expression::Let { name, value, body } if alternate => parts.extend([
+                    Piece::Text("(let "),
+                    Piece::Text(name),
+                    Piece::Text(" "),
+                    Piece::Expr(value),
+                    Piece::Text(" "),
+                    Piece::Expr(body),
+                    Piece::Text(")"),
+                ]),
+                Expression::Let { name, value, body } => parts.extend([
+                    Piece::Text("let "),
+                    Piece::Text(name),
+                    Piece::Text(" = "),
+                    Piece::Expr(value),
+                    Piece::Text(" in "),
+                    Piece::Expr(body),
+                ]),
+                Expression::Define { function, body } if alternate => parts.extend([
+                    Piece::Function(function),
+                    Piece::Text(" "),
+                    Piece::Expr(body),
+                    Piece::Text(")"),
+                ]),
+                Expression::Define { function, body } => parts.extend([
+                    Piece::Function(function),
+                    Piece::Text(" in "),
+                    Piece::Expr(body),
+                ]),
+                Expression::Call { name, args } => {
+                    let separator = if alternate { " " } else { ", " };
+                    if alternate {
+                        parts.extend([Piece::Text("("), Piece::Text(name)]);
+                    } else {
+                        parts.extend([Piece::Text(name), Piece::Text("(")]);
+                    }
+                    for (i, arg) in args.iter().enumerate() {
+                        if i > 0 || alternate {
+                            parts.push(Piece::Text(separator));
+                        }
+                        parts.push(Piece::Expr(arg));
+                    }
+                    parts.push(Piece::Text(")"));
+                }
+                Expression::Op { op, left, right } if alternate => parts.extend([
+                    Piece::Text("("),
+                    Piece::Text(op.symbol()),
+                    Piece::Text(" "),
+                    Piece::Expr(left),
+                    Piece::Text(" "),
+                    Piece::Expr(right),
+                    Piece::Text(")"),
+                ]),
+                Expression::Op { op: op @ (Operation::Min | Operation::Max), left, right } => {
+                    parts.extend([
+                        Piece::Text(op.symbol()),
+                        Piece::Text("("),
+                        Piece::Expr(left),
+                        Piece::Text(", "),
+                        Piece::Expr(right),
+                        Piece::Text(")"),
+                    ]);
+                }
+                Expression::Op { op, left, right } => {
+                    // An operand of equal precedence needs parentheses on the
+                    // side the operator does not associate towards.
+                    let prec = op.precedence();
+                    let (left_parens, right_parens) = if op.is_right_associative() {
+                        (left.precedence() <= prec, right.precedence() < prec)
+                    } else {
+                        (left.precedence() < prec, right.precedence() <= prec)
+                    };
+                    operand(&mut parts, left, left_parens);
+                    parts.extend([Piece::Text(" "), Piece::Text(op.symbol()), Piece::Text(" ")]);
+                    operand(&mut parts, right, right_parens);
+                }
+                Expression::Unary { op, operand } if alternate => parts.extend([
+                    Piece::Text("("),
+                    Piece::Text(op.name()),
+                    Piece::Text(" "),
+                    Piece::Expr(operand),
+                    Piece::Text(")"),
+                ]),
+                Expression::Unary { op: UnaryOperation::Abs, operand } => {
+                    parts.extend([Piece::Text("abs("), Piece::Expr(operand), Piece::Text(")")])
+                }
+                Expression::Unary { op: UnaryOperation::Not, operand: e } => {
+                    parts.push(Piece::Text("!"));
+                    operand(&mut parts, e, e.precedence() < NEG_PRECEDENCE);
+                }
+                Expression::Unary { op: UnaryOperation::Neg, operand: e } => {
+                    // `-4` would read back as a negative literal, not a
+                    // negation. Other literals are parenthesized already.
+                    let parens = e.precedence() < NEG_PRECEDENCE
+                        || matches!(&**e, Expression::Value(v) if is_integer(&v.to_string()));
+                    parts.push(Piece::Text("-"));
+                    operand(&mut parts, e, parens);
+                }
+            },
+        }
+        stack.extend(parts.drain(..).rev());
+    }
+    Ok(())
+}
+
+impl Expression<i64> {
+    /// Convert the literals of an integer expression to another backend, so
+    /// the same formula can be evaluated exactly or approximately.
+    pub fn cast<M: Number>(&self) -> Expression<M> {
+        match self {
+            Expression::Op { op, left, right } => Expression::Op {
+                op: op.clone(),
+                left: Box::new(left.cast()),
+                right: Box::new(right.cast()),
+            },
+            Expression::Unary { op, operand } => {
+                Expression::Unary { op: op.clone(), operand: Box::new(operand.cast()) }
+            }
+            Expression::Value(v) => Expression::Value(M::from_i64(*v)),
+            Expression::Bool(b) => Expression::Bool(*b),
+            Expression::Var(name) => Expression::Var(name.clone()),
+            Expression::If { cond, then, otherwise } => Expression::If {
+                cond: Box::new(cond.cast()),
+                then: Box::new(then.cast()),
+                otherwise: Box::new(otherwise.cast()),
+            },
+            Expression::Let { name, value, body } => Expression::Let {
+                name: name.clone(),
+                value: Box::new(value.cast()),
+                body: Box::new(body.cast()),
+            },
+            Expression::Define { function, body } => Expression::Define {
+                function: Box::new(Function {
+                    name: function.name.clone(),
+                    params: function.params.clone(),
+                    body: function.body.cast(),
+                }),
+                body: Box::new(body.cast()),
+            },
+            Expression::Call { name, args } => Expression::Call {
+                name: name.clone(),
+                args: args.iter().map(|a| a.cast()).collect(),
+            },
+        }
+    }
+}
+
+/// Evaluate an expression that has no free variables.
+pub fn eval<N: Number>(e: &Expression<N>) -> Result<N, EvalError<N>> {
+    eval_with(e, &Env::new())
+}
+
+/// The innermost of the names bound by `let`, `fn` and calls, linked to the
+/// ones that were visible where it was bound. Scopes are shared, since a
+/// function keeps the scope it was defined in alive for its calls.
+///
+/// Variables are bound to `V`s, which are values of type `N` unless the
+/// expression is evaluated over something else, like intervals.
+struct Scope<'a, V, N = V> {
+    name: &'a str,
+    binding: Binding<'a, V, N>,
+    parent: Option<Rc<Scope<'a, V, N>>>,
+}
+
+/// Long chains of scopes would be dropped recursively, so unlink the
+/// parents that nothing else refers to one by one.
+impl<V, N> Drop for Scope<'_, V, N> {
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(scope) = parent {
+            parent = match Rc::try_unwrap(scope) {
+                Ok(mut scope) => scope.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+/// Values and functions have separate names.
+enum Binding<'a, V, N = V> {
+    Value(V),
+    Function(&'a Function<N>),
+}
+
+/// Bind `name` on top of `parent`.
+fn bind<'a, V, N>(
+    parent: &Option<Rc<Scope<'a, V, N>>>,
+    name: &'a str,
+    binding: Binding<'a, V, N>,
+) -> Option<Rc<Scope<'a, V, N>>> {
+    Some(Rc::new(Scope { name, binding, parent: parent.clone() }))
+}
+
+/// The value of the variable `name`, from the innermost binding or `env`.
+fn lookup_value<V: Clone, N>(
+    scope: &Option<Rc<Scope<'_, V, N>>>,
+    env: &Env<V>,
+    name: &str,
+) -> Result<V, EvalError<N>> {
+    let mut scope = scope;
+    while let Some(s) = scope {
+        if let (true, Binding::Value(value)) = (s.name == name, &s.binding) {
+            return Ok(value.clone());
+        }
+        scope = &s.parent;
+    }
+    env.get(name).cloned().ok_or_else(|| EvalError::UnboundVariable(name.to_string()))
+}
+
+/// The function `name`, after checking that it takes `arity` arguments,
+/// together with the scope that binds it, which its body is evaluated in.
+#[allow(clippy::type_complexity)]
+fn lookup_function<'a, V, N>(
+    scope: &Option<Rc<Scope<'a, V, N>>>,
+    name: &str,
+    arity: usize,
+) -> Result<(&'a Function<N>, Option<Rc<Scope<'a, V, N>>>), EvalError<N>> {
+    let mut scope = scope;
+    while let Some(s) = scope {
+        if let (true, Binding::Function(function)) = (s.name == name, &s.binding) {
+            if function.params.len() != arity {
+                return Err(EvalError::Arity {
+                    name: name.to_string(),
+                    expected: function.params.len(),
+                    found: arity,
+                });
+            }
+            return Ok((function, scope.clone()));
+        }
+        scope = &s.parent;
+    }
+    Err(EvalError::UnknownFunction(name.to_string()))
+}
+
+/// Told about the steps `evaluate` takes, e.g. to record a trace.
+///
+/// Every expression evaluated is entered, and left with its value unless
+/// evaluation fails; the expressions evaluated for it nest in between.
+trait Observer<'a, N> {
+    /// Evaluating `e` starts.
+    fn enter(&mut self, _e: &'a Expression<N>) {}
+
+    /// The innermost expression entered is applied to `operands`: both
+    /// operands of a binary operation, the operand of a unary one, just the
+    /// condition or left operand of a branch that did not need the rest, the
+    /// value bound by a `let` or the arguments of a call.
+    fn apply(&mut self, _operands: &[N]) {}
+
+    /// The innermost expression entered has the value `value`.
+    fn leave(&mut self, _value: &N) {}
+}
+
+/// Follows along without doing anything, for plain evaluation.
+impl<N> Observer<'_, N> for () {}
+
+/// A pending step of `evaluate`.
+enum Task<'a, N> {
+    /// Evaluate the expression and push its value.
+    Eval(&'a Expression<N>),
+    /// Pop the values of the expression's operands and push its value.
+    Apply(&'a Expression<N>),
+    /// Pop the value of the condition of an `If`, or of the left operand of
+    /// `&&` or `||`, and decide what to evaluate next.
+    Branch(&'a Expression<N>),
+    /// Pop the value of a `Let` and evaluate its body with it bound.
+    Bind(&'a Expression<N>),
+    /// Pop the arguments of a call and evaluate the function's body with
+    /// them bound on top of the given scope.
+    Call(&'a Expression<N>, &'a Function<N>, Option<Rc<Scope<'a, N>>>),
+    /// Go back to the scope outside of a `let`, `fn` or call body, and
+    /// whether it was a call.
+    Leave(Option<Rc<Scope<'a, N>>>, bool),
+    /// The value of the branch or body on top is that of the `If`, `Let`,
+    /// `Define` or call it belongs to.
+    Finish,
+}
+
+/// Evaluate an expression, taking variable values from `env`.
+///
+/// Operands are evaluated left to right using an explicit stack rather than
+/// recursion, so arbitrarily deep trees can be evaluated. Names bound by
+/// `let`, `fn` and calls shadow those in `env` and are resolved lexically.
+/// Calls may nest up to `MAX_CALL_DEPTH` deep.
+pub fn eval_with<N: Number>(e: &Expression<N>, env: &Env<N>) -> Result<N, EvalError<N>> {
+    evaluate(e, env, &mut ())
+}
+
+/// `eval_with`, telling `observer` about every step.
+fn evaluate<'a, N: Number>(
+    e: &'a Expression<N>,
+    env: &Env<N>,
+    observer: &mut impl Observer<'a, N>,
+) -> Result<N, EvalError<N>> {
+    let mut tasks = vec![Task::Eval(e)];
+    let mut values = Vec::new();
+    let mut scope = None;
+    let mut calls = 0;
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Eval(e) => {
+                observer.enter(e);
+                let value = match e {
+                    Expression::Value(v) => v.clone(),
+                    Expression::Bool(b) => N::from_bool(*b),
+                    Expression::Var(name) => lookup_value(&scope, env, name)?,
+                    Expression::Let { value, .. } => {
+                        tasks.push(Task::Bind(e));
+                        tasks.push(Task::Eval(value));
+                        continue;
+                    }
+                    Expression::Define { function, body } => {
+                        let inner = bind(&scope, &function.name, Binding::Function(function));
+                        tasks.push(Task::Finish);
+                        tasks.push(Task::Leave(std::mem::replace(&mut scope, inner), false));
+                        tasks.push(Task::Eval(body));
+                        continue;
+                    }
+                    Expression::Call { name, args } => {
+                        let (function, defined) = lookup_function(&scope, name, args.len())?;
+                        tasks.push(Task::Call(e, function, defined));
+                        tasks.extend(args.iter().rev().map(Task::Eval));
+                        continue;
+                    }
+                    Expression::Op { op: Operation::And | Operation::Or, left, .. } => {
+                        tasks.push(Task::Branch(e));
+                        tasks.push(Task::Eval(left));
+                        continue;
+                    }
+                    Expression::If { cond, .. } => {
+                        tasks.push(Task::Branch(e));
+                        tasks.push(Task::Eval(cond));
+                        continue;
+                    }
+                    Expression::Op { left, right, .. } => {
+                        tasks.push(Task::Apply(e));
+                        tasks.push(Task::Eval(right));
+                        tasks.push(Task::Eval(left));
+                        continue;
+                    }
+                    Expression::Unary { operand, .. } => {
+                        tasks.push(Task::Apply(e));
+                        tasks.push(Task::Eval(operand));
+                        continue;
+                    }
+                };
+                observer.leave(&value);
+                values.push(value);
+            }
+            Task::Apply(e @ Expression::Op { op, .. }) => {
+                let right = values.pop().expect("missing right operand");
+                let left = values.pop().expect("missing left operand");
+                let operands = [left, right];
+                observer.apply(&operands);
+                let [left, right] = &operands;
+                let value = op.apply(left, right).map_err(|err| err.at(e.clone()))?;
+                observer.leave(&value);
+                values.push(value);
+            }
+            Task::Apply(e @ Expression::Unary { op, .. }) => {
+                let operand = values.pop().expect("missing operand");
+                observer.apply(std::slice::from_ref(&operand));
+                let value = op.apply(&operand).map_err(|err| err.at(e.clone()))?;
+                observer.leave(&value);
+                values.push(value);
+            }
+            Task::Branch(Expression::If { then, otherwise, .. }) => {
+                let cond = values.pop().expect("missing condition");
+                observer.apply(std::slice::from_ref(&cond));
+                tasks.push(Task::Finish);
+                tasks.push(Task::Eval(if cond.is_true() { then } else { otherwise }));
+            }
+            Task::Branch(e @ Expression::Op { op, right, .. }) => {
+                let left = values.last().expect("missing left operand");
+                if let Some(value) = op.short_circuit(left) {
+                    observer.apply(std::slice::from_ref(left));
+                    observer.leave(&value);
+                    *values.last_mut().expect("missing left operand") = value;
+                } else {
+                    tasks.push(Task::Apply(e));
+                    tasks.push(Task::Eval(right));
+                }
+            }
+            Task::Bind(Expression::Let { name, body, .. }) => {
+                let value = values.pop().expect("missing bound value");
+                observer.apply(std::slice::from_ref(&value));
+                let inner = bind(&scope, name, Binding::Value(value));
+                tasks.push(Task::Finish);
+                tasks.push(Task::Leave(std::mem::replace(&mut scope, inner), false));
+                tasks.push(Task::Eval(body));
+            }
+            Task::Call(e, function, defined) => {
+                let args = values.split_off(values.len() - function.params.len());
+                observer.apply(&args);
+                if calls == MAX_CALL_DEPTH {
+                    return Err(EvalError::RecursionLimit(e.clone()));
+                }
+                calls += 1;
+                let mut inner = defined;
+                for (param, arg) in function.params.iter().zip(args) {
+                    inner = bind(&inner, param, Binding::Value(arg));
+                }
+                tasks.push(Task::Finish);
+                tasks.push(Task::Leave(std::mem::replace(&mut scope, inner), true));
+                tasks.push(Task::Eval(&function.body));
+            }
+            Task::Leave(outer, call) => {
+                scope = outer;
+                calls -= usize::from(call);
+            }
+            Task::Finish => observer.leave(values.last().expect("missing value")),
+            Task::Apply(
+                Expression::Value(_)
+                | Expression::Bool(_)
+                | Expression::Var(_)
+                | Expression::If { .. }
+                | Expression::Let { .. }
+                | Expression::Define { .. }
+                | Expression::Call { .. },
+            )
+            | Task::Branch(_)
+            | Task::Bind(_) => unreachable!("no such step for this expression"),
+        }
+    }
+    Ok(values.pop().expect("no value left"))
+}
+
+#[test]
+fn test_value() {
+    assert_eq!(eval(&Expression::Value(19)), Ok(19));
+}
+
+#[test]
+fn test_sum() {
+    assert_eq!(
+        eval(&Expression::Op {
+            op: Operation::Add,
+            left: Box::new(Expression::Value(10)),
+            right: Box::new(Expression::Value(20)),
+        }),
+        Ok(30)
+    );
+}
+
+#[test]
+fn test_recursion() {
+    let term1 = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(Expression::Value(10)),
+        right: Box::new(Expression::Value(9)),
+    };
+    let term2 = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(Expression::Op {
+            op: Operation::Sub,
+            left: Box::new(Expression::Value(3)),
+            right: Box::new(Expression::Value(4)),
+        }),
+        right: Box::new(Expression::Value(5)),
+    };
+    assert_eq!(
+        eval(&Expression::Op { op: Operation::Add, left: Box::new(term1), right: Box::new(term2) }),
+        Ok(85)
+    );
+}
+
+#[test]
+fn test_error() {
+    assert_eq!(
+        eval(&Expression::Op {
+            op: Operation::Div,
+            left: Box::new(Expression::Value(99)),
+            right: Box::new(Expression::Value(0)),
+        }),
+        Err(EvalError::DivisionByZero(Expression::Op {
+            op: Operation::Div,
+            left: Box::new(Expression::Value(99)),
+            right: Box::new(Expression::Value(0)),
+        }))
+    );
+}
+
+#[test]
+fn test_overflow() {
+    let add = Expression::Op {
+        op: Operation::Add,
+        left: Box::new(Expression::Value(i64::MAX)),
+        right: Box::new(Expression::Value(1)),
+    };
+    assert_eq!(eval(&add), Err(EvalError::Overflow(add)));
+
+    let sub = Expression::Op {
+        op: Operation::Sub,
+        left: Box::new(Expression::Value(i64::MIN)),
+        right: Box::new(Expression::Value(1)),
+    };
+    assert_eq!(eval(&sub), Err(EvalError::Overflow(sub)));
+
+    let mul = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(Expression::Value(i64::MAX)),
+        right: Box::new(Expression::Value(2)),
+    };
+    // The error points at the overflowing node, not at the root.
+    assert_eq!(
+        eval(&Expression::Op {
+            op: Operation::Add,
+            left: Box::new(Expression::Value(1)),
+            right: Box::new(mul.clone()),
+        }),
+        Err(EvalError::Overflow(mul))
+    );
+
+    let div = Expression::Op {
+        op: Operation::Div,
+        left: Box::new(Expression::Value(i64::MIN)),
+        right: Box::new(Expression::Value(-1)),
+    };
+    assert_eq!(eval(&div), Err(EvalError::Overflow(div)));
+}
+
+#[test]
+fn test_rem() {
+    let rem = Expression::Op {
+        op: Operation::Rem,
+        left: Box::new(Expression::Value(-17)),
+        right: Box::new(Expression::Op {
+            op: Operation::Sub,
+            left: Box::new(Expression::Value(9)),
+            right: Box::new(Expression::Value(4)),
+        }),
+    };
+    assert_eq!(eval(&rem), Ok(-2));
+
+    let by_zero = Expression::Op {
+        op: Operation::Rem,
+        left: Box::new(Expression::Value(7)),
+        right: Box::new(Expression::Value(0)),
+    };
+    assert_eq!(eval(&by_zero), Err(EvalError::DivisionByZero(by_zero.clone())));
+
+    let overflow = Expression::Op {
+        op: Operation::Rem,
+        left: Box::new(Expression::Value(i64::MIN)),
+        right: Box::new(Expression::Value(-1)),
+    };
+    assert_eq!(eval(&overflow), Err(EvalError::Overflow(overflow.clone())));
+}
+
+#[test]
+fn test_pow() {
+    let pow = |base, exponent| Expression::Op {
+        op: Operation::Pow,
+        left: Box::new(Expression::Value(base)),
+        right: Box::new(Expression::Value(exponent)),
+    };
+    assert_eq!(eval(&pow(3, 4)), Ok(81));
+    assert_eq!(eval(&pow(-2, 3)), Ok(-8));
+    assert_eq!(eval(&pow(5, 0)), Ok(1));
+    assert_eq!(eval(&pow(-1, i64::MAX)), Ok(-1));
+    assert_eq!(eval(&pow(1, i64::MAX)), Ok(1));
+    assert_eq!(eval(&pow(2, 64)), Err(EvalError::Overflow(pow(2, 64))));
+    assert_eq!(eval(&pow(2, i64::MAX)), Err(EvalError::Overflow(pow(2, i64::MAX))));
+    assert_eq!(eval(&pow(2, -1)), Err(EvalError::NegativeExponent(pow(2, -1))));
+
+    // 2 ^ 3 ^ 2 is 2 ^ (3 ^ 2).
+    let nested = Expression::Op {
+        op: Operation::Pow,
+        left: Box::new(Expression::Value(2)),
+        right: Box::new(pow(3, 2)),
+    };
+    assert_eq!(eval(&nested), Ok(512));
+}
+
+#[test]
+fn test_min_max() {
+    let term = Expression::Op {
+        op: Operation::Min,
+        left: Box::new(Expression::Value(4)),
+        right: Box::new(Expression::Value(-6)),
+    };
+    assert_eq!(
+        eval(&Expression::Op {
+            op: Operation::Max,
+            left: Box::new(term),
+            right: Box::new(Expression::Value(-5)),
+        }),
+        Ok(-5)
+    );
+}
+
+#[test]
+fn test_unary() {
+    let abs = Expression::Unary {
+        op: UnaryOperation::Abs,
+        operand: Box::new(Expression::Op {
+            op: Operation::Sub,
+            left: Box::new(Expression::Value(3)),
+            right: Box::new(Expression::Value(10)),
+        }),
+    };
+    assert_eq!(
+        eval(&Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(abs) }),
+        Ok(-7)
+    );
+
+    let neg_min = Expression::Unary {
+        op: UnaryOperation::Neg,
+        operand: Box::new(Expression::Value(i64::MIN)),
+    };
+    assert_eq!(eval(&neg_min), Err(EvalError::Overflow(neg_min.clone())));
+    let abs_min = Expression::Unary {
+        op: UnaryOperation::Abs,
+        operand: Box::new(Expression::Value(i64::MIN)),
+    };
+    assert_eq!(eval(&abs_min), Err(EvalError::Overflow(abs_min.clone())));
+}
+
+#[test]
+fn test_deep_tree() {
+    // A left-leaning chain like the ones folding a long sum produces.
+    let mut e = Expression::Value(1);
+    for _ in 0..1_000_000 {
+        e = Expression::Op {
+            op: Operation::Add,
+            left: Box::new(e),
+            right: Box::new(Expression::Value(1)),
+        };
+    }
+    assert_eq!(eval(&e), Ok(1_000_001));
+    drop(e);
+
+    // And a right-leaning one through unary nodes.
+    let mut e = Expression::Var(String::from("x"));
+    for _ in 0..1_000_000 {
+        e = Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(e) };
+    }
+    let env = Env::from([(String::from("x"), 7)]);
+    assert_eq!(eval_with(&e, &env), Ok(7));
+    drop(e);
+}
+
+#[test]
+fn test_backends() {
+    let half = Expression::Op {
+        op: Operation::Div,
+        left: Box::new(Expression::Value(7)),
+        right: Box::new(Expression::Value(2)),
+    };
+    let e = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(half),
+        right: Box::new(Expression::Value(2)),
+    };
+    assert_eq!(eval(&e), Ok(6));
+    assert_eq!(eval(&e.cast::<f64>()), Ok(7.0));
+    assert_eq!(eval(&e.cast::<Rational>()), Ok(Rational::from_i64(7)));
+
+    let third = Expression::Op {
+        op: Operation::Div,
+        left: Box::new(Expression::Value(1)),
+        right: Box::new(Expression::Value(3)),
+    };
+    assert_eq!(eval(&third.cast::<Rational>()), Ok(Rational::new(1, 3).unwrap()));
+    assert!((eval(&third.cast::<f64>()).unwrap() - 1.0 / 3.0).abs() < 1e-12);
+
+    // Errors carry the sub-expression in the chosen backend.
+    let by_zero = Expression::Op {
+        op: Operation::Div,
+        left: Box::new(Expression::Value(1)),
+        right: Box::new(Expression::Value(0)),
+    };
+    assert_eq!(eval(&by_zero.cast::<f64>()), Err(EvalError::DivisionByZero(by_zero.cast())));
+    assert_eq!(eval(&by_zero.cast::<Rational>()), Err(EvalError::DivisionByZero(by_zero.cast())));
+}
+
+#[test]
+fn test_display() {
+    for (input, expected) in [
+        ("(a * b) + c", "a * b + c"),
+        ("a - (b - c)", "a - (b - c)"),
+        ("(a - b) - c", "a - b - c"),
+        ("a + (b + c)", "a + (b + c)"),
+        ("(3 - 4) * 5 + 10 * 9", "(3 - 4) * 5 + 10 * 9"),
+        ("a / (b * c)", "a / (b * c)"),
+        ("((-4)) * -2", "-4 * -2"),
+        ("2 ^ (3 ^ 2)", "2 ^ 3 ^ 2"),
+        ("(2 ^ 3) ^ 2", "(2 ^ 3) ^ 2"),
+        ("a % b * c ^ d", "a % b * c ^ d"),
+        ("max(a + 1, min(b, c)) * 2", "max(a + 1, min(b, c)) * 2"),
+        ("-(a + b) - -c", "-(a + b) - -c"),
+        ("-(4) ^ 2", "-(4) ^ 2"),
+        ("-(-x ^ 2)", "-(-x ^ 2)"),
+        ("abs(-x) - abs(3)", "abs(-x) - abs(3)"),
+        ("(a < b) == (c >= d)", "a < b == (c >= d)"),
+        ("a + 1 > b * 2 && !(c || d) || e != f", "a + 1 > b * 2 && !(c || d) || e != f"),
+        ("!!a && (b && c)", "!!a && (b && c)"),
+        ("(if a then b else c) + 1", "(if a then b else c) + 1"),
+        ("if a then (if b then c else d) else e", "if a then if b then c else d else e"),
+        ("-(if a then b else c)", "-(if a then b else c)"),
+    ] {
+        let e = parse(input).unwrap();
+        assert_eq!(e.to_string(), expected);
+        assert_eq!(parse(&e.to_string()), Ok(e));
+    }
+}
+
+#[test]
+fn test_display_non_integer_literals() {
+    let half = |num| Expression::Value(Rational::new(num, 2).unwrap());
+    let e = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(Expression::Var(String::from("x"))),
+        right: Box::new(half(7)),
+    };
+    assert_eq!(e.to_string(), "x * (7/2)");
+    assert_eq!(format!("{e:#}"), "(* x 7/2)");
+    // The literal reads back as a division, with the same value.
+    let env = Env::from([(String::from("x"), Rational::from_i64(2))]);
+    let read_back = parse(&e.to_string()).unwrap().cast::<Rational>();
+    assert_eq!(eval_with(&read_back, &env), Ok(Rational::from_i64(7)));
+
+    let negated = Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(half(-1)) };
+    assert_eq!(negated.to_string(), "-(-1/2)");
+    assert_eq!(half(4).to_string(), "2");
+    assert_eq!(Expression::Value(-4).to_string(), "-4");
+    // Floats have no literals in the parser at all.
+    assert_eq!(Expression::Value(0.5).to_string(), "(0.5)");
+    assert!(parse("(0.5)").is_err());
+}
+
+#[test]
+fn test_display_deep_trees() {
+    let x = || Box::new(Expression::Var(String::from("x")));
+    let mut e: Expression = Expression::Var(String::from("x"));
+    for _ in 0..100_000 {
+        e = Expression::Op { op: Operation::Sub, left: x(), right: Box::new(e) };
+    }
+    let text = e.to_string();
+    assert!(text.starts_with("x - (x - (x - "));
+    assert!(text.trim_end_matches(')').ends_with(" - (x - (x - x"));
+    assert_eq!(text.len(), 99_999 * "x - ()".len() + "x - x".len());
+    let sexpr = format!("{e:#}");
+    assert!(sexpr.starts_with("(- x (- x (- x "));
+    assert_eq!(sexpr.len(), 100_000 * "(- x )".len() + 1);
+}
+
+#[test]
+fn test_display_sexpr() {
+    let e = parse("(3 - x) * 5 + 10 / -2").unwrap();
+    assert_eq!(format!("{e:#}"), "(+ (* (- 3 x) 5) (/ 10 -2))");
+    let e = parse("-max(x, 2 ^ y) % abs(z)").unwrap();
+    assert_eq!(format!("{e:#}"), "(% (neg (max x (^ 2 y))) (abs z))");
+    let e = parse("if !a || b <= 1 then true else c").unwrap();
+    assert_eq!(format!("{e:#}"), "(if (|| (not a) (<= b 1)) true c)");
+}
+
+#[test]
+fn test_conditionals() {
+    let env = Env::from([(String::from("price"), 120)]);
+    let discount = parse("if price > 100 then price * 9 / 10 else price").unwrap();
+    assert_eq!(eval_with(&discount, &env), Ok(108));
+    let env = Env::from([(String::from("price"), 80)]);
+    assert_eq!(eval_with(&discount, &env), Ok(80));
+
+    // Booleans evaluate to 1 and 0.
+    assert_eq!(eval(&parse("1 <= 2 && !(3 == 4) && (5 != 5 || 6 >= 7 || 8 < 9)").unwrap()), Ok(1));
+    assert_eq!(eval(&parse("true && (2 > 3 || false)").unwrap()), Ok(0));
+
+    // Only the operands and branches that are needed are evaluated.
+    let env = Env::from([(String::from("x"), 0)]);
+    let guarded = parse("x != 0 && 10 / x > 1 || x == 0").unwrap();
+    assert_eq!(eval_with(&guarded, &env), Ok(1));
+    let branch = parse("if x == 0 then 0 else 10 / x").unwrap();
+    assert_eq!(eval_with(&branch, &env), Ok(0));
+    let failing = parse("x == 0 && 10 / x > 1").unwrap();
+    assert_eq!(eval_with(&failing, &env), Err(EvalError::DivisionByZero(parse("10 / x").unwrap())));
+    let halved = parse("if x > 1 then x / 2 else x").unwrap().cast::<f64>();
+    assert_eq!(eval_with(&halved, &Env::from([(String::from("x"), 3.0)])), Ok(1.5));
+}
+
+#[test]
+fn test_variables() {
+    let e = Expression::Op {
+        op: Operation::Mul,
+        left: Box::new(Expression::Var(String::from("price"))),
+        right: Box::new(Expression::Op {
+            op: Operation::Add,
+            left: Box::new(Expression::Var(String::from("qty"))),
+            right: Box::new(Expression::Value(1)),
+        }),
+    };
+    // The same tree can be evaluated against several environments.
+    let env = Env::from([(String::from("price"), 3), (String::from("qty"), 4)]);
+    assert_eq!(eval_with(&e, &env), Ok(15));
+    let env = Env::from([(String::from("price"), 10), (String::from("qty"), 0)]);
+    assert_eq!(eval_with(&e, &env), Ok(10));
+
+    let env = Env::from([(String::from("price"), 10)]);
+    assert_eq!(eval_with(&e, &env), Err(EvalError::UnboundVariable(String::from("qty"))));
+    assert_eq!(eval(&e), Err(EvalError::UnboundVariable(String::from("price"))));
+}
+
+#[test]
+fn test_let() {
+    let env = Env::from([(String::from("x"), 10)]);
+    assert_eq!(eval_with(&parse("let y = x * 2 in y + x").unwrap(), &env), Ok(30));
+    // Inner bindings shadow outer ones and `env`, but only in their body.
+    let shadowed = parse("(let x = 1 in let x = x + 1 in x * 100) + x").unwrap();
+    assert_eq!(eval_with(&shadowed, &env), Ok(210));
+    assert_eq!(
+        eval(&parse("(let y = 1 in y) + y").unwrap()),
+        Err(EvalError::UnboundVariable(String::from("y")))
+    );
+    // The bound value is evaluated once, before the body.
+    assert_eq!(
+        eval(&parse("let y = 1 / 0 in 5").unwrap()),
+        Err(EvalError::DivisionByZero(parse("1 / 0").unwrap()))
+    );
+}
+
+#[test]
+fn test_functions() {
+    let env = Env::from([(String::from("price"), 250)]);
+    let total = parse("fn tax(x) = x * 8 / 100 in price + tax(price)").unwrap();
+    assert_eq!(eval_with(&total, &env), Ok(270));
+    let factorial = parse("fn fact(n) = if n <= 1 then 1 else n * fact(n - 1) in fact(20)");
+    assert_eq!(eval(&factorial.unwrap()), Ok(2_432_902_008_176_640_000));
+    // Functions see the names visible where they were defined, not those
+    // of their callers.
+    let lexical = parse("let k = 1 in fn f(x) = x + k in let k = 100 in f(k)").unwrap();
+    assert_eq!(eval(&lexical), Ok(101));
+    let caller = parse("fn f() = y in let y = 1 in f()").unwrap();
+    assert_eq!(eval(&caller), Err(EvalError::UnboundVariable(String::from("y"))));
+    assert_eq!(eval_with(&caller, &Env::from([(String::from("y"), 7)])), Ok(7));
+    // Values and functions have separate names.
+    assert_eq!(eval(&parse("fn f(f) = f * 2 in let f = 3 in f(f)").unwrap()), Ok(6));
+    let inner = parse("fn f(x) = x + 1 in (fn f(x) = f(x) * 10 in f(1)) + f(1)").unwrap();
+    assert_eq!(eval(&inner), Err(EvalError::RecursionLimit(parse("f(x)").unwrap())));
+    let outer = parse("fn f(x) = x + 1 in fn g(x) = f(x) * 10 in g(1) + f(1)").unwrap();
+    assert_eq!(eval(&outer), Ok(22));
+}
+
+#[test]
+fn test_call_errors() {
+    assert_eq!(eval(&parse("f(1)").unwrap()), Err(EvalError::UnknownFunction(String::from("f"))));
+    assert_eq!(
+        eval(&parse("fn f(a, b) = a in f(1)").unwrap()),
+        Err(EvalError::Arity { name: String::from("f"), expected: 2, found: 1 })
+    );
+    assert_eq!(
+        eval(&parse("(fn f() = 1 in f()) + f()").unwrap()),
+        Err(EvalError::UnknownFunction(String::from("f")))
+    );
+    // Runaway recursion is an error rather than a stack overflow, however
+    // deep the calls nest.
+    let endless = parse("fn f(x) = f(x) in f(1)").unwrap();
+    assert_eq!(eval(&endless), Err(EvalError::RecursionLimit(parse("f(x)").unwrap())));
+    let limit = |n: usize| {
+        let e = format!("fn count(n) = if n == 0 then 0 else 1 + count(n - 1) in count({n})");
+        eval(&parse(&e).unwrap())
+    };
+    assert_eq!(limit(MAX_CALL_DEPTH - 1), Ok(MAX_CALL_DEPTH as i64 - 1));
+    assert!(matches!(limit(MAX_CALL_DEPTH), Err(EvalError::RecursionLimit(_))));
+}
+
+#[test]
+fn test_display_bindings() {
+    for (input, expected) in [
+        ("let x = 1 in x + 2", "let x = 1 in x + 2"),
+        ("(let x = 1 in x) + 2", "(let x = 1 in x) + 2"),
+        ("let x = (let y = 1 in y) in x", "let x = let y = 1 in y in x"),
+        ("fn f(a, b) = a * b in f(1, 2) + g()", "fn f(a, b) = a * b in f(1, 2) + g()"),
+        ("-f(x)", "-f(x)"),
+        ("fn zero() = 0 in -(fn one() = 1 in one())", "fn zero() = 0 in -(fn one() = 1 in one())"),
+    ] {
+        let e = parse(input).unwrap();
+        assert_eq!(e.to_string(), expected);
+        assert_eq!(parse(&e.to_string()), Ok(e));
+    }
+    let e = parse("fn f(a, b) = a * b in let x = 2 in f(x, 3)").unwrap();
+    assert_eq!(format!("{e:#}"), "(fn f (a b) (* a b) (let x 2 (f x 3)))");
+}
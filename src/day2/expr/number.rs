@@ -0,0 +1,317 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::ArithmeticError;
+
+/// A numeric type that expressions can be evaluated over.
+///
+/// Every operation is checked: instead of panicking, wrapping or producing
+/// infinities, it reports why it failed.
+pub trait Number: Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display {
+    /// Convert an integer, e.g. a literal from the parser.
+    fn from_i64(value: i64) -> Self;
+
+    /// Represent a boolean as `1` or `0`.
+    fn from_bool(value: bool) -> Self {
+        Self::from_i64(value.into())
+    }
+
+    /// Whether the value counts as true, i.e. is nonzero.
+    fn is_true(&self) -> bool {
+        *self != Self::from_i64(0)
+    }
+
+    fn try_add(&self, other: &Self) -> Result<Self, ArithmeticError>;
+    fn try_sub(&self, other: &Self) -> Result<Self, ArithmeticError>;
+    fn try_mul(&self, other: &Self) -> Result<Self, ArithmeticError>;
+    fn try_div(&self, other: &Self) -> Result<Self, ArithmeticError>;
+    /// Remainder of truncating division, with the sign of `self`.
+    fn try_rem(&self, other: &Self) -> Result<Self, ArithmeticError>;
+    fn try_pow(&self, exponent: &Self) -> Result<Self, ArithmeticError>;
+    fn try_neg(&self) -> Result<Self, ArithmeticError>;
+    fn try_abs(&self) -> Result<Self, ArithmeticError>;
+}
+
+impl Number for i64 {
+    fn from_i64(value: i64) -> Self {
+        value
+    }
+
+    fn try_add(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        self.checked_add(*other).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn try_sub(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        self.checked_sub(*other).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn try_mul(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        self.checked_mul(*other).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn try_div(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        if *other == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        self.checked_div(*other).ok_or(ArithmeticError::Overflow)
+    }
+
+    fn try_rem(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        if *other == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        self.checked_rem(*other).ok_or(ArithmeticError::Overflow)
+    }
+
+    /// Integer powers only: a negative exponent is an error.
+    fn try_pow(&self, exponent: &Self) -> Result<Self, ArithmeticError> {
+        let (base, exponent) = (*self, *exponent);
+        if exponent < 0 {
+            return Err(ArithmeticError::NegativeExponent);
+        }
+        match (base, u32::try_from(exponent)) {
+            (_, Ok(exponent)) => base.checked_pow(exponent).ok_or(ArithmeticError::Overflow),
+            // Huge exponents only stay in range for these bases.
+            (0 | 1, Err(_)) => Ok(base),
+            (-1, Err(_)) => Ok(if exponent % 2 == 0 { 1 } else { -1 }),
+            (_, Err(_)) => Err(ArithmeticError::Overflow),
+        }
+    }
+
+    fn try_neg(&self) -> Result<Self, ArithmeticError> {
+        self.checked_neg().ok_or(ArithmeticError::Overflow)
+    }
+
+    fn try_abs(&self) -> Result<Self, ArithmeticError> {
+        self.checked_abs().ok_or(ArithmeticError::Overflow)
+    }
+}
+
+/// Reject results that left the finite range of `f64`.
+fn finite(value: f64) -> Result<f64, ArithmeticError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(ArithmeticError::Overflow)
+    }
+}
+
+impl Number for f64 {
+    fn from_i64(value: i64) -> Self {
+        value as f64
+    }
+
+    fn try_add(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        finite(self + other)
+    }
+
+    fn try_sub(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        finite(self - other)
+    }
+
+    fn try_mul(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        finite(self * other)
+    }
+
+    fn try_div(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        if *other == 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        finite(self / other)
+    }
+
+    fn try_rem(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        if *other == 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        finite(self % other)
+    }
+
+    /// Any real exponent, as long as the result is real.
+    fn try_pow(&self, exponent: &Self) -> Result<Self, ArithmeticError> {
+        if *self == 0.0 && *exponent < 0.0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        if *self < 0.0 && exponent.fract() != 0.0 {
+            return Err(ArithmeticError::NonIntegerExponent);
+        }
+        finite(self.powf(*exponent))
+    }
+
+    fn try_neg(&self) -> Result<Self, ArithmeticError> {
+        Ok(-self)
+    }
+
+    fn try_abs(&self) -> Result<Self, ArithmeticError> {
+        Ok(self.abs())
+    }
+}
+
+/// An exact fraction, always stored in lowest terms with a positive
+/// denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.abs()
+}
+
+impl Rational {
+    /// The fraction `num / den`, reduced.
+    pub fn new(num: i64, den: i64) -> Result<Rational, ArithmeticError> {
+        Rational::reduce(num.into(), den.into())
+    }
+
+    /// Reduce a fraction computed in wide arithmetic, checking that the
+    /// result fits.
+    fn reduce(num: i128, den: i128) -> Result<Rational, ArithmeticError> {
+        if den == 0 {
+            return Err(ArithmeticError::DivisionByZero);
+        }
+        let divisor = gcd(num, den) * den.signum();
+        let num = i64::try_from(num / divisor).map_err(|_| ArithmeticError::Overflow)?;
+        let den = i64::try_from(den / divisor).map_err(|_| ArithmeticError::Overflow)?;
+        Ok(Rational { num, den })
+    }
+
+    /// The quotient rounded towards zero.
+    fn trunc(&self) -> Rational {
+        Rational { num: self.num / self.den, den: 1 }
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (i128::from(self.num) * i128::from(other.den))
+            .cmp(&(i128::from(other.num) * i128::from(self.den)))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+impl Number for Rational {
+    fn from_i64(value: i64) -> Self {
+        Rational { num: value, den: 1 }
+    }
+
+    fn try_add(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        let (a, b, c, d) =
+            (self.num as i128, self.den as i128, other.num as i128, other.den as i128);
+        Rational::reduce(a * d + c * b, b * d)
+    }
+
+    fn try_sub(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        let (a, b, c, d) =
+            (self.num as i128, self.den as i128, other.num as i128, other.den as i128);
+        Rational::reduce(a * d - c * b, b * d)
+    }
+
+    fn try_mul(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        let (a, b, c, d) =
+            (self.num as i128, self.den as i128, other.num as i128, other.den as i128);
+        Rational::reduce(a * c, b * d)
+    }
+
+    fn try_div(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        let (a, b, c, d) =
+            (self.num as i128, self.den as i128, other.num as i128, other.den as i128);
+        Rational::reduce(a * d, b * c)
+    }
+
+    fn try_rem(&self, other: &Self) -> Result<Self, ArithmeticError> {
+        let quotient = self.try_div(other)?.trunc();
+        self.try_sub(&other.try_mul(&quotient)?)
+    }
+
+    /// Integer exponents only; negative ones give the reciprocal.
+    fn try_pow(&self, exponent: &Self) -> Result<Self, ArithmeticError> {
+        if exponent.den != 1 {
+            return Err(ArithmeticError::NonIntegerExponent);
+        }
+        let power = exponent.num.unsigned_abs();
+        let power = i64::try_from(power).map_err(|_| ArithmeticError::Overflow)?;
+        let num = self.num.try_pow(&power)?;
+        let den = self.den.try_pow(&power)?;
+        if exponent.num < 0 {
+            Rational::new(den, num)
+        } else {
+            Rational::new(num, den)
+        }
+    }
+
+    fn try_neg(&self) -> Result<Self, ArithmeticError> {
+        Rational::reduce(-(self.num as i128), self.den.into())
+    }
+
+    fn try_abs(&self) -> Result<Self, ArithmeticError> {
+        Rational::reduce((self.num as i128).abs(), self.den.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(num: i64, den: i64) -> Rational {
+        Rational::new(num, den).unwrap()
+    }
+
+    #[test]
+    fn rational_reduces() {
+        assert_eq!(ratio(6, -4), ratio(-3, 2));
+        assert_eq!(ratio(6, -4), Rational { num: -3, den: 2 });
+        assert_eq!(ratio(0, -5), Rational::from_i64(0));
+        assert_eq!(Rational::new(1, 0), Err(ArithmeticError::DivisionByZero));
+        assert_eq!(Rational::new(i64::MIN, -1), Err(ArithmeticError::Overflow));
+    }
+
+    #[test]
+    fn rational_arithmetic() {
+        assert_eq!(ratio(1, 2).try_add(&ratio(1, 3)), Ok(ratio(5, 6)));
+        assert_eq!(ratio(1, 2).try_sub(&ratio(3, 4)), Ok(ratio(-1, 4)));
+        assert_eq!(ratio(2, 3).try_mul(&ratio(9, 4)), Ok(ratio(3, 2)));
+        assert_eq!(ratio(2, 3).try_div(&ratio(-4, 9)), Ok(ratio(-3, 2)));
+        assert_eq!(ratio(7, 2).try_rem(&ratio(1, 1)), Ok(ratio(1, 2)));
+        assert_eq!(ratio(-7, 2).try_rem(&ratio(2, 1)), Ok(ratio(-3, 2)));
+        assert_eq!(ratio(2, 3).try_pow(&ratio(-2, 1)), Ok(ratio(9, 4)));
+        assert_eq!(ratio(2, 3).try_pow(&ratio(1, 2)), Err(ArithmeticError::NonIntegerExponent));
+        assert_eq!(ratio(0, 1).try_pow(&ratio(-1, 1)), Err(ArithmeticError::DivisionByZero));
+        assert_eq!(ratio(1, 3).try_div(&ratio(0, 1)), Err(ArithmeticError::DivisionByZero));
+        assert_eq!(
+            Rational::from_i64(i64::MAX).try_add(&ratio(1, 1)),
+            Err(ArithmeticError::Overflow)
+        );
+        assert!(ratio(1, 3) < ratio(1, 2));
+        assert_eq!(ratio(-7, 2).to_string(), "-7/2");
+    }
+
+    #[test]
+    fn float_arithmetic() {
+        assert_eq!(7.0.try_div(&2.0), Ok(3.5));
+        assert_eq!(1.0.try_div(&0.0), Err(ArithmeticError::DivisionByZero));
+        assert_eq!(f64::MAX.try_mul(&2.0), Err(ArithmeticError::Overflow));
+        assert_eq!(2.0.try_pow(&-1.0), Ok(0.5));
+        assert_eq!((-8.0).try_pow(&0.5), Err(ArithmeticError::NonIntegerExponent));
+        assert_eq!(0.0.try_pow(&-1.0), Err(ArithmeticError::DivisionByZero));
+    }
+}
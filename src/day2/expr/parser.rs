@@ -0,0 +1,687 @@
+use std::fmt;
+
+use thiserror::Error;
+
+use super::{Expression, Function, Operation, UnaryOperation};
+
+/// What the parser was looking for when it hit a bad token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// A literal, a variable or a parenthesized expression.
+    Operand,
+    /// The `)` closing an earlier `(`.
+    ClosingParen,
+    /// The `,` between two function arguments.
+    Comma,
+    /// The `then` after the condition of an `if`.
+    Then,
+    /// The `else` after the first branch of an `if`.
+    Else,
+    /// The name of a variable, function or parameter.
+    Name,
+    /// The `(` before the arguments of `min`, `max` or `abs`, or the
+    /// parameters of a function.
+    OpeningParen,
+    /// The `=` after the name in a `let` or the parameters of a `fn`.
+    Assign,
+    /// The `in` before the body of a `let` or `fn`.
+    In,
+    /// The `fn` starting a function definition.
+    Fn,
+    /// Nothing: the expression is complete.
+    EndOfInput,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Operand => write!(f, "an operand"),
+            Expected::ClosingParen => write!(f, "`)`"),
+            Expected::Comma => write!(f, "`,`"),
+            Expected::Then => write!(f, "`then`"),
+            Expected::Else => write!(f, "`else`"),
+            Expected::Name => write!(f, "a name"),
+            Expected::OpeningParen => write!(f, "`(`"),
+            Expected::Assign => write!(f, "`=`"),
+            Expected::In => write!(f, "`in`"),
+            Expected::Fn => write!(f, "`fn`"),
+            Expected::EndOfInput => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ParseError {
+    #[error("expected {expected} at offset {offset}")]
+    Unexpected { offset: usize, expected: Expected },
+    #[error("integer literal out of range at offset {offset}")]
+    LiteralOutOfRange { offset: usize },
+    #[error("duplicate parameter `{name}` at offset {offset}")]
+    DuplicateParameter { offset: usize, name: String },
+    #[error("expression nested more than {MAX_DEPTH} deep at offset {offset}")]
+    TooDeep { offset: usize },
+}
+
+/// How deeply expressions may nest, so that deeply nested input is an error
+/// rather than a stack overflow.
+pub const MAX_DEPTH: usize = 256;
+
+impl ParseError {
+    /// Byte offset into the input of the token that caused the error.
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::Unexpected { offset, .. } => *offset,
+            ParseError::LiteralOutOfRange { offset } => *offset,
+            ParseError::DuplicateParameter { offset, .. } => *offset,
+            ParseError::TooDeep { offset } => *offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Number(&'a str),
+    Ident(&'a str),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Less,
+    LessEq,
+    EqEq,
+    NotEq,
+    Greater,
+    GreaterEq,
+    AndAnd,
+    OrOr,
+    Bang,
+    Assign,
+    Comma,
+    LParen,
+    RParen,
+    Unknown,
+    End,
+}
+
+/// Split `input` into tokens, each paired with its byte offset.
+fn tokenize(input: &str) -> Vec<(usize, Token<'_>)> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '0'..='9' => {
+                let mut end = offset + 1;
+                while let Some((i, '0'..='9')) = chars.peek() {
+                    end = i + 1;
+                    chars.next();
+                }
+                Token::Number(&input[offset..end])
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = offset + 1;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + 1;
+                    chars.next();
+                }
+                Token::Ident(&input[offset..end])
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '^' => Token::Caret,
+            '<' | '>' | '=' | '!' | '&' | '|' => {
+                let next = chars.peek().map(|&(_, c)| c);
+                let (token, pair) = match (c, next) {
+                    ('<', Some('=')) => (Token::LessEq, true),
+                    ('>', Some('=')) => (Token::GreaterEq, true),
+                    ('=', Some('=')) => (Token::EqEq, true),
+                    ('!', Some('=')) => (Token::NotEq, true),
+                    ('&', Some('&')) => (Token::AndAnd, true),
+                    ('|', Some('|')) => (Token::OrOr, true),
+                    ('<', _) => (Token::Less, false),
+                    ('>', _) => (Token::Greater, false),
+                    ('!', _) => (Token::Bang, false),
+                    ('=', _) => (Token::Assign, false),
+                    _ => (Token::Unknown, false),
+                };
+                if pair {
+                    chars.next();
+                }
+                token
+            }
+            ',' => Token::Comma,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            _ => Token::Unknown,
+        };
+        tokens.push((offset, token));
+    }
+    tokens.push((input.len(), Token::End));
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    pos: usize,
+    /// How many nested expressions and operands are being parsed.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> (usize, Token<'a>) {
+        self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> (usize, Token<'a>) {
+        let token = self.peek();
+        if token.1 != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected(&self, expected: Expected) -> ParseError {
+        ParseError::Unexpected { offset: self.peek().0, expected }
+    }
+
+    /// Check that all of the input was parsed.
+    fn end(&self) -> Result<(), ParseError> {
+        if self.peek().1 != Token::End {
+            return Err(self.unexpected(Expected::EndOfInput));
+        }
+        Ok(())
+    }
+
+    fn binary_operator(&self) -> Option<Operation> {
+        match self.peek().1 {
+            Token::Plus => Some(Operation::Add),
+            Token::Minus => Some(Operation::Sub),
+            Token::Star => Some(Operation::Mul),
+            Token::Slash => Some(Operation::Div),
+            Token::Percent => Some(Operation::Rem),
+            Token::Caret => Some(Operation::Pow),
+            Token::Less => Some(Operation::Lt),
+            Token::LessEq => Some(Operation::Le),
+            Token::EqEq => Some(Operation::Eq),
+            Token::NotEq => Some(Operation::Ne),
+            Token::Greater => Some(Operation::Gt),
+            Token::GreaterEq => Some(Operation::Ge),
+            Token::AndAnd => Some(Operation::And),
+            Token::OrOr => Some(Operation::Or),
+            _ => None,
+        }
+    }
+
+    /// Run `parse` one level deeper, failing if that is too deep.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError::TooDeep { offset: self.peek().0 });
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parse a chain of binary operations whose operators bind at least as
+    /// tightly as `min_precedence`.
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression, ParseError> {
+        self.nested(|parser| parser.chain(min_precedence))
+    }
+
+    fn chain(&mut self, min_precedence: u8) -> Result<Expression, ParseError> {
+        let mut left = self.operand()?;
+        while let Some(op) = self.binary_operator() {
+            let prec = op.precedence();
+            if prec < min_precedence {
+                break;
+            }
+            self.advance();
+            let right = if op.is_right_associative() {
+                self.expression(prec)?
+            } else {
+                self.expression(prec + 1)?
+            };
+            left = Expression::Op { op, left: Box::new(left), right: Box::new(right) };
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expression, ParseError> {
+        match self.peek().1 {
+            Token::Number(digits) => {
+                let (offset, _) = self.advance();
+                literal(offset, digits, false)
+            }
+            Token::Ident("true" | "false") => {
+                let (_, token) = self.advance();
+                Ok(Expression::Bool(token == Token::Ident("true")))
+            }
+            Token::Ident("if") => self.conditional(),
+            Token::Ident("let") => self.binding(),
+            Token::Ident("fn") => self.definition(),
+            Token::Ident(name) if KEYWORDS.contains(&name) => {
+                Err(self.unexpected(Expected::Operand))
+            }
+            Token::Ident(name) => {
+                self.advance();
+                if self.peek().1 == Token::LParen {
+                    self.call(name)
+                } else {
+                    Ok(Expression::Var(name.to_string()))
+                }
+            }
+            Token::Minus => {
+                let (offset, _) = self.advance();
+                match self.peek().1 {
+                    Token::Number(digits) => {
+                        self.advance();
+                        literal(offset, digits, true)
+                    }
+                    _ => {
+                        let operand = self.nested(Self::operand)?;
+                        Ok(Expression::Unary {
+                            op: UnaryOperation::Neg,
+                            operand: Box::new(operand),
+                        })
+                    }
+                }
+            }
+            Token::Bang => {
+                self.advance();
+                let operand = self.nested(Self::operand)?;
+                Ok(Expression::Unary { op: UnaryOperation::Not, operand: Box::new(operand) })
+            }
+            Token::LParen => {
+                self.advance();
+                let inner = self.expression(0)?;
+                self.expect(Token::RParen, Expected::ClosingParen)?;
+                Ok(inner)
+            }
+            _ => Err(self.unexpected(Expected::Operand)),
+        }
+    }
+
+    /// `if cond then a else b`.
+    fn conditional(&mut self) -> Result<Expression, ParseError> {
+        self.advance();
+        let cond = self.expression(0)?;
+        self.expect(Token::Ident("then"), Expected::Then)?;
+        let then = self.expression(0)?;
+        self.expect(Token::Ident("else"), Expected::Else)?;
+        let otherwise = self.expression(0)?;
+        Ok(Expression::If {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    /// `let name = value in body`.
+    fn binding(&mut self) -> Result<Expression, ParseError> {
+        self.advance();
+        let name = self.name()?;
+        self.expect(Token::Assign, Expected::Assign)?;
+        let value = self.expression(0)?;
+        self.expect(Token::Ident("in"), Expected::In)?;
+        let body = self.expression(0)?;
+        Ok(Expression::Let { name: name.to_string(), value: Box::new(value), body: Box::new(body) })
+    }
+
+    /// `fn name(params) = body in rest`.
+    fn definition(&mut self) -> Result<Expression, ParseError> {
+        let function = self.function()?;
+        self.expect(Token::Ident("in"), Expected::In)?;
+        let body = self.expression(0)?;
+        Ok(Expression::Define { function: Box::new(function), body: Box::new(body) })
+    }
+
+    /// The arguments of a call to `name`, a built-in or a defined function.
+    fn call(&mut self, name: &str) -> Result<Expression, ParseError> {
+        match name {
+            "abs" => {
+                let [operand] = self.arguments()?;
+                Ok(Expression::Unary { op: UnaryOperation::Abs, operand: Box::new(operand) })
+            }
+            "min" | "max" => {
+                let op = if name == "min" { Operation::Min } else { Operation::Max };
+                let [left, right] = self.arguments()?;
+                Ok(Expression::Op { op, left: Box::new(left), right: Box::new(right) })
+            }
+            _ => {
+                self.advance();
+                let mut args = Vec::new();
+                if self.peek().1 != Token::RParen {
+                    args.push(self.expression(0)?);
+                    while self.peek().1 == Token::Comma {
+                        self.advance();
+                        args.push(self.expression(0)?);
+                    }
+                }
+                self.expect(Token::RParen, Expected::ClosingParen)?;
+                Ok(Expression::Call { name: name.to_string(), args })
+            }
+        }
+    }
+
+    /// A name that is not a keyword, for a binding.
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        match self.peek().1 {
+            Token::Ident(name) if !KEYWORDS.contains(&name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.unexpected(Expected::Name)),
+        }
+    }
+
+    /// `fn name(a, b) = body`. The built-in functions can't be redefined.
+    fn function(&mut self) -> Result<Function, ParseError> {
+        self.expect(Token::Ident("fn"), Expected::Fn)?;
+        if let Token::Ident("abs" | "min" | "max") = self.peek().1 {
+            return Err(self.unexpected(Expected::Name));
+        }
+        let name = self.name()?.to_string();
+        self.expect(Token::LParen, Expected::OpeningParen)?;
+        let mut params: Vec<String> = Vec::new();
+        if self.peek().1 != Token::RParen {
+            loop {
+                let offset = self.peek().0;
+                let param = self.name()?;
+                if params.iter().any(|p| p == param) {
+                    let name = param.to_string();
+                    return Err(ParseError::DuplicateParameter { offset, name });
+                }
+                params.push(param.to_string());
+                if self.peek().1 != Token::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(Token::RParen, Expected::ClosingParen)?;
+        self.expect(Token::Assign, Expected::Assign)?;
+        let body = self.expression(0)?;
+        Ok(Function { name, params, body })
+    }
+
+    fn expect(&mut self, token: Token, expected: Expected) -> Result<(), ParseError> {
+        if self.peek().1 != token {
+            return Err(self.unexpected(expected));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    /// Parse exactly `N` parenthesized, comma-separated arguments.
+    fn arguments<const N: usize>(&mut self) -> Result<[Expression; N], ParseError> {
+        self.expect(Token::LParen, Expected::OpeningParen)?;
+        let mut args = Vec::with_capacity(N);
+        for i in 0..N {
+            if i > 0 {
+                self.expect(Token::Comma, Expected::Comma)?;
+            }
+            args.push(self.expression(0)?);
+        }
+        self.expect(Token::RParen, Expected::ClosingParen)?;
+        Ok(args.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+/// Words with a meaning of their own, which can't name anything.
+pub const KEYWORDS: [&str; 8] = ["true", "false", "if", "then", "else", "let", "in", "fn"];
+
+/// Convert the digits of a literal (and its sign) into a value.
+fn literal(offset: usize, digits: &str, negative: bool) -> Result<Expression, ParseError> {
+    let text = if negative { format!("-{digits}") } else { digits.to_string() };
+    text.parse().map(Expression::Value).map_err(|_| ParseError::LiteralOutOfRange { offset })
+}
+
+/// Parse an infix expression such as `"(3 - 4) * 5 + 10 * 9"`.
+///
+/// From loosest to tightest, the binary operators are `||`, `&&`, the
+/// comparisons `< <= == != > >=`, `+ -`, `* / %` and `^`; all are
+/// left-associative except `^`. A `-` directly in front of a literal makes it
+/// negative, and in front of anything else negates it; `!` is logical
+/// negation. `min(a, b)`, `max(a, b)` and `abs(a)` are built in, as are
+/// `true`, `false` and `if cond then a else b`, whose `else` branch extends
+/// as far as possible. Other identifiers such as `price` or `unit_cost`
+/// become variables, or calls if followed by arguments. `let x = a in b`
+/// binds a variable and `fn f(x, y) = a in b` a function, both in `b`, which
+/// extends as far as possible too.
+pub fn parse(input: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser { tokens: tokenize(input), pos: 0, depth: 0 };
+    let expr = parser.expression(0)?;
+    parser.end()?;
+    Ok(expr)
+}
+
+/// Parse a function definition on its own, such as
+/// `"fn tax(x) = x * 8 / 100"`.
+pub fn parse_function(input: &str) -> Result<Function, ParseError> {
+    let mut parser = Parser { tokens: tokenize(input), pos: 0, depth: 0 };
+    let function = parser.function()?;
+    parser.end()?;
+    Ok(function)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(op: Operation, left: Expression, right: Expression) -> Expression {
+        Expression::Op { op, left: Box::new(left), right: Box::new(right) }
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse("42"), Ok(Expression::Value(42)));
+        assert_eq!(parse("  -7 "), Ok(Expression::Value(-7)));
+        assert_eq!(parse("-9223372036854775808"), Ok(Expression::Value(i64::MIN)));
+        assert_eq!(parse("9223372036854775808"), Err(ParseError::LiteralOutOfRange { offset: 0 }));
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        use Expression::Value;
+        assert_eq!(
+            parse("(3 - 4) * 5 + 10 * 9"),
+            Ok(op(
+                Operation::Add,
+                op(Operation::Mul, op(Operation::Sub, Value(3), Value(4)), Value(5)),
+                op(Operation::Mul, Value(10), Value(9)),
+            ))
+        );
+        assert_eq!(
+            parse("1 - 2 - 3"),
+            Ok(op(Operation::Sub, op(Operation::Sub, Value(1), Value(2)), Value(3)))
+        );
+        assert_eq!(
+            parse("8 / 4 / 2"),
+            Ok(op(Operation::Div, op(Operation::Div, Value(8), Value(4)), Value(2)))
+        );
+        assert_eq!(parse("3 - -4"), Ok(op(Operation::Sub, Value(3), Value(-4))));
+    }
+
+    #[test]
+    fn extended_operators() {
+        use Expression::{Value, Var};
+        let unary = |op, operand| Expression::Unary { op, operand: Box::new(operand) };
+        assert_eq!(
+            parse("2 ^ 3 ^ 2"),
+            Ok(op(Operation::Pow, Value(2), op(Operation::Pow, Value(3), Value(2))))
+        );
+        assert_eq!(
+            parse("7 % 4 * 2"),
+            Ok(op(Operation::Mul, op(Operation::Rem, Value(7), Value(4)), Value(2)))
+        );
+        assert_eq!(
+            parse("-x ^ 2"),
+            Ok(op(Operation::Pow, unary(UnaryOperation::Neg, Var(String::from("x"))), Value(2)))
+        );
+        assert_eq!(
+            parse("max(1, -abs(min(2, 3)))"),
+            Ok(op(
+                Operation::Max,
+                Value(1),
+                unary(
+                    UnaryOperation::Neg,
+                    unary(UnaryOperation::Abs, op(Operation::Min, Value(2), Value(3)))
+                )
+            ))
+        );
+        assert_eq!(parse("-(4)"), Ok(unary(UnaryOperation::Neg, Value(4))));
+    }
+
+    #[test]
+    fn conditionals() {
+        use Expression::{Bool, Value, Var};
+        let x = || Var(String::from("x"));
+        assert_eq!(
+            parse("x > 1 || !(x <= 0) && true"),
+            Ok(op(
+                Operation::Or,
+                op(Operation::Gt, x(), Value(1)),
+                op(
+                    Operation::And,
+                    Expression::Unary {
+                        op: UnaryOperation::Not,
+                        operand: Box::new(op(Operation::Le, x(), Value(0)))
+                    },
+                    Bool(true)
+                )
+            ))
+        );
+        assert_eq!(
+            parse("x + 1 == 2 != false"),
+            Ok(op(
+                Operation::Ne,
+                op(Operation::Eq, op(Operation::Add, x(), Value(1)), Value(2)),
+                Bool(false)
+            ))
+        );
+        assert_eq!(
+            parse("if x >= 1 then x else -x + 1"),
+            Ok(Expression::If {
+                cond: Box::new(op(Operation::Ge, x(), Value(1))),
+                then: Box::new(x()),
+                otherwise: Box::new(op(
+                    Operation::Add,
+                    Expression::Unary { op: UnaryOperation::Neg, operand: Box::new(x()) },
+                    Value(1)
+                )),
+            })
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let var = |name: &str| Expression::Var(name.to_string());
+        assert_eq!(
+            parse("unit_cost * qty2"),
+            Ok(op(Operation::Mul, var("unit_cost"), var("qty2")))
+        );
+        assert_eq!(parse("(_x)"), Ok(var("_x")));
+    }
+
+    #[test]
+    fn bindings() {
+        use Expression::Value;
+        let var = |name: &str| Expression::Var(name.to_string());
+        let call = |name: &str, args| Expression::Call { name: name.to_string(), args };
+        assert_eq!(
+            parse("let x = 1 in x + 2"),
+            Ok(Expression::Let {
+                name: String::from("x"),
+                value: Box::new(Value(1)),
+                body: Box::new(op(Operation::Add, var("x"), Value(2))),
+            })
+        );
+        let tax = Function {
+            name: String::from("tax"),
+            params: vec![String::from("x")],
+            body: op(Operation::Div, op(Operation::Mul, var("x"), Value(8)), Value(100)),
+        };
+        assert_eq!(parse_function("fn tax(x) = x * 8 / 100"), Ok(tax.clone()));
+        assert_eq!(
+            parse("fn tax(x) = x * 8 / 100 in price + tax(price)"),
+            Ok(Expression::Define {
+                function: Box::new(tax),
+                body: Box::new(op(Operation::Add, var("price"), call("tax", vec![var("price")]))),
+            })
+        );
+        assert_eq!(
+            parse("f() * g(1, h(x))"),
+            Ok(op(
+                Operation::Mul,
+                call("f", vec![]),
+                call("g", vec![Value(1), call("h", vec![var("x")])])
+            ))
+        );
+        assert_eq!(
+            parse_function("fn zero() = 0"),
+            Ok(Function { name: String::from("zero"), params: vec![], body: Value(0) })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let unexpected = |offset, expected| ParseError::Unexpected { offset, expected };
+        assert_eq!(parse(""), Err(unexpected(0, Expected::Operand)));
+        assert_eq!(parse("1 +"), Err(unexpected(3, Expected::Operand)));
+        assert_eq!(parse("1 + * 2"), Err(unexpected(4, Expected::Operand)));
+        assert_eq!(parse("(1 + 2"), Err(unexpected(6, Expected::ClosingParen)));
+        assert_eq!(parse("1 2"), Err(unexpected(2, Expected::EndOfInput)));
+        assert_eq!(parse("1 $ 2"), Err(unexpected(2, Expected::EndOfInput)));
+        assert_eq!(parse("- )"), Err(unexpected(2, Expected::Operand)));
+        assert_eq!(parse("min(1)"), Err(unexpected(5, Expected::Comma)));
+        assert_eq!(parse("abs(1, 2)"), Err(unexpected(5, Expected::ClosingParen)));
+        assert_eq!(parse("x = 1"), Err(unexpected(2, Expected::EndOfInput)));
+        assert_eq!(parse("x & y"), Err(unexpected(2, Expected::EndOfInput)));
+        assert_eq!(parse("if x 1 else 2"), Err(unexpected(5, Expected::Then)));
+        assert_eq!(parse("if x then 1"), Err(unexpected(11, Expected::Else)));
+        assert_eq!(parse("else + 1"), Err(unexpected(0, Expected::Operand)));
+        assert_eq!(parse("let = 1 in 2"), Err(unexpected(4, Expected::Name)));
+        assert_eq!(parse("let if = 1 in 2"), Err(unexpected(4, Expected::Name)));
+        assert_eq!(parse("let x 1 in x"), Err(unexpected(6, Expected::Assign)));
+        assert_eq!(parse("let x = 1; x"), Err(unexpected(9, Expected::In)));
+        assert_eq!(parse("fn f x = x in 1"), Err(unexpected(5, Expected::OpeningParen)));
+        assert_eq!(parse("fn f(x y) = x in 1"), Err(unexpected(7, Expected::ClosingParen)));
+        assert_eq!(parse("fn f(x,) = x in 1"), Err(unexpected(7, Expected::Name)));
+        assert_eq!(parse("fn f(x) x in 1"), Err(unexpected(8, Expected::Assign)));
+        assert_eq!(parse("fn max(a, b) = a in 1"), Err(unexpected(3, Expected::Name)));
+        assert_eq!(parse("f(1, 2"), Err(unexpected(6, Expected::ClosingParen)));
+        assert_eq!(parse("in"), Err(unexpected(0, Expected::Operand)));
+        assert_eq!(
+            parse("fn f(x, y, x) = x in 1"),
+            Err(ParseError::DuplicateParameter { offset: 11, name: String::from("x") })
+        );
+        assert_eq!(parse_function("f(x) = x"), Err(unexpected(0, Expected::Fn)));
+        assert_eq!(parse_function("fn f(x) = x in 1"), Err(unexpected(12, Expected::EndOfInput)));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let parens = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(&parens(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(parse(&parens(MAX_DEPTH)), Err(ParseError::TooDeep { offset: MAX_DEPTH }));
+        assert_eq!(parse(&"(".repeat(200_000)), Err(ParseError::TooDeep { offset: MAX_DEPTH }));
+        assert!(matches!(parse(&"-!".repeat(100_000)), Err(ParseError::TooDeep { .. })));
+        assert!(matches!(parse(&"2 ^ ".repeat(100_000)), Err(ParseError::TooDeep { .. })));
+        assert_eq!(
+            parse(&"(".repeat(MAX_DEPTH + 1)).unwrap_err().to_string(),
+            format!("expression nested more than {MAX_DEPTH} deep at offset {MAX_DEPTH}")
+        );
+    }
+}
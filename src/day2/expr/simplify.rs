@@ -0,0 +1,357 @@
+use super::{Expression, Number, Operation, UnaryOperation};
+
+/// Simplify an expression bottom-up.
+///
+/// Constant subtrees are folded, the identities `x + 0`, `x - 0`, `x * 1` and
+/// `x / 1` are applied, and the operands of `+` and `*` are put in a
+/// canonical order (variables first, constants last). `&&`, `||` and `if`
+/// are decided early if their first operand is constant, and `x && true` and
+/// `x || false` become `x`, assuming the expression passes `typecheck` so
+/// that `x` is a boolean.
+///
+/// Simplification never removes a failure: an operation whose evaluation
+/// fails, such as `1 / 0` or an overflowing sum, is left in the tree. So
+/// `x * 0` and `x - x` are only folded when `x` is a literal, since a
+/// variable may be unbound or, in floating point, infinite, which makes them
+/// fail rather than be `0`. Operands are only reordered if the error
+/// reported first stays the same, assuming variables are bound.
+///
+/// The tree is walked with an explicit stack rather than recursion, so
+/// arbitrarily deep trees can be simplified.
+pub fn simplify<N: Number>(e: Expression<N>) -> Expression<N> {
+    let mut tasks = vec![Task::Simplify(e)];
+    let mut done = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Simplify(mut e) => match &mut e {
+                Expression::Op { left, right, .. } => {
+                    let (left, right) = (left.take(), right.take());
+                    tasks.extend([Task::Rebuild(e), Task::Simplify(right), Task::Simplify(left)]);
+                }
+                Expression::Unary { operand, .. } => {
+                    let operand = operand.take();
+                    tasks.extend([Task::Rebuild(e), Task::Simplify(operand)]);
+                }
+                Expression::If { cond, .. } => {
+                    let cond = cond.take();
+                    tasks.extend([Task::Branch(e), Task::Simplify(cond)]);
+                }
+                // Bound names are never substituted, so only the parts are
+                // simplified.
+                Expression::Let { value, body, .. } => {
+                    let (value, body) = (value.take(), body.take());
+                    tasks.extend([Task::Rebuild(e), Task::Simplify(body), Task::Simplify(value)]);
+                }
+                Expression::Define { function, body } => {
+                    let (function_body, body) = (function.body.take(), body.take());
+                    tasks.extend([
+                        Task::Rebuild(e),
+                        Task::Simplify(body),
+                        Task::Simplify(function_body),
+                    ]);
+                }
+                Expression::Call { args, .. } => {
+                    let args: Vec<_> = args.iter_mut().map(Expression::take).collect();
+                    tasks.push(Task::Rebuild(e));
+                    tasks.extend(args.into_iter().rev().map(Task::Simplify));
+                }
+                Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => done.push(e),
+            },
+            Task::Branch(mut e) => {
+                let Expression::If { cond, then, otherwise } = &mut e else {
+                    unreachable!("only an `if` branches")
+                };
+                match done.pop().expect("missing condition") {
+                    Expression::Bool(true) => tasks.push(Task::Simplify(then.take())),
+                    Expression::Bool(false) => tasks.push(Task::Simplify(otherwise.take())),
+                    simplified => {
+                        **cond = simplified;
+                        let (then, otherwise) = (then.take(), otherwise.take());
+                        tasks.extend([
+                            Task::Rebuild(e),
+                            Task::Simplify(otherwise),
+                            Task::Simplify(then),
+                        ]);
+                    }
+                }
+            }
+            Task::Rebuild(mut e) => {
+                let rebuilt = match &mut e {
+                    Expression::Op { op, .. } => {
+                        let right = done.pop().expect("missing right operand");
+                        let left = done.pop().expect("missing left operand");
+                        simplify_op(op.clone(), left, right)
+                    }
+                    Expression::Unary { op, .. } => {
+                        simplify_unary(op.clone(), done.pop().expect("missing operand"))
+                    }
+                    Expression::If { then, otherwise, .. } => {
+                        **otherwise = done.pop().expect("missing else branch");
+                        **then = done.pop().expect("missing then branch");
+                        e
+                    }
+                    Expression::Let { value, body, .. } => {
+                        **body = done.pop().expect("missing body");
+                        **value = done.pop().expect("missing bound value");
+                        e
+                    }
+                    Expression::Define { function, body } => {
+                        **body = done.pop().expect("missing body");
+                        function.body = done.pop().expect("missing function body");
+                        e
+                    }
+                    Expression::Call { args, .. } => {
+                        let simplified = done.split_off(done.len() - args.len());
+                        *args = simplified;
+                        e
+                    }
+                    Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => {
+                        unreachable!("leaves are not rebuilt")
+                    }
+                };
+                done.push(rebuilt);
+            }
+        }
+    }
+    done.pop().expect("no expression left")
+}
+
+/// A pending step of `simplify`.
+enum Task<N> {
+    /// Simplify the expression and push the result.
+    Simplify(Expression<N>),
+    /// Pop the simplified condition of an `If`, whose condition has been
+    /// taken out, and decide what to simplify next.
+    Branch(Expression<N>),
+    /// Pop the simplified children of the expression, which have been taken
+    /// out of it, and push it back together.
+    Rebuild(Expression<N>),
+}
+
+/// Whether evaluating `e` can never fail (given its variables are bound).
+fn cannot_fail<N>(e: &Expression<N>) -> bool {
+    matches!(e, Expression::Value(_) | Expression::Bool(_) | Expression::Var(_))
+}
+
+/// The value of `e` if it is a literal number or boolean.
+fn constant<N: Number>(e: &Expression<N>) -> Option<N> {
+    match e {
+        Expression::Value(v) => Some(v.clone()),
+        Expression::Bool(b) => Some(N::from_bool(*b)),
+        _ => None,
+    }
+}
+
+/// A literal of the type the operation produces.
+fn literal<N: Number>(value: N, boolean: bool) -> Expression<N> {
+    if boolean {
+        Expression::Bool(value.is_true())
+    } else {
+        Expression::Value(value)
+    }
+}
+
+/// Whether `e` is the literal `value`.
+fn is_literal<N: Number>(e: &Expression<N>, value: i64) -> bool {
+    matches!(e, Expression::Value(v) if *v == N::from_i64(value))
+}
+
+/// Position of an operand in the canonical order of commutative operations.
+fn rank<N>(e: &Expression<N>) -> u8 {
+    match e {
+        Expression::Var(_) => 0,
+        Expression::Op { .. }
+        | Expression::Unary { .. }
+        | Expression::If { .. }
+        | Expression::Let { .. }
+        | Expression::Define { .. }
+        | Expression::Call { .. } => 1,
+        Expression::Value(_) | Expression::Bool(_) => 2,
+    }
+}
+
+fn simplify_op<N: Number>(
+    op: Operation,
+    left: Expression<N>,
+    right: Expression<N>,
+) -> Expression<N> {
+    if let Some(l) = constant(&left) {
+        // Only the left operand is evaluated if it decides the result.
+        if let Some(v) = op.short_circuit(&l) {
+            return literal(v, true);
+        }
+        if let Some(r) = constant(&right) {
+            if let Ok(v) = op.apply(&l, &r) {
+                return literal(v, op.is_boolean());
+            }
+        }
+    }
+
+    match op {
+        Operation::Add | Operation::Sub if is_literal(&right, 0) => return left,
+        Operation::Add if is_literal(&left, 0) => return right,
+        Operation::Mul | Operation::Div if is_literal(&right, 1) => return left,
+        Operation::Mul if is_literal(&left, 1) => return right,
+        Operation::And if right == Expression::Bool(true) => return left,
+        Operation::And if left == Expression::Bool(true) => return right,
+        Operation::Or if right == Expression::Bool(false) => return left,
+        Operation::Or if left == Expression::Bool(false) => return right,
+        _ => {}
+    }
+
+    // Swapping two operands that can both fail would change which error is
+    // reported first, so at least one of them must be infallible.
+    let (left, right) = match (&op, &left, &right) {
+        (Operation::Add | Operation::Mul, l, r)
+            if (cannot_fail(l) || cannot_fail(r)) && out_of_order(l, r) =>
+        {
+            (right, left)
+        }
+        _ => (left, right),
+    };
+    Expression::Op { op, left: Box::new(left), right: Box::new(right) }
+}
+
+fn simplify_unary<N: Number>(op: UnaryOperation, operand: Expression<N>) -> Expression<N> {
+    if let Some(v) = constant(&operand) {
+        if let Ok(v) = op.apply(&v) {
+            return literal(v, op == UnaryOperation::Not);
+        }
+    }
+    Expression::Unary { op, operand: Box::new(operand) }
+}
+
+fn out_of_order<N>(left: &Expression<N>, right: &Expression<N>) -> bool {
+    match (left, right) {
+        (Expression::Var(l), Expression::Var(r)) => l > r,
+        _ => rank(left) > rank(right),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{eval_with, parse, Env, EvalError};
+
+    fn simplified(input: &str) -> Expression {
+        simplify(parse(input).unwrap())
+    }
+
+    #[test]
+    fn folds_constants() {
+        assert_eq!(simplified("(3 - 4) * 5 + 10 * 9"), Expression::Value(85));
+        assert_eq!(simplified("x * (2 + 3)"), parse("x * 5").unwrap());
+        assert_eq!(simplified("max(2 ^ 3, -abs(-9)) % 5"), Expression::Value(3));
+        assert_eq!(simplified("1 + 2 == 3 && !(4 < 3)"), Expression::Bool(true));
+        assert_eq!(simplified("if 2 > 1 then x else y"), parse("x").unwrap());
+        assert_eq!(simplified("false && 1 / 0 == 0"), Expression::Bool(false));
+        assert_eq!(simplified("1 > 0 || x"), Expression::Bool(true));
+        assert_eq!(simplified("x > 0 || 1 > 0"), parse("x > 0 || true").unwrap());
+        assert_eq!(simplified("x > 0 && 1 > 0"), parse("x > 0").unwrap());
+        assert_eq!(simplified("1 < 0 || x > 0"), parse("x > 0").unwrap());
+    }
+
+    #[test]
+    fn identities() {
+        assert_eq!(simplified("x + 0"), parse("x").unwrap());
+        assert_eq!(simplified("0 + x"), parse("x").unwrap());
+        assert_eq!(simplified("x - (2 - 2)"), parse("x").unwrap());
+        assert_eq!(simplified("1 * x"), parse("x").unwrap());
+        assert_eq!(simplified("x / 1"), parse("x").unwrap());
+        assert_eq!(simplified("(x + 1) * 1"), parse("x + 1").unwrap());
+        // Inside bindings too, but bound names are left alone.
+        assert_eq!(simplified("let y = x * 1 in y + 0"), parse("let y = x in y").unwrap());
+        assert_eq!(
+            simplified("fn f(x) = 0 + x in f(2 * 3) * 1"),
+            parse("fn f(x) = x in f(6)").unwrap()
+        );
+    }
+
+    #[test]
+    fn canonical_order() {
+        assert_eq!(simplified("3 + x"), parse("x + 3").unwrap());
+        assert_eq!(simplified("b * a"), parse("a * b").unwrap());
+        assert_eq!(simplified("(a - b) * c"), parse("c * (a - b)").unwrap());
+        assert_eq!(simplified("3 - x"), parse("3 - x").unwrap());
+    }
+
+    #[test]
+    fn keeps_failures() {
+        assert_eq!(simplified("1 / 0"), parse("1 / 0").unwrap());
+        assert_eq!(simplified("(1 / 0) * 0"), parse("(1 / 0) * 0").unwrap());
+        assert_eq!(
+            simplified("9223372036854775807 + 1"),
+            parse("9223372036854775807 + 1").unwrap()
+        );
+        assert_eq!(simplified("(x * x) - (x * x)"), parse("(x * x) - (x * x)").unwrap());
+        // `x` may be unbound.
+        assert_eq!(simplified("x * 0"), parse("x * 0").unwrap());
+        assert_eq!(simplified("(y * 0) + (x - x)"), parse("(y * 0) + (x - x)").unwrap());
+        assert_eq!(simplified("(a / 0) + (b * c)"), parse("(a / 0) + (b * c)").unwrap());
+        assert_eq!(simplified("2 ^ -1"), parse("2 ^ -1").unwrap());
+        assert_eq!(simplified("x % 0"), parse("x % 0").unwrap());
+        assert_eq!(
+            simplified("-(-9223372036854775808)"),
+            parse("-(-9223372036854775808)").unwrap()
+        );
+    }
+
+    #[test]
+    fn other_backends() {
+        use crate::expr::Rational;
+        let e = parse("x * (7 / 2) + 0").unwrap();
+        assert_eq!(simplify(e.cast::<Rational>()).to_string(), "x * (7/2)");
+        assert_eq!(simplify(e.cast::<f64>()).to_string(), "x * (3.5)");
+        assert_eq!(simplify(e).to_string(), "x * 3");
+        // `inf * 0` and `inf - inf` are not numbers.
+        let env = Env::from([(String::from("x"), f64::INFINITY)]);
+        for input in ["x * 0", "x - x"] {
+            let e = simplify(parse(input).unwrap().cast::<f64>());
+            assert!(matches!(eval_with(&e, &env), Err(EvalError::Overflow(_))), "{input}");
+        }
+    }
+
+    #[test]
+    fn preserves_results() {
+        let env = Env::from([(String::from("x"), 6), (String::from("y"), -4)]);
+        for input in [
+            "(x + 0) * (1 * y) - (3 - 3)",
+            "2 * x + y * 0 + (y - y)",
+            "(x / (y + 4)) * 0",
+            "(9223372036854775807 + x) * 1",
+            "if x > y && 3 > 2 then x * 1 else (y - y) / 0",
+            "y < 0 || 1 / 0 > 0",
+            "let y = x * 1 in y + 0 - (y - y)",
+            "fn f(x) = x * 1 + 0 in f(y) * (2 + 1)",
+            "z * 0",
+            "0 * z + (z - z)",
+        ] {
+            let e = parse(input).unwrap();
+            let before = eval_with(&e, &env).map_err(|err| std::mem::discriminant(&err));
+            let after = eval_with(&simplify(e), &env).map_err(|err| std::mem::discriminant(&err));
+            assert_eq!(before, after, "{input}");
+        }
+    }
+    #[test]
+    fn deep_trees() {
+        // `1 + (1 + (... + x))`, which becomes `((x + 1) + 1) + ...`.
+        let mut e = parse("x").unwrap();
+        for _ in 0..100_000 {
+            e = Expression::Op {
+                op: Operation::Add,
+                left: Box::new(Expression::Value(1)),
+                right: Box::new(e),
+            };
+        }
+        let e = simplify(e);
+        assert!(matches!(&e, Expression::Op { right, .. } if **right == Expression::Value(1)));
+        let env = Env::from([(String::from("x"), 1)]);
+        assert_eq!(eval_with(&e, &env), Ok(100_001));
+
+        let mut e: Expression = Expression::Bool(true);
+        for _ in 0..100_001 {
+            e = Expression::Unary { op: UnaryOperation::Not, operand: Box::new(e) };
+        }
+        assert_eq!(simplify(e), Expression::Bool(false));
+    }
+}
@@ -0,0 +1,543 @@
+use std::fmt::{self, Write};
+
+use super::{evaluate, Env, EvalError, Expression, Function, Number, Observer, Operation};
+
+/// The evaluation of one node of the traced expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step<'a, N = i64> {
+    /// Distance from the root of the expression.
+    pub depth: usize,
+    pub expr: &'a Expression<N>,
+    /// The values the node's operation was applied to: both operands of a
+    /// binary operation, the operand of a unary one, just the condition
+    /// or left operand of a branch that did not need the rest, the value
+    /// bound by a `let` or the arguments of a call.
+    pub operands: Vec<N>,
+    /// The node's value, or `None` if evaluating it failed.
+    pub value: Option<N>,
+    /// The steps the operands came from.
+    operand_steps: Vec<usize>,
+    /// Whether the value is a boolean, assuming the expression is well
+    /// typed. Variables are taken to be numbers.
+    boolean: bool,
+}
+
+/// A record of how an expression was evaluated, node by node.
+///
+/// The steps are in pre-order, so each node comes before its operands, and
+/// only contain the nodes that were evaluated. `Display` renders them as an
+/// indented explanation of where every value came from, ending with the
+/// error if there was one; [`explain`](Trace::explain) renders part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace<'a, N = i64> {
+    steps: Vec<Step<'a, N>>,
+    result: Result<N, EvalError<N>>,
+    /// The step that caused the error, and how.
+    culprit: Option<(usize, &'static str)>,
+}
+
+/// Records the steps of `evaluate`.
+struct Recorder<'a, N> {
+    steps: Vec<Step<'a, N>>,
+    /// The steps entered and not left yet, innermost last, each with the
+    /// steps nested in it that have been left since it was entered or last
+    /// applied.
+    open: Vec<(usize, Vec<usize>)>,
+}
+
+impl<'a, N: Number> Observer<'a, N> for Recorder<'a, N> {
+    fn enter(&mut self, e: &'a Expression<N>) {
+        self.open.push((self.steps.len(), Vec::new()));
+        self.steps.push(Step {
+            depth: self.open.len() - 1,
+            expr: e,
+            operands: Vec::new(),
+            value: None,
+            operand_steps: Vec::new(),
+            boolean: false,
+        });
+    }
+
+    fn apply(&mut self, operands: &[N]) {
+        let (step, nested) = self.open.last_mut().expect("no step entered");
+        let step = &mut self.steps[*step];
+        step.operands = operands.to_vec();
+        step.operand_steps = std::mem::take(nested);
+    }
+
+    fn leave(&mut self, value: &N) {
+        let (step, nested) = self.open.pop().expect("no step entered");
+        let boolean = match self.steps[step].expr {
+            // These take the value of the branch or body evaluated last.
+            Expression::If { .. }
+            | Expression::Let { .. }
+            | Expression::Define { .. }
+            | Expression::Call { .. } => {
+                nested.last().is_some_and(|&last| self.steps[last].boolean)
+            }
+            e => e.is_boolean(),
+        };
+        self.steps[step].value = Some(value.clone());
+        self.steps[step].boolean = boolean;
+        if let Some((_, nested)) = self.open.last_mut() {
+            nested.push(step);
+        }
+    }
+}
+
+/// Evaluate an expression like `eval_with`, recording every step.
+pub fn trace<'a, N: Number>(e: &'a Expression<N>, env: &Env<N>) -> Trace<'a, N> {
+    let mut recorder = Recorder { steps: Vec::new(), open: Vec::new() };
+    let result = evaluate(e, env, &mut recorder);
+    let steps = recorder.steps;
+    // The step that failed is the innermost one still open.
+    let culprit = match (&result, recorder.open.last()) {
+        (Err(err), Some(&(step, _))) => Some(blame(&steps, step, err)),
+        _ => None,
+    };
+    Trace { steps, result, culprit }
+}
+
+/// The step to blame for `step` failing with `err`, and why: the operand
+/// that made it fail if there is one, or else the step itself.
+fn blame<N>(steps: &[Step<'_, N>], step: usize, err: &EvalError<N>) -> (usize, &'static str) {
+    let operand = |i: usize| steps[step].operand_steps[i];
+    match (steps[step].expr, err) {
+        (
+            Expression::Op { op: Operation::Div | Operation::Rem, .. },
+            EvalError::DivisionByZero(_),
+        ) => (operand(1), "the divisor is zero"),
+        (Expression::Op { op: Operation::Pow, .. }, EvalError::DivisionByZero(_)) => {
+            (operand(0), "the base is zero")
+        }
+        (Expression::Op { op: Operation::Pow, .. }, EvalError::NegativeExponent(_)) => {
+            (operand(1), "the exponent is negative")
+        }
+        (Expression::Op { op: Operation::Pow, .. }, EvalError::NonIntegerExponent(_)) => {
+            (operand(1), "the exponent is not an integer")
+        }
+        (_, EvalError::UnboundVariable(_)) => (step, "this variable is unbound"),
+        (_, EvalError::UnknownFunction(_)) => (step, "this function is unknown"),
+        (_, EvalError::Arity { .. }) => (step, "the number of arguments is wrong"),
+        (_, EvalError::RecursionLimit(_)) => (step, "calls nest too deep"),
+        _ => (step, "the result overflows"),
+    }
+}
+
+impl<'a, N> Trace<'a, N> {
+    /// The evaluated nodes, in pre-order.
+    pub fn steps(&self) -> &[Step<'a, N>] {
+        &self.steps
+    }
+
+    /// What `eval_with` returns for the expression.
+    pub fn result(&self) -> &Result<N, EvalError<N>> {
+        &self.result
+    }
+
+    /// The subtree that caused the error, if evaluation failed.
+    pub fn culprit(&self) -> Option<&Step<'a, N>> {
+        self.culprit.map(|(step, _)| &self.steps[step])
+    }
+
+    /// The explanation `Display` writes, but only of the step `root` and
+    /// the steps nested in it, indented from `root`. For an expression
+    /// wrapped in `n` definitions, such as `fn f(x) = x + 1 in f(2)`, step
+    /// `n` is the expression inside them.
+    ///
+    /// Panics if there is no such step.
+    pub fn explain(&self, root: usize) -> Explanation<'_, 'a, N> {
+        assert!(root < self.steps.len(), "no step {root} in the trace");
+        Explanation { trace: self, root }
+    }
+}
+
+/// Part of a [`Trace`] to write out, made by [`Trace::explain`].
+pub struct Explanation<'t, 'a, N> {
+    trace: &'t Trace<'a, N>,
+    root: usize,
+}
+
+/// The most a step is indented, in spaces. Steps nested deeper line up with
+/// the ones at this depth.
+const MAX_INDENT: usize = 80;
+
+/// The most of an expression that a step shows, in bytes.
+const SUMMARY_LEN: usize = 60;
+
+/// Collects text up to `SUMMARY_LEN`, then fails so the rest isn't written.
+struct Summary(String);
+
+impl Write for Summary {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.push_str(s);
+        if self.0.len() > SUMMARY_LEN {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The most nodes of an expression that a step shows, nearest the root
+/// first.
+const SUMMARY_NODES: usize = 2 * SUMMARY_LEN;
+
+/// The text of `e`, cut short with `...` if it is longer than
+/// `SUMMARY_LEN`, so that every step takes the same time to write whatever
+/// the size of its subtree. Only the levels of `e` that hold up to
+/// `SUMMARY_NODES` nodes are looked at, and deeper subtrees are written as
+/// `...`.
+fn summary<N: Number>(e: &Expression<N>) -> String {
+    let mut summary = Summary(String::new());
+    if write!(summary, "{}", pruned(e, shown_levels(e))).is_err() {
+        let mut end = SUMMARY_LEN;
+        while !summary.0.is_char_boundary(end) {
+            end -= 1;
+        }
+        summary.0.truncate(end);
+        summary.0.push_str("...");
+    }
+    summary.0
+}
+
+/// How many levels below the root of `e` fit in `SUMMARY_NODES`, found a
+/// level at a time without walking the rest of the tree.
+fn shown_levels<N>(e: &Expression<N>) -> usize {
+    let mut level = vec![e];
+    let mut seen = 1;
+    for depth in 0.. {
+        let mut next = Vec::new();
+        for e in level {
+            next.extend(children(e).take(SUMMARY_NODES + 1 - seen - next.len()));
+            if seen + next.len() > SUMMARY_NODES {
+                return depth;
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        seen += next.len();
+        level = next;
+    }
+    usize::MAX
+}
+
+fn children<N>(e: &Expression<N>) -> Box<dyn Iterator<Item = &Expression<N>> + '_> {
+    match e {
+        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => {
+            Box::new(std::iter::empty())
+        }
+        Expression::Unary { operand, .. } => Box::new(std::iter::once(&**operand)),
+        Expression::Op { left, right, .. } => Box::new([&**left, right].into_iter()),
+        Expression::If { cond, then, otherwise } => {
+            Box::new([&**cond, then, otherwise].into_iter())
+        }
+        Expression::Let { value, body, .. } => Box::new([&**value, body].into_iter()),
+        Expression::Define { function, body } => Box::new([&function.body, &**body].into_iter()),
+        Expression::Call { args, .. } => Box::new(args.iter()),
+    }
+}
+
+/// A copy of `e` down to `levels` below its root, with the subtrees below
+/// that replaced by `...`.
+fn pruned<N: Number>(e: &Expression<N>, levels: usize) -> Expression<N> {
+    let below = |e: &Expression<N>| Box::new(pruned(e, levels - 1));
+    match e {
+        Expression::Value(_) | Expression::Bool(_) | Expression::Var(_) => e.clone(),
+        _ if levels == 0 => Expression::Var(String::from("...")),
+        Expression::Unary { op, operand } => {
+            Expression::Unary { op: op.clone(), operand: below(operand) }
+        }
+        Expression::Op { op, left, right } => {
+            Expression::Op { op: op.clone(), left: below(left), right: below(right) }
+        }
+        Expression::If { cond, then, otherwise } => {
+            Expression::If { cond: below(cond), then: below(then), otherwise: below(otherwise) }
+        }
+        Expression::Let { name, value, body } => {
+            Expression::Let { name: name.clone(), value: below(value), body: below(body) }
+        }
+        Expression::Define { function, body } => Expression::Define {
+            function: Box::new(Function {
+                name: function.name.clone(),
+                params: function.params.clone(),
+                body: *below(&function.body),
+            }),
+            body: below(body),
+        },
+        Expression::Call { name, args } => {
+            Expression::Call { name: name.clone(), args: args.iter().map(|a| *below(a)).collect() }
+        }
+    }
+}
+
+impl<N: Number> Trace<'_, N> {
+    /// The value of a step as a literal of its type.
+    fn literal(&self, step: usize, value: &N) -> Expression<N> {
+        if self.steps[step].boolean {
+            Expression::Bool(value.is_true())
+        } else {
+            Expression::Value(value.clone())
+        }
+    }
+
+    /// The operation of a step applied to its operands' values, such as
+    /// `3 * 5` for `price * (qty + 1)`.
+    fn applied(&self, step: &Step<'_, N>) -> Option<Expression<N>> {
+        let mut operands = step
+            .operand_steps
+            .iter()
+            .zip(&step.operands)
+            .map(|(&operand, value)| self.literal(operand, value));
+        match step.expr {
+            Expression::Op { op, .. } if step.operands.len() == 2 => Some(Expression::Op {
+                op: op.clone(),
+                left: Box::new(operands.next()?),
+                right: Box::new(operands.next()?),
+            }),
+            Expression::Unary { op, .. } => {
+                Some(Expression::Unary { op: op.clone(), operand: Box::new(operands.next()?) })
+            }
+            Expression::Call { name, args } if step.operands.len() == args.len() => {
+                Some(Expression::Call { name: name.clone(), args: operands.collect() })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<N: Number> fmt::Display for Explanation<'_, '_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let trace = self.trace;
+        let root = &trace.steps[self.root];
+        let subtree =
+            trace.steps[self.root + 1..].iter().take_while(|step| step.depth > root.depth);
+        for (i, step) in (self.root..).zip(std::iter::once(root).chain(subtree)) {
+            let culprit = trace.culprit.filter(|&(culprit, _)| culprit == i);
+            // Literals explain themselves.
+            if matches!(step.expr, Expression::Value(_) | Expression::Bool(_)) && culprit.is_none()
+            {
+                continue;
+            }
+            let expr = summary(step.expr);
+            let indent = (2 * (step.depth - root.depth)).min(MAX_INDENT);
+            write!(f, "{:indent$}{expr}", "")?;
+            if let Some(applied) = trace.applied(step).map(|e| summary(&e)) {
+                if applied != expr {
+                    write!(f, " = {applied}")?;
+                }
+            }
+            match &step.value {
+                Some(value) => write!(f, " = {}", trace.literal(i, value))?,
+                None => write!(f, " failed")?,
+            }
+            if matches!(step.expr, Expression::Op { .. }) && step.operands.len() == 1 {
+                write!(f, " (short-circuited)")?;
+            }
+            if let Some((_, reason)) = culprit {
+                write!(f, "  <- {reason}")?;
+            }
+            writeln!(f)?;
+        }
+        if let (Err(err), None) = (&trace.result, &root.value) {
+            writeln!(f, "error: {err}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<N: Number> fmt::Display for Trace<'_, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.explain(0).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::{eval, eval_with, parse};
+
+    fn env(vars: &[(&str, i64)]) -> Env {
+        vars.iter().map(|&(name, value)| (name.to_string(), value)).collect()
+    }
+
+    #[test]
+    fn records_steps() {
+        let e = parse("price * (qty + 1)").unwrap();
+        let traced = trace(&e, &env(&[("price", 3), ("qty", 4)]));
+        assert_eq!(traced.result(), &Ok(15));
+        let steps: Vec<_> = traced
+            .steps()
+            .iter()
+            .map(|step| (step.depth, step.expr.to_string(), step.operands.clone(), step.value))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (0, String::from("price * (qty + 1)"), vec![3, 5], Some(15)),
+                (1, String::from("price"), vec![], Some(3)),
+                (1, String::from("qty + 1"), vec![4, 1], Some(5)),
+                (2, String::from("qty"), vec![], Some(4)),
+                (2, String::from("1"), vec![], Some(1)),
+            ]
+        );
+        assert_eq!(
+            traced.to_string(),
+            "price * (qty + 1) = 3 * 5 = 15\n  price = 3\n  qty + 1 = 4 + 1 = 5\n    qty = 4\n"
+        );
+    }
+
+    #[test]
+    fn explains_branches() {
+        let e = parse("if price > 100 || member then price * 9 / 10 else -price").unwrap();
+        let traced = trace(&e, &env(&[("price", 120), ("member", 0)]));
+        assert_eq!(
+            traced.to_string(),
+            "\
+if price > 100 || member then price * 9 / 10 else -price = 108
+  price > 100 || member = true (short-circuited)
+    price > 100 = 120 > 100 = true
+      price = 120
+  price * 9 / 10 = 1080 / 10 = 108
+    price * 9 = 120 * 9 = 1080
+      price = 120
+"
+        );
+    }
+
+    #[test]
+    fn points_at_the_culprit() {
+        let e = parse("1 + 2 * (7 / (a - a))").unwrap();
+        let traced = trace(&e, &env(&[("a", 3)]));
+        assert_eq!(traced.result(), &eval_with(&e, &env(&[("a", 3)])));
+        assert_eq!(traced.culprit().unwrap().expr, &parse("a - a").unwrap());
+        assert_eq!(
+            traced.to_string(),
+            "\
+1 + 2 * (7 / (a - a)) failed
+  2 * (7 / (a - a)) failed
+    7 / (a - a) = 7 / 0 failed
+      a - a = 3 - 3 = 0  <- the divisor is zero
+        a = 3
+        a = 3
+error: division by zero in `7 / (a - a)`
+"
+        );
+
+        for (input, culprit) in [
+            ("2 ^ (1 - x)", "1 - x"),
+            ("x * 9223372036854775807", "x * 9223372036854775807"),
+            ("-(x - 9223372036854775807 - 3)", "-(x - 9223372036854775807 - 3)"),
+            ("x + y", "y"),
+        ] {
+            let e = parse(input).unwrap();
+            let traced = trace(&e, &env(&[("x", 2)]));
+            assert_eq!(traced.culprit().unwrap().expr, &parse(culprit).unwrap(), "{input}");
+        }
+    }
+
+    #[test]
+    fn follows_calls() {
+        let e = parse("fn tax(x) = x * 8 / 100 in let p = price + 5 in p + tax(p)").unwrap();
+        let traced = trace(&e, &env(&[("price", 120)]));
+        assert_eq!(traced.result(), &Ok(135));
+        assert_eq!(
+            traced.to_string(),
+            "\
+fn tax(x) = x * 8 / 100 in let p = price + 5 in p + tax(p) = 135
+  let p = price + 5 in p + tax(p) = 135
+    price + 5 = 120 + 5 = 125
+      price = 120
+    p + tax(p) = 125 + 10 = 135
+      p = 125
+      tax(p) = tax(125) = 10
+        p = 125
+        x * 8 / 100 = 1000 / 100 = 10
+          x * 8 = 125 * 8 = 1000
+            x = 125
+"
+        );
+
+        for (input, culprit) in [
+            ("fn f(x) = 1 / x in f(2) + f(0)", "x"),
+            ("f(1)", "f(1)"),
+            ("fn f(x) = x in f(1, 2)", "f(1, 2)"),
+            ("fn f(x) = f(x + 1) in f(1)", "f(x + 1)"),
+            ("let x = 1 in y", "y"),
+        ] {
+            let e = parse(input).unwrap();
+            let traced = trace(&e, &env(&[]));
+            assert_eq!(traced.result(), &eval(&e), "{input}");
+            assert_eq!(traced.culprit().unwrap().expr, &parse(culprit).unwrap(), "{input}");
+        }
+    }
+
+    #[test]
+    fn explains_part_of_a_trace() {
+        let e = parse("fn pos(x) = x > 0 in fn neg(x) = -x in pos(neg(n))").unwrap();
+        let traced = trace(&e, &env(&[("n", 4)]));
+        assert_eq!(traced.result(), &Ok(0));
+        // Steps 0 and 1 are the definitions.
+        assert_eq!(
+            traced.explain(2).to_string(),
+            "\
+pos(neg(n)) = pos(-4) = false
+  neg(n) = neg(4) = -4
+    n = 4
+    -x = -(4) = -4
+      x = 4
+  x > 0 = -4 > 0 = false
+    x = -4
+"
+        );
+        assert_eq!(traced.explain(4).to_string(), "n = 4\n");
+    }
+
+    #[test]
+    fn long_expressions_are_cut_short() {
+        let x = || Box::new(Expression::Var(String::from("x")));
+        let mut e = *x();
+        for _ in 0..1000 {
+            e = Expression::Op { op: Operation::Add, left: x(), right: Box::new(e) };
+        }
+        let explained = trace(&e, &env(&[("x", 1)])).to_string();
+        let first = explained.lines().next().unwrap();
+        assert_eq!(first, format!("{}... = 1 + 1000 = 1001", "x + (".repeat(12)));
+        assert_eq!(explained.lines().count(), 2001);
+    }
+
+    #[test]
+    fn deep_trees() {
+        // `x + 1 + 1 + ...`, `n` deep.
+        let sum = |n: usize| {
+            let mut e = Expression::Var(String::from("x"));
+            for _ in 0..n {
+                e = Expression::Op {
+                    op: Operation::Add,
+                    left: Box::new(e),
+                    right: Box::new(Expression::Value(1)),
+                };
+            }
+            e
+        };
+        let e = sum(20_000);
+        let explained = trace(&e, &env(&[("x", 0)])).to_string();
+        let lines: Vec<_> = explained.lines().collect();
+        assert_eq!(lines.len(), 20_001);
+        assert_eq!(lines[0], format!("...{} ... = 19999 + 1 = 20000", " + 1".repeat(14)));
+        assert_eq!(lines[20_000], format!("{}x = 0", " ".repeat(MAX_INDENT)));
+
+        let e = sum(1_000_000);
+        let traced = trace(&e, &env(&[("x", 0)]));
+        assert_eq!(traced.result(), &Ok(1_000_000));
+        assert_eq!(traced.explain(999_999).to_string(), "x + 1 = 0 + 1 = 1\n  x = 0\n");
+        // Writing each step takes the same time however deep it is.
+        let mut head = Summary(String::new());
+        for step in (0..1_000_000).step_by(1000) {
+            head.0.clear();
+            assert!(write!(head, "{}", traced.explain(step)).is_err());
+            assert!(head.0.starts_with("... + 1 + 1"), "{}", head.0);
+        }
+    }
+}
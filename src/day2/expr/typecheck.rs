@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use thiserror::Error;
+
+use super::{Expression, Number, Operation, UnaryOperation};
+
+/// The type of value an expression evaluates to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "a number"),
+            Type::Bool => write!(f, "a boolean"),
+        }
+    }
+}
+
+/// An expression that mixes up numbers and booleans, carrying the offending
+/// sub-expression.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum TypeError<N = i64> {
+    #[error("expected {expected}, but `{expr}` is {found}")]
+    Mismatch { expected: Type, found: Type, expr: Expression<N> },
+    #[error("the branches of `{0}` have different types")]
+    BranchMismatch(Expression<N>),
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+    #[error("`{name}` takes {expected} arguments but was given {found}")]
+    Arity { name: String, expected: usize, found: usize },
+}
+
+/// A type being inferred: either known, or an unknown numbered in
+/// [`Checker::unknowns`], which unification settles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Known(Type),
+    Unknown(usize),
+}
+
+/// A name bound by `let`, `fn` or a parameter. Values and functions have
+/// separate names.
+#[derive(Clone, Copy)]
+enum Binding {
+    Var(Ty),
+    /// A function taking `arity` arguments, whose parameter types are the
+    /// unknowns `first..first + arity` and whose result type is the unknown
+    /// `first + arity`.
+    Function {
+        arity: usize,
+        first: usize,
+    },
+}
+
+struct Checker<'a, 'v> {
+    /// The names bound around the expression being checked, innermost last.
+    /// Entering a scope pushes its bindings and leaving it truncates them.
+    scope: Vec<(&'a str, Binding)>,
+    /// The types of free variables.
+    vars: &'v HashMap<String, Type>,
+    /// What each unknown has been unified with so far.
+    unknowns: Vec<Option<Ty>>,
+}
+
+/// The type of an expression whose variables are all numbers.
+pub fn typecheck<N: Number>(e: &Expression<N>) -> Result<Type, TypeError<N>> {
+    typecheck_with(e, &HashMap::new())
+}
+
+/// The type of an expression, taking the types of variables from `vars`.
+/// Variables that are not listed are numbers.
+///
+/// Arithmetic and ordering need numbers, `&&`, `||`, `!` and conditions need
+/// booleans, and both sides of `==`, `!=` and both branches of an `if` need
+/// the same type. An expression that passes can be evaluated without ever
+/// treating a boolean as a number or the other way around.
+///
+/// The types of function parameters and results are inferred in a single
+/// pass: each starts out unknown and is settled by the first use that needs
+/// a particular type, and those never settled are numbers. A function has a
+/// single type, so it cannot take a number in one call and a boolean in
+/// another. Calls must refer to a function in scope and pass it the right
+/// number of arguments.
+pub fn typecheck_with<N: Number>(
+    e: &Expression<N>,
+    vars: &HashMap<String, Type>,
+) -> Result<Type, TypeError<N>> {
+    let mut checker = Checker { scope: Vec::new(), vars, unknowns: Vec::new() };
+    let ty = checker.check(e)?;
+    Ok(checker.known(ty))
+}
+
+impl<'a> Checker<'a, '_> {
+    /// `count` new unknowns, numbered from the one returned.
+    fn fresh(&mut self, count: usize) -> usize {
+        let first = self.unknowns.len();
+        self.unknowns.resize(first + count, None);
+        first
+    }
+
+    /// What `ty` is known to be so far.
+    fn resolve(&self, mut ty: Ty) -> Ty {
+        while let Ty::Unknown(i) = ty {
+            match self.unknowns[i] {
+                Some(next) => ty = next,
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// `ty` as a type, taking unknowns that nothing has settled as numbers.
+    fn known(&self, ty: Ty) -> Type {
+        match self.resolve(ty) {
+            Ty::Known(ty) => ty,
+            Ty::Unknown(_) => Type::Int,
+        }
+    }
+
+    /// Make `a` and `b` the same type, or return false if they are known to
+    /// differ.
+    fn unify(&mut self, a: Ty, b: Ty) -> bool {
+        match (self.resolve(a), self.resolve(b)) {
+            (a, b) if a == b => true,
+            (Ty::Unknown(i), other) | (other, Ty::Unknown(i)) => {
+                self.unknowns[i] = Some(other);
+                true
+            }
+            (Ty::Known(_), Ty::Known(_)) => false,
+        }
+    }
+
+    fn lookup_var(&self, name: &str) -> Ty {
+        let bound = self.scope.iter().rev().find_map(|&(bound, binding)| match binding {
+            Binding::Var(ty) if bound == name => Some(ty),
+            _ => None,
+        });
+        bound.unwrap_or_else(|| Ty::Known(self.vars.get(name).copied().unwrap_or(Type::Int)))
+    }
+
+    fn lookup_function(&self, name: &str) -> Option<(usize, usize)> {
+        self.scope.iter().rev().find_map(|&(bound, binding)| match binding {
+            Binding::Function { arity, first } if bound == name => Some((arity, first)),
+            _ => None,
+        })
+    }
+
+    /// The type of `e`, checked with an explicit stack rather than recursion
+    /// so that arbitrarily deep trees can be checked.
+    fn check<N: Number>(&mut self, e: &'a Expression<N>) -> Result<Ty, TypeError<N>> {
+        let mut tasks = vec![Task::Check(e)];
+        let mut types = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Check(e) => self.visit(e, &mut tasks, &mut types)?,
+                Task::Expect(e, expected) => {
+                    let found = types.pop().expect("operand type");
+                    self.expect(e, found, expected)?;
+                }
+                Task::Same(e) => {
+                    let found = types.pop().expect("right operand type");
+                    let expected = types.pop().expect("left operand type");
+                    self.expect(e, found, expected)?;
+                }
+                Task::Branches(e) => {
+                    let other = types.pop().expect("else branch type");
+                    let ty = *types.last().expect("then branch type");
+                    if !self.unify(ty, other) {
+                        return Err(TypeError::BranchMismatch(e.clone()));
+                    }
+                }
+                Task::Push(ty) => types.push(ty),
+                Task::Bind(name) => {
+                    let ty = types.pop().expect("bound value type");
+                    self.scope.push((name, Binding::Var(ty)));
+                }
+                Task::Truncate(len) => self.scope.truncate(len),
+            }
+        }
+        let ty = types.pop().expect("expression type");
+        debug_assert!(types.is_empty());
+        Ok(ty)
+    }
+
+    /// Fail unless `found`, the type of `e`, can be made `expected`.
+    fn expect<N: Number>(
+        &mut self,
+        e: &Expression<N>,
+        found: Ty,
+        expected: Ty,
+    ) -> Result<(), TypeError<N>> {
+        if self.unify(found, expected) {
+            Ok(())
+        } else {
+            Err(TypeError::Mismatch {
+                expected: self.known(expected),
+                found: self.known(found),
+                expr: e.clone(),
+            })
+        }
+    }
+
+    /// Push the type of `e` if it is a leaf, or else the tasks that check
+    /// its children and combine their types. Tasks run last pushed first.
+    fn visit<N: Number>(
+        &mut self,
+        e: &'a Expression<N>,
+        tasks: &mut Vec<Task<'a, N>>,
+        types: &mut Vec<Ty>,
+    ) -> Result<(), TypeError<N>> {
+        let (int, bool) = (Ty::Known(Type::Int), Ty::Known(Type::Bool));
+        match e {
+            Expression::Value(_) => types.push(int),
+            Expression::Bool(_) => types.push(bool),
+            Expression::Var(name) => types.push(self.lookup_var(name)),
+            Expression::Let { name, value, body } => tasks.extend([
+                Task::Truncate(self.scope.len()),
+                Task::Check(body),
+                Task::Bind(name),
+                Task::Check(value),
+            ]),
+            Expression::Define { function, body } => {
+                // The body of the function sees the function itself, for
+                // recursive calls, and its parameters.
+                let outer = self.scope.len();
+                let arity = function.params.len();
+                let first = self.fresh(arity + 1);
+                self.scope.push((&function.name, Binding::Function { arity, first }));
+                let inner = self.scope.len();
+                for (i, param) in function.params.iter().enumerate() {
+                    self.scope.push((param, Binding::Var(Ty::Unknown(first + i))));
+                }
+                tasks.extend([
+                    Task::Truncate(outer),
+                    Task::Check(body),
+                    Task::Truncate(inner),
+                    Task::Expect(&function.body, Ty::Unknown(first + arity)),
+                    Task::Check(&function.body),
+                ]);
+            }
+            Expression::Call { name, args } => {
+                let Some((arity, first)) = self.lookup_function(name) else {
+                    return Err(TypeError::UnknownFunction(name.clone()));
+                };
+                if args.len() != arity {
+                    return Err(TypeError::Arity {
+                        name: name.clone(),
+                        expected: arity,
+                        found: args.len(),
+                    });
+                }
+                tasks.push(Task::Push(Ty::Unknown(first + arity)));
+                for (i, arg) in args.iter().enumerate().rev() {
+                    tasks.extend([Task::Expect(arg, Ty::Unknown(first + i)), Task::Check(arg)]);
+                }
+            }
+            Expression::Unary { op, operand } => {
+                let ty = if *op == UnaryOperation::Not { bool } else { int };
+                tasks.extend([Task::Push(ty), Task::Expect(operand, ty), Task::Check(operand)]);
+            }
+            Expression::Op { op: Operation::Eq | Operation::Ne, left, right } => {
+                tasks.extend([
+                    Task::Push(bool),
+                    Task::Same(right),
+                    Task::Check(right),
+                    Task::Check(left),
+                ]);
+            }
+            Expression::Op { op, left, right } => {
+                let (operands, ty) = match op {
+                    Operation::And | Operation::Or => (bool, bool),
+                    op if op.is_boolean() => (int, bool),
+                    _ => (int, int),
+                };
+                tasks.extend([
+                    Task::Push(ty),
+                    Task::Expect(right, operands),
+                    Task::Check(right),
+                    Task::Expect(left, operands),
+                    Task::Check(left),
+                ]);
+            }
+            Expression::If { cond, then, otherwise } => tasks.extend([
+                Task::Branches(e),
+                Task::Check(otherwise),
+                Task::Check(then),
+                Task::Expect(cond, bool),
+                Task::Check(cond),
+            ]),
+        }
+        Ok(())
+    }
+}
+
+/// A pending step of [`Checker::check`], which keeps the types of the
+/// expressions checked so far on a stack.
+enum Task<'a, N> {
+    /// Check the expression and push its type.
+    Check(&'a Expression<N>),
+    /// Pop the type of the expression and make it the given type.
+    Expect(&'a Expression<N>, Ty),
+    /// Pop the type of the right operand of `==` or `!=` and make it that of
+    /// the left operand, popping that as well.
+    Same(&'a Expression<N>),
+    /// Pop the type of the `else` branch of the `If` and make it that of the
+    /// `then` branch, which is left as the type of the `If`.
+    Branches(&'a Expression<N>),
+    /// Push the type of an expression whose operands have been checked.
+    Push(Ty),
+    /// Pop the type of the value of a `let` and bind its name to it.
+    Bind(&'a str),
+    /// Drop the names bound since the scope had this many.
+    Truncate(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::parse;
+
+    fn checked(input: &str) -> Result<Type, TypeError> {
+        typecheck(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn well_typed() {
+        assert_eq!(checked("price * 9 / 10"), Ok(Type::Int));
+        assert_eq!(checked("price > 100 && !(qty == 0)"), Ok(Type::Bool));
+        assert_eq!(checked("true != (1 < 2)"), Ok(Type::Bool));
+        assert_eq!(checked("if price > 100 then price * 9 / 10 else price"), Ok(Type::Int));
+        assert_eq!(checked("if true then false else x >= 1"), Ok(Type::Bool));
+    }
+
+    #[test]
+    fn mixing_is_rejected() {
+        let mismatch = |expected, found, expr: &str| TypeError::Mismatch {
+            expected,
+            found,
+            expr: parse(expr).unwrap(),
+        };
+        assert_eq!(checked("1 + true"), Err(mismatch(Type::Int, Type::Bool, "true")));
+        assert_eq!(checked("x && y > 1"), Err(mismatch(Type::Bool, Type::Int, "x")));
+        assert_eq!(checked("!(x + 1)"), Err(mismatch(Type::Bool, Type::Int, "x + 1")));
+        assert_eq!(checked("-(x < 1)"), Err(mismatch(Type::Int, Type::Bool, "x < 1")));
+        assert_eq!(checked("x == (y > 1)"), Err(mismatch(Type::Int, Type::Bool, "y > 1")));
+        assert_eq!(checked("(1 < 2) < 3"), Err(mismatch(Type::Int, Type::Bool, "1 < 2")));
+        assert_eq!(checked("if x then 1 else 2"), Err(mismatch(Type::Bool, Type::Int, "x")));
+        let e = parse("if x > 0 then 1 else false").unwrap();
+        assert_eq!(typecheck(&e), Err(TypeError::BranchMismatch(e.clone())));
+    }
+
+    #[test]
+    fn variable_types() {
+        let e = parse("if big then price * 9 / 10 else price").unwrap();
+        assert!(typecheck(&e).is_err());
+        let vars = HashMap::from([(String::from("big"), Type::Bool)]);
+        assert_eq!(typecheck_with(&e, &vars), Ok(Type::Int));
+    }
+
+    #[test]
+    fn bindings() {
+        assert_eq!(checked("let big = price > 100 in if big then 1 else 2"), Ok(Type::Int));
+        assert_eq!(checked("fn tax(x) = x * 8 / 100 in price + tax(price)"), Ok(Type::Int));
+        assert_eq!(checked("fn pos(x) = x > 0 in pos(1) && !pos(2)"), Ok(Type::Bool));
+        let fact = "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1) in fact(5)";
+        assert_eq!(checked(fact), Ok(Type::Int));
+        let even = "fn even(n) = if n == 0 then true else !even(n - 1) in even(4)";
+        assert_eq!(checked(even), Ok(Type::Bool));
+        let mismatch = |expected, found, expr: &str| TypeError::Mismatch {
+            expected,
+            found,
+            expr: parse(expr).unwrap(),
+        };
+        assert_eq!(checked("let b = true in b + 1"), Err(mismatch(Type::Int, Type::Bool, "b")));
+        assert_eq!(
+            checked("fn pos(x) = x > 0 in pos(1) + 1"),
+            Err(mismatch(Type::Int, Type::Bool, "pos(1)"))
+        );
+        // Parameter types come from the body, or else from the calls.
+        assert_eq!(
+            checked("fn f(x) = x + 1 in f(true)"),
+            Err(mismatch(Type::Int, Type::Bool, "true"))
+        );
+        assert_eq!(
+            checked("fn f(x) = x && true in f(1)"),
+            Err(mismatch(Type::Bool, Type::Int, "1"))
+        );
+        assert_eq!(checked("fn f(x) = x && true in f(1 > 0)"), Ok(Type::Bool));
+        assert_eq!(checked("fn id(x) = x in id(true)"), Ok(Type::Bool));
+        assert_eq!(
+            checked("fn id(x) = x in id(1) + id(true)"),
+            Err(mismatch(Type::Int, Type::Bool, "true"))
+        );
+        // Never settled, so a number.
+        assert_eq!(checked("fn loop(x) = loop(x) in loop(1)"), Ok(Type::Int));
+        assert_eq!(checked("f(1)"), Err(TypeError::UnknownFunction(String::from("f"))));
+        assert_eq!(
+            checked("fn f(x) = x in f()"),
+            Err(TypeError::Arity { name: String::from("f"), expected: 1, found: 0 })
+        );
+    }
+
+    #[test]
+    fn nested_functions_are_checked_once() {
+        // Checking each body twice would take 2^40 steps.
+        let mut input = String::from("x");
+        for i in 0..40 {
+            input = format!("fn f{i}(x) = {input} in f{i}(x)");
+        }
+        assert_eq!(checked(&format!("let x = true in {input}")), Ok(Type::Bool));
+    }
+
+    #[test]
+    fn deep_trees() {
+        let mut e = Expression::Var(String::from("x"));
+        for _ in 0..1_000_000 {
+            e = Expression::Op {
+                op: Operation::Add,
+                left: Box::new(e),
+                right: Box::new(Expression::Value(1)),
+            };
+        }
+        assert_eq!(typecheck(&e), Ok(Type::Int));
+        let mut e = Expression::Op {
+            op: Operation::Lt,
+            left: Box::new(e),
+            right: Box::new(Expression::Value(1)),
+        };
+        for _ in 0..1_000_000 {
+            e = Expression::Unary { op: UnaryOperation::Not, operand: Box::new(e) };
+        }
+        assert_eq!(typecheck(&e), Ok(Type::Bool));
+        let vars = HashMap::from([(String::from("x"), Type::Bool)]);
+        assert_eq!(
+            typecheck_with(&e, &vars),
+            Err(TypeError::Mismatch {
+                expected: Type::Int,
+                found: Type::Bool,
+                expr: Expression::Var(String::from("x")),
+            })
+        );
+    }
+}
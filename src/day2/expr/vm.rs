@@ -0,0 +1,615 @@
+use super::{Env, EvalError, Expression, Number, Operation, UnaryOperation, MAX_CALL_DEPTH};
+
+/// A single step of a compiled expression, operating on a value stack.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction<N = i64> {
+    /// Push a constant.
+    Push(N),
+    /// Push the value of a free variable, from the `Env`.
+    Load(String),
+    /// Push the value in `slot` of the frame of the function `depth` levels
+    /// out of the current one, counting the functions it is defined in.
+    Local {
+        depth: usize,
+        slot: usize,
+    },
+    /// Pop a value into the next slot of the current frame, until the
+    /// matching `Unbind`.
+    Bind,
+    /// Drop the last slot of the current frame.
+    Unbind,
+    /// Pop `arity` arguments into the slots of a new frame and continue at
+    /// `target`. The function is defined `depth` levels out of the current
+    /// one.
+    Call {
+        target: usize,
+        arity: usize,
+        depth: usize,
+    },
+    /// Drop the current frame and continue after the call that made it.
+    Return,
+    /// Stop with an error, for calls of functions that aren't in scope or
+    /// take a different number of arguments.
+    Fail(EvalError<N>),
+    /// Pop two values and push the result of the operation on them.
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Min,
+    Max,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    /// Replace the top value with the result of the operation on it.
+    Neg,
+    Abs,
+    Not,
+    /// Continue at the given instruction.
+    Jump(usize),
+    /// Pop a value and continue at the given instruction if it is true.
+    JumpIf(usize),
+    /// Pop a value and continue at the given instruction if it is false.
+    JumpUnless(usize),
+}
+
+/// An expression lowered to a flat sequence of stack instructions.
+///
+/// Compile once with `compile` and `run` as often as needed; `run` gives the
+/// same results and errors as `eval_with` on the source expression.
+#[derive(Debug)]
+pub struct Program<'a, N = i64> {
+    code: Vec<Instruction<N>>,
+    /// For each instruction, the node of the source it came from, to report
+    /// errors against.
+    origins: Vec<&'a Expression<N>>,
+    max_stack: usize,
+}
+
+/// What `compile` knows about a function in scope.
+struct Compiled<'a> {
+    name: &'a str,
+    arity: usize,
+    /// The address of the body.
+    target: usize,
+    /// How many functions the definition is nested in.
+    level: usize,
+}
+
+/// The names in scope while compiling.
+#[derive(Default)]
+struct Scope<'a> {
+    /// The names in the slots of each frame, outermost first: the code
+    /// outside of any function, then the functions being compiled.
+    frames: Vec<Vec<&'a str>>,
+    functions: Vec<Compiled<'a>>,
+}
+
+/// Lower `e` into a `Program`.
+///
+/// Function bodies are compiled once, where they are defined, and called
+/// with frames of their own. Variables are resolved to slots up front, so
+/// only free variables are looked up by name when the program runs.
+pub fn compile<N: Number>(e: &Expression<N>) -> Program<'_, N> {
+    let mut program = Program { code: Vec::new(), origins: Vec::new(), max_stack: 0 };
+    let mut scope = Scope { frames: vec![Vec::new()], ..Scope::default() };
+    program.max_stack = program.emit(e, &mut scope);
+    program
+}
+
+/// A pending step of `emit`. Jumps are emitted with a target of 0 and
+/// patched once the code they jump to is known.
+enum Emit<'a, N> {
+    /// Emit the code for a node and push the stack depth it needs.
+    Node(&'a Expression<N>),
+    /// After the value of a `let`: bind it and emit the body.
+    Bind(&'a Expression<N>),
+    /// After the body of a `let`: drop the binding.
+    Unbind(&'a Expression<N>),
+    /// After the body of a function: return, and emit the body of the
+    /// `Define` past the jump at the given address.
+    Return(&'a Expression<N>, usize),
+    /// After the body of a `Define`: the function goes out of scope.
+    Undefine,
+    /// After the arguments of a call: make it.
+    Call(&'a Expression<N>, Instruction<N>),
+    /// After the left operand of `&&` or `||`: jump if it decides.
+    Left(&'a Expression<N>),
+    /// After the right operand of `&&` or `||`, whose left operand jumps
+    /// from the given address: push the result.
+    Right(&'a Expression<N>, usize),
+    /// After the operands of an operation: apply it.
+    Apply(&'a Expression<N>),
+    /// After the condition of an `If`: jump to the else branch if it is
+    /// false, and emit the then branch.
+    Cond(&'a Expression<N>),
+    /// After the then branch, with the address of the jump to the else
+    /// branch.
+    Then(&'a Expression<N>, usize),
+    /// After the else branch, with the address of the jump past it.
+    Else(usize),
+}
+
+/// A call being run, or the code outside of any function.
+struct Frame {
+    /// Where the frame's slots start.
+    base: usize,
+    /// The frame of the function the called one is defined in.
+    link: usize,
+    /// Where to continue after returning.
+    return_to: usize,
+}
+
+impl<'a, N: Number> Program<'a, N> {
+    /// Append the code for `e`, returning the stack depth it needs, not
+    /// counting the calls it makes.
+    ///
+    /// `&&`, `||` and `if` become jumps, so that what they do not need is
+    /// skipped just like in `eval_with`. Like `eval_with`, this uses an
+    /// explicit stack, so arbitrarily deep trees can be compiled.
+    fn emit(&mut self, e: &'a Expression<N>, scope: &mut Scope<'a>) -> usize {
+        let mut tasks = vec![Emit::Node(e)];
+        // The stack depths needed by the nodes emitted so far.
+        let mut depths = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Emit::Node(e) => match e {
+                    Expression::Value(v) => {
+                        self.push(Instruction::Push(v.clone()), e);
+                        depths.push(1);
+                    }
+                    Expression::Bool(b) => {
+                        self.push(Instruction::Push(N::from_bool(*b)), e);
+                        depths.push(1);
+                    }
+                    Expression::Var(name) => {
+                        let local =
+                            scope.frames.iter().rev().enumerate().find_map(|(depth, slots)| {
+                                let slot = slots.iter().rposition(|slot| slot == name)?;
+                                Some(Instruction::Local { depth, slot })
+                            });
+                        self.push(local.unwrap_or_else(|| Instruction::Load(name.clone())), e);
+                        depths.push(1);
+                    }
+                    Expression::Let { value, .. } => {
+                        tasks.push(Emit::Bind(e));
+                        tasks.push(Emit::Node(value));
+                    }
+                    Expression::Define { function, .. } => {
+                        // fn f(x) = d in b: Jump(B); d; Return; B: b
+                        let skip = self.push(Instruction::Jump(0), e);
+                        scope.functions.push(Compiled {
+                            name: &function.name,
+                            arity: function.params.len(),
+                            target: self.code.len(),
+                            level: scope.frames.len() - 1,
+                        });
+                        scope.frames.push(function.params.iter().map(String::as_str).collect());
+                        tasks.push(Emit::Return(e, skip));
+                        tasks.push(Emit::Node(&function.body));
+                    }
+                    Expression::Call { name, args } => {
+                        let Some(function) = scope.functions.iter().rev().find(|f| f.name == name)
+                        else {
+                            let err = EvalError::UnknownFunction(name.clone());
+                            self.push(Instruction::Fail(err), e);
+                            depths.push(0);
+                            continue;
+                        };
+                        if function.arity != args.len() {
+                            let err = EvalError::Arity {
+                                name: name.clone(),
+                                expected: function.arity,
+                                found: args.len(),
+                            };
+                            self.push(Instruction::Fail(err), e);
+                            depths.push(0);
+                            continue;
+                        }
+                        let call = Instruction::Call {
+                            target: function.target,
+                            arity: function.arity,
+                            depth: scope.frames.len() - 1 - function.level,
+                        };
+                        tasks.push(Emit::Call(e, call));
+                        tasks.extend(args.iter().rev().map(Emit::Node));
+                    }
+                    Expression::Op { op: Operation::And | Operation::Or, left, .. } => {
+                        tasks.push(Emit::Left(e));
+                        tasks.push(Emit::Node(left));
+                    }
+                    Expression::Op { left, right, .. } => {
+                        tasks.push(Emit::Apply(e));
+                        tasks.push(Emit::Node(right));
+                        tasks.push(Emit::Node(left));
+                    }
+                    Expression::Unary { operand, .. } => {
+                        tasks.push(Emit::Apply(e));
+                        tasks.push(Emit::Node(operand));
+                    }
+                    Expression::If { cond, .. } => {
+                        tasks.push(Emit::Cond(e));
+                        tasks.push(Emit::Node(cond));
+                    }
+                },
+                Emit::Bind(e @ Expression::Let { name, body, .. }) => {
+                    self.push(Instruction::Bind, e);
+                    scope.frames.last_mut().expect("no frame").push(name);
+                    tasks.push(Emit::Unbind(e));
+                    tasks.push(Emit::Node(body));
+                }
+                Emit::Unbind(e) => {
+                    scope.frames.last_mut().expect("no frame").pop();
+                    self.push(Instruction::Unbind, e);
+                    let body = depths.pop().expect("missing body depth");
+                    let value = depths.pop().expect("missing value depth");
+                    depths.push(value.max(body));
+                }
+                Emit::Return(e @ Expression::Define { body, .. }, skip) => {
+                    // The body of the function runs on top of its caller.
+                    depths.pop().expect("missing function depth");
+                    scope.frames.pop();
+                    self.push(Instruction::Return, e);
+                    self.patch(skip, self.code.len());
+                    tasks.push(Emit::Undefine);
+                    tasks.push(Emit::Node(body));
+                }
+                Emit::Undefine => {
+                    scope.functions.pop();
+                }
+                Emit::Call(e @ Expression::Call { args, .. }, call) => {
+                    let args = depths.split_off(depths.len() - args.len());
+                    let depth = args.into_iter().enumerate().map(|(i, arg)| i + arg).max();
+                    self.push(call, e);
+                    depths.push(depth.unwrap_or(0).max(1));
+                }
+                Emit::Left(e @ Expression::Op { op, right, .. }) => {
+                    // a && b: a; JumpUnless(F); b; JumpUnless(F); Push(1); Jump(E);
+                    //     F: Push(0); E:
+                    // and `||` the other way around.
+                    let jump = if *op == Operation::And {
+                        Instruction::JumpUnless(0)
+                    } else {
+                        Instruction::JumpIf(0)
+                    };
+                    let left_jump = self.push(jump, e);
+                    tasks.push(Emit::Right(e, left_jump));
+                    tasks.push(Emit::Node(right));
+                }
+                Emit::Right(e @ Expression::Op { op, .. }, left_jump) => {
+                    let decided = *op == Operation::Or;
+                    let right_jump = self.push(self.code[left_jump].clone(), e);
+                    self.push(Instruction::Push(N::from_bool(!decided)), e);
+                    let end_jump = self.push(Instruction::Jump(0), e);
+                    let target = self.push(Instruction::Push(N::from_bool(decided)), e);
+                    self.patch(left_jump, target);
+                    self.patch(right_jump, target);
+                    self.patch(end_jump, self.code.len());
+                    let right = depths.pop().expect("missing right depth");
+                    let left = depths.pop().expect("missing left depth");
+                    depths.push(left.max(right));
+                }
+                Emit::Apply(e @ Expression::Op { op, .. }) => {
+                    let instruction = match op {
+                        Operation::Add => Instruction::Add,
+                        Operation::Sub => Instruction::Sub,
+                        Operation::Mul => Instruction::Mul,
+                        Operation::Div => Instruction::Div,
+                        Operation::Rem => Instruction::Rem,
+                        Operation::Pow => Instruction::Pow,
+                        Operation::Min => Instruction::Min,
+                        Operation::Max => Instruction::Max,
+                        Operation::Lt => Instruction::Lt,
+                        Operation::Le => Instruction::Le,
+                        Operation::Eq => Instruction::Eq,
+                        Operation::Ne => Instruction::Ne,
+                        Operation::Gt => Instruction::Gt,
+                        Operation::Ge => Instruction::Ge,
+                        Operation::And | Operation::Or => unreachable!("compiled to jumps"),
+                    };
+                    self.push(instruction, e);
+                    let right = depths.pop().expect("missing right depth");
+                    let left = depths.pop().expect("missing left depth");
+                    depths.push(left.max(right + 1));
+                }
+                Emit::Apply(e @ Expression::Unary { op, .. }) => {
+                    let instruction = match op {
+                        UnaryOperation::Neg => Instruction::Neg,
+                        UnaryOperation::Abs => Instruction::Abs,
+                        UnaryOperation::Not => Instruction::Not,
+                    };
+                    self.push(instruction, e);
+                }
+                Emit::Cond(e @ Expression::If { then, .. }) => {
+                    let else_jump = self.push(Instruction::JumpUnless(0), e);
+                    tasks.push(Emit::Then(e, else_jump));
+                    tasks.push(Emit::Node(then));
+                }
+                Emit::Then(e @ Expression::If { otherwise, .. }, else_jump) => {
+                    let end_jump = self.push(Instruction::Jump(0), e);
+                    self.patch(else_jump, self.code.len());
+                    tasks.push(Emit::Else(end_jump));
+                    tasks.push(Emit::Node(otherwise));
+                }
+                Emit::Else(end_jump) => {
+                    self.patch(end_jump, self.code.len());
+                    let otherwise = depths.pop().expect("missing else depth");
+                    let then = depths.pop().expect("missing then depth");
+                    let cond = depths.pop().expect("missing condition depth");
+                    depths.push(cond.max(then).max(otherwise));
+                }
+                Emit::Bind(_)
+                | Emit::Return(..)
+                | Emit::Call(..)
+                | Emit::Left(_)
+                | Emit::Right(..)
+                | Emit::Apply(_)
+                | Emit::Cond(_)
+                | Emit::Then(..) => unreachable!("no such step for this expression"),
+            }
+        }
+        depths.pop().expect("no depth left")
+    }
+
+    /// Append an instruction compiled from `origin`, returning its address.
+    fn push(&mut self, instruction: Instruction<N>, origin: &'a Expression<N>) -> usize {
+        self.code.push(instruction);
+        self.origins.push(origin);
+        self.code.len() - 1
+    }
+
+    /// Point the jump at `address` to `target`.
+    fn patch(&mut self, address: usize, target: usize) {
+        match &mut self.code[address] {
+            Instruction::Jump(to) | Instruction::JumpIf(to) | Instruction::JumpUnless(to) => {
+                *to = target
+            }
+            _ => unreachable!("not a jump"),
+        }
+    }
+
+    /// The instructions making up the program.
+    pub fn code(&self) -> &[Instruction<N>] {
+        &self.code
+    }
+
+    /// Execute the program, taking variable values from `env`.
+    pub fn run(&self, env: &Env<N>) -> Result<N, EvalError<N>> {
+        let mut stack = Vec::with_capacity(self.max_stack);
+        // The slots of all frames, outermost first.
+        let mut slots: Vec<N> = Vec::new();
+        let mut frames = vec![Frame { base: 0, link: 0, return_to: self.code.len() }];
+        let mut pc = 0;
+        while let Some(instruction) = self.code.get(pc) {
+            let mut next = pc + 1;
+            match instruction {
+                Instruction::Push(v) => stack.push(v.clone()),
+                Instruction::Load(name) => stack.push(
+                    env.get(name)
+                        .cloned()
+                        .ok_or_else(|| EvalError::UnboundVariable(name.clone()))?,
+                ),
+                Instruction::Local { depth, slot } => {
+                    let frame = Self::outer(&frames, *depth);
+                    stack.push(slots[frames[frame].base + slot].clone());
+                }
+                Instruction::Bind => slots.push(stack.pop().expect("stack underflow")),
+                Instruction::Unbind => {
+                    slots.pop().expect("no slot to drop");
+                }
+                Instruction::Call { target, arity, depth } => {
+                    // The outermost frame isn't a call.
+                    if frames.len() - 1 == MAX_CALL_DEPTH {
+                        return Err(EvalError::RecursionLimit(self.origins[pc].clone()));
+                    }
+                    let link = Self::outer(&frames, *depth);
+                    let base = slots.len();
+                    slots.extend(stack.drain(stack.len() - arity..));
+                    frames.push(Frame { base, link, return_to: next });
+                    next = *target;
+                }
+                Instruction::Return => {
+                    let frame = frames.pop().expect("no frame to return from");
+                    slots.truncate(frame.base);
+                    next = frame.return_to;
+                }
+                Instruction::Fail(err) => return Err(err.clone()),
+                Instruction::Add => self.binary(&mut stack, pc, Operation::Add)?,
+                Instruction::Sub => self.binary(&mut stack, pc, Operation::Sub)?,
+                Instruction::Mul => self.binary(&mut stack, pc, Operation::Mul)?,
+                Instruction::Div => self.binary(&mut stack, pc, Operation::Div)?,
+                Instruction::Rem => self.binary(&mut stack, pc, Operation::Rem)?,
+                Instruction::Pow => self.binary(&mut stack, pc, Operation::Pow)?,
+                Instruction::Min => self.binary(&mut stack, pc, Operation::Min)?,
+                Instruction::Max => self.binary(&mut stack, pc, Operation::Max)?,
+                Instruction::Neg => self.unary(&mut stack, pc, UnaryOperation::Neg)?,
+                Instruction::Lt => self.binary(&mut stack, pc, Operation::Lt)?,
+                Instruction::Le => self.binary(&mut stack, pc, Operation::Le)?,
+                Instruction::Eq => self.binary(&mut stack, pc, Operation::Eq)?,
+                Instruction::Ne => self.binary(&mut stack, pc, Operation::Ne)?,
+                Instruction::Gt => self.binary(&mut stack, pc, Operation::Gt)?,
+                Instruction::Ge => self.binary(&mut stack, pc, Operation::Ge)?,
+                Instruction::Abs => self.unary(&mut stack, pc, UnaryOperation::Abs)?,
+                Instruction::Not => self.unary(&mut stack, pc, UnaryOperation::Not)?,
+                Instruction::Jump(target) => next = *target,
+                Instruction::JumpIf(target) | Instruction::JumpUnless(target) => {
+                    let value = stack.pop().expect("stack underflow");
+                    if value.is_true() == matches!(instruction, Instruction::JumpIf(_)) {
+                        next = *target;
+                    }
+                }
+            }
+            pc = next;
+        }
+        Ok(stack.pop().expect("empty program"))
+    }
+
+    /// The index of the frame `depth` levels out of the current one.
+    fn outer(frames: &[Frame], depth: usize) -> usize {
+        (0..depth).fold(frames.len() - 1, |frame, _| frames[frame].link)
+    }
+
+    fn binary(&self, stack: &mut Vec<N>, pc: usize, op: Operation) -> Result<(), EvalError<N>> {
+        let right = stack.pop().expect("stack underflow");
+        let left = stack.pop().expect("stack underflow");
+        let value = op.apply(&left, &right).map_err(|err| err.at(self.origins[pc].clone()))?;
+        stack.push(value);
+        Ok(())
+    }
+
+    fn unary(&self, stack: &mut Vec<N>, pc: usize, op: UnaryOperation) -> Result<(), EvalError<N>> {
+        let operand = stack.pop().expect("stack underflow");
+        let value = op.apply(&operand).map_err(|err| err.at(self.origins[pc].clone()))?;
+        stack.push(value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::arbitrary::{Config, Generator};
+    use crate::expr::eval_with;
+
+    #[test]
+    fn compiles_to_postfix() {
+        let e = crate::expr::parse("(a - 4) * 5").unwrap();
+        assert_eq!(
+            compile(&e).code(),
+            &[
+                Instruction::Load(String::from("a")),
+                Instruction::Push(4),
+                Instruction::Sub,
+                Instruction::Push(5),
+                Instruction::Mul,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_source() {
+        let e = crate::expr::parse("1 + (2 * (7 / (a - a)))").unwrap();
+        let env = Env::from([(String::from("a"), 3)]);
+        assert_eq!(
+            compile(&e).run(&env),
+            Err(EvalError::DivisionByZero(crate::expr::parse("7 / (a - a)").unwrap()))
+        );
+    }
+
+    #[test]
+    fn short_circuits() {
+        let e = crate::expr::parse("a != 0 && 10 / a > 1 || if a == 0 then false else 1 / 0 == 0")
+            .unwrap();
+        let program = compile(&e);
+        assert_eq!(program.run(&Env::from([(String::from("a"), 0)])), Ok(0));
+        assert_eq!(program.run(&Env::from([(String::from("a"), 5)])), Ok(1));
+        assert_eq!(
+            program.run(&Env::from([(String::from("a"), 20)])),
+            Err(EvalError::DivisionByZero(crate::expr::parse("1 / 0").unwrap()))
+        );
+    }
+
+    #[test]
+    fn bindings() {
+        let e = crate::expr::parse("let x = a + 1 in x * x").unwrap();
+        assert_eq!(
+            compile(&e).code(),
+            [
+                Instruction::Load(String::from("a")),
+                Instruction::Push(1),
+                Instruction::Add,
+                Instruction::Bind,
+                Instruction::Local { depth: 0, slot: 0 },
+                Instruction::Local { depth: 0, slot: 0 },
+                Instruction::Mul,
+                Instruction::Unbind,
+            ]
+        );
+        let env = Env::from([(String::from("a"), 3), (String::from("x"), 100)]);
+        for input in [
+            "(let x = a + 1 in x * x) + x",
+            "let k = 2 in fn f(v) = v * k + x in let x = 1 in f(a) + x",
+            "fn f(v) = v / (v - 3) in a + f(a)",
+            "let k = 1 in k / (k - 1)",
+        ] {
+            let e = crate::expr::parse(input).unwrap();
+            assert_eq!(compile(&e).run(&env), eval_with(&e, &env), "{input}");
+        }
+    }
+
+    #[test]
+    fn functions() {
+        let e = crate::expr::parse("let k = 2 in fn f(v) = v * k in f(3) + f(f(1))").unwrap();
+        assert_eq!(
+            compile(&e).code(),
+            [
+                Instruction::Push(2),
+                Instruction::Bind,
+                Instruction::Jump(7),
+                Instruction::Local { depth: 0, slot: 0 },
+                Instruction::Local { depth: 1, slot: 0 },
+                Instruction::Mul,
+                Instruction::Return,
+                Instruction::Push(3),
+                Instruction::Call { target: 3, arity: 1, depth: 0 },
+                Instruction::Push(1),
+                Instruction::Call { target: 3, arity: 1, depth: 0 },
+                Instruction::Call { target: 3, arity: 1, depth: 0 },
+                Instruction::Add,
+                Instruction::Unbind,
+            ]
+        );
+        let env = Env::from([(String::from("a"), 5)]);
+        for input in [
+            "fn fact(n) = if n <= 1 then 1 else n * fact(n - 1) in fact(a) + fact(10)",
+            "fn f(n) = fn g(m) = if m == 0 then n else f(m - 1) in g(n) in f(a)",
+            "fn loop(n) = loop(n + 1) in loop(0)",
+            "fn f(v) = v in f(1, 2)",
+            "fn f(v) = v in if a > 0 then 1 else g(1)",
+            "fn f(v) = 1 / v in f(a) + f(a - 5)",
+            "let x = 3 in fn f(v) = v + x in let x = 10 in fn g(x) = f(x) * x in g(a) + x",
+        ] {
+            let e = crate::expr::parse(input).unwrap();
+            assert_eq!(compile(&e).run(&env), eval_with(&e, &env), "{input}");
+        }
+    }
+
+    #[test]
+    fn deep_trees() {
+        let mut e = Expression::Value(1);
+        for _ in 0..1_000_000 {
+            e = Expression::Op {
+                op: Operation::Add,
+                left: Box::new(e),
+                right: Box::new(Expression::Value(1)),
+            };
+        }
+        let program = compile(&e);
+        assert_eq!(program.code().len(), 2_000_001);
+        assert_eq!(program.run(&Env::new()), Ok(1_000_001));
+    }
+
+    #[test]
+    fn matches_eval() {
+        let config = Config {
+            max_depth: 6,
+            extremes: true,
+            vars: vec!["a", "b", "c"],
+            well_typed: false,
+            functions: true,
+            ..Config::default()
+        };
+        let mut generator = Generator::new(0x2545_f491_4f6c_dd1d, config);
+        // "c" is deliberately left unbound.
+        let env = Env::from([(String::from("a"), 7), (String::from("b"), -3)]);
+        for _ in 0..2000 {
+            let e = generator.any();
+            assert_eq!(compile(&e).run(&env), eval_with(&e, &env), "{e:?}");
+        }
+    }
+}
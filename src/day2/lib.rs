@@ -0,0 +1,5 @@
+//! The expression language and the loggers of day 2, shared by the `day2`
+//! and `calc` binaries.
+
+pub mod expr;
+pub mod logging;
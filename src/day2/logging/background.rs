@@ -0,0 +1,389 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+use super::{Logger, Record};
+
+/// What an [`AsyncLogger`] does with a record when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait until the writer thread makes room.
+    Block,
+    /// Drop the record being logged.
+    DropNewest,
+    /// Drop the oldest queued record to make room.
+    DropOldest,
+}
+
+/// Hands records to a writer thread, so that logging doesn't wait for a
+/// slow output.
+///
+/// Records are stamped when they are logged, not when they are written.
+/// Writing failures are kept until the next [`flush`](AsyncLogger::flush).
+/// Dropping the logger writes what is still queued, like
+/// [`shutdown`](AsyncLogger::shutdown).
+pub struct AsyncLogger {
+    shared: Arc<Shared>,
+    /// Shared with the writer thread, so that it can be flushed from here.
+    inner: Arc<dyn Logger>,
+    overflow: Overflow,
+    writer: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    /// Signalled when a record is queued or the logger shuts down.
+    queued: Condvar,
+    /// Signalled when records leave the queue or have been written.
+    progress: Condvar,
+}
+
+struct State {
+    queue: VecDeque<Record>,
+    capacity: usize,
+    /// How many records have been queued, and how many of those have been
+    /// written or dropped since.
+    accepted: u64,
+    finished: u64,
+    dropped: u64,
+    /// The first failure since the last flush.
+    error: Option<io::Error>,
+    closed: bool,
+    /// Whether the writer thread has stopped, normally or not.
+    stopped: bool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, condvar: &Condvar, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        condvar.wait(state).unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("the log writer thread has stopped")
+}
+
+impl AsyncLogger {
+    /// Start a writer thread passing records on to `inner`, with room for
+    /// `capacity` records waiting to be written.
+    ///
+    /// Panics if `capacity` is zero or the thread cannot be started.
+    pub fn new(inner: Box<dyn Logger>, capacity: usize, overflow: Overflow) -> Self {
+        assert!(capacity > 0, "an AsyncLogger needs room for at least one record");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity),
+                capacity,
+                accepted: 0,
+                finished: 0,
+                dropped: 0,
+                error: None,
+                closed: false,
+                stopped: false,
+            }),
+            queued: Condvar::new(),
+            progress: Condvar::new(),
+        });
+        let inner: Arc<dyn Logger> = Arc::from(inner);
+        let writer = {
+            let (shared, inner) = (Arc::clone(&shared), Arc::clone(&inner));
+            thread::Builder::new()
+                .name(String::from("log writer"))
+                .spawn(move || write_queued(&shared, &*inner))
+                .expect("failed to start the log writer thread")
+        };
+        AsyncLogger { shared, inner, overflow, writer: Some(writer) }
+    }
+
+    /// How many records have been dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Write every queued record and stop the writer thread.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.take() else { return Ok(()) };
+        let result = self.flush();
+        self.shared.lock().closed = true;
+        self.shared.queued.notify_one();
+        // A panic in the writer thread has already been reported, and
+        // `flush` has turned it into an error.
+        let _ = writer.join();
+        result
+    }
+}
+
+impl Drop for AsyncLogger {
+    fn drop(&mut self) {
+        if let Err(err) = self.stop() {
+            eprintln!("failed to write queued log records: {err}");
+        }
+    }
+}
+
+impl Logger for AsyncLogger {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.try_log_record(&Record::new(verbosity, "", message))
+    }
+
+    /// Only fails if the writer thread has stopped; records dropped because
+    /// the queue is full are counted instead.
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        let mut record = record.clone();
+        record.timestamp.get_or_insert_with(SystemTime::now);
+        let mut state = self.shared.lock();
+        while self.overflow == Overflow::Block
+            && state.queue.len() >= state.capacity
+            && !state.stopped
+        {
+            state = self.shared.wait(&self.shared.progress, state);
+        }
+        if state.stopped {
+            return Err(stopped());
+        }
+        if state.queue.len() >= state.capacity {
+            state.dropped += 1;
+            if self.overflow == Overflow::DropNewest {
+                return Ok(());
+            }
+            state.queue.pop_front();
+            state.finished += 1;
+        }
+        state.queue.push_back(record);
+        state.accepted += 1;
+        drop(state);
+        self.shared.queued.notify_one();
+        Ok(())
+    }
+
+    /// Wait until every record logged so far has been written, and return
+    /// the first failure to write one since the last flush. Once they are
+    /// written, the inner logger is flushed too.
+    fn flush(&self) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        let target = state.accepted;
+        while state.finished < target && !state.stopped {
+            state = self.shared.wait(&self.shared.progress, state);
+        }
+        match state.error.take() {
+            Some(err) => Err(err),
+            None if state.finished < target => Err(stopped()),
+            None => {
+                drop(state);
+                self.inner.flush()
+            }
+        }
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        self.inner.enabled(target, verbosity)
+    }
+}
+
+/// The writer thread: writes everything queued until the logger shuts down.
+fn write_queued(shared: &Shared, inner: &dyn Logger) {
+    /// Wakes up anyone waiting for the writer however it stops, panics
+    /// included.
+    struct Stopped<'a>(&'a Shared);
+
+    impl Drop for Stopped<'_> {
+        fn drop(&mut self) {
+            self.0.lock().stopped = true;
+            self.0.progress.notify_all();
+        }
+    }
+
+    let _stopped = Stopped(shared);
+    loop {
+        let mut state = shared.lock();
+        while state.queue.is_empty() && !state.closed {
+            state = shared.wait(&shared.queued, state);
+        }
+        if state.queue.is_empty() {
+            return;
+        }
+        let batch: Vec<Record> = state.queue.drain(..).collect();
+        drop(state);
+        shared.progress.notify_all();
+
+        let mut error = None;
+        for record in &batch {
+            if let Err(err) = inner.try_log_record(record) {
+                error.get_or_insert(err);
+            }
+        }
+        let mut state = shared.lock();
+        state.finished += batch.len() as u64;
+        if state.error.is_none() {
+            state.error = error;
+        }
+        drop(state);
+        shared.progress.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use super::*;
+    use crate::logging::MemoryLogger;
+
+    /// Holds up every record until it is released, and says when it has
+    /// started writing one.
+    struct Gated {
+        memory: MemoryLogger,
+        started: Sender<()>,
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl Logger for Gated {
+        fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+            // Only the first record is waited for.
+            let _ = self.started.send(());
+            self.release.lock().unwrap().recv().unwrap();
+            self.memory.try_log(verbosity, message)
+        }
+    }
+
+    /// A logger whose writer is stuck on the record `stuck`, and a way to
+    /// release records one by one.
+    fn stuck(capacity: usize, overflow: Overflow) -> (AsyncLogger, MemoryLogger, Sender<()>) {
+        let memory = MemoryLogger::new();
+        let (started, has_started) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let gated = Gated { memory: memory.clone(), started, release: Mutex::new(released) };
+        let logger = AsyncLogger::new(Box::new(gated), capacity, overflow);
+        logger.log(1, &"stuck");
+        has_started.recv().unwrap();
+        (logger, memory, release)
+    }
+
+    fn texts(memory: &MemoryLogger) -> Vec<String> {
+        memory.messages().into_iter().map(|(_, message)| message).collect()
+    }
+
+    #[test]
+    fn writes_everything_in_order() {
+        let memory = MemoryLogger::new();
+        let logger = AsyncLogger::new(Box::new(memory.clone()), 4, Overflow::Block);
+        for i in 0..1000 {
+            logger.log_record(&Record::new(2, "burst", i).with("i", i));
+        }
+        logger.flush().unwrap();
+        let expected: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        assert_eq!(texts(&memory), expected);
+        assert_eq!(logger.dropped(), 0);
+        // Records are stamped when logged.
+        assert!(memory.records().iter().all(|record| record.timestamp.is_some()));
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (logger, memory, release) = stuck(2, Overflow::DropNewest);
+        for message in ["a", "b", "c", "d"] {
+            logger.log(1, &message);
+        }
+        assert_eq!(logger.dropped(), 2);
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        logger.flush().unwrap();
+        assert_eq!(texts(&memory), ["stuck", "a", "b"]);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (logger, memory, release) = stuck(2, Overflow::DropOldest);
+        for message in ["a", "b", "c", "d"] {
+            logger.log(1, &message);
+        }
+        assert_eq!(logger.dropped(), 2);
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        logger.flush().unwrap();
+        assert_eq!(texts(&memory), ["stuck", "c", "d"]);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (logger, memory, release) = stuck(1, Overflow::Block);
+        let logger = Arc::new(logger);
+        logger.log(1, &"a");
+        // The queue is full, so this waits until the writer takes "a".
+        let blocked = {
+            let logger = Arc::clone(&logger);
+            thread::spawn(move || logger.log(1, &"b"))
+        };
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        blocked.join().unwrap();
+        logger.flush().unwrap();
+        assert_eq!(texts(&memory), ["stuck", "a", "b"]);
+        assert_eq!(logger.dropped(), 0);
+    }
+
+    #[test]
+    fn shutdown_and_drop_write_what_is_queued() {
+        let (logger, memory, release) = stuck(8, Overflow::Block);
+        logger.log(1, &"queued");
+        for _ in 0..2 {
+            release.send(()).unwrap();
+        }
+        logger.shutdown().unwrap();
+        assert_eq!(texts(&memory), ["stuck", "queued"]);
+
+        let memory = MemoryLogger::new();
+        let logger = AsyncLogger::new(Box::new(memory.clone()), 8, Overflow::DropNewest);
+        logger.log(1, &"before exit");
+        drop(logger);
+        assert_eq!(texts(&memory), ["before exit"]);
+    }
+
+    #[test]
+    fn failures_are_reported_by_flush() {
+        struct Failing;
+
+        impl Logger for Failing {
+            fn try_log(&self, _: u8, message: &dyn Display) -> io::Result<()> {
+                Err(io::Error::other(format!("cannot write {message}")))
+            }
+        }
+
+        let logger = AsyncLogger::new(Box::new(Failing), 8, Overflow::Block);
+        logger.log(1, &"one");
+        logger.log(1, &"two");
+        assert_eq!(logger.flush().unwrap_err().to_string(), "cannot write one");
+        assert!(logger.flush().is_ok());
+        assert!(logger.shutdown().is_ok());
+    }
+
+    #[test]
+    fn a_panicking_writer_is_reported() {
+        struct Panicking;
+
+        impl Logger for Panicking {
+            fn try_log(&self, _: u8, _: &dyn Display) -> io::Result<()> {
+                panic!("the output exploded");
+            }
+        }
+
+        let logger = AsyncLogger::new(Box::new(Panicking), 1, Overflow::Block);
+        logger.log(1, &"boom");
+        assert_eq!(logger.flush().unwrap_err().to_string(), "the log writer thread has stopped");
+        assert!(logger.try_log(1, &"after").is_err());
+    }
+}
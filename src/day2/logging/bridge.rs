@@ -0,0 +1,135 @@
+use log::{Level, LevelFilter, Metadata, SetLoggerError};
+
+use super::{Logger, Record};
+
+/// The verbosity of a `log` level: `Error` is 1 up to `Trace` at 5, the same
+/// scale as the level names in [`Directives`](super::Directives).
+fn verbosity(level: Level) -> u8 {
+    match level {
+        Level::Error => 1,
+        Level::Warn => 2,
+        Level::Info => 3,
+        Level::Debug => 4,
+        Level::Trace => 5,
+    }
+}
+
+/// Passes the records of the `log` crate on to a [`Logger`].
+///
+/// A record's target, which is the module path of the code that logged it
+/// unless it names another target, becomes the target of our record.
+pub struct LogBridge {
+    logger: Box<dyn Logger>,
+}
+
+impl LogBridge {
+    pub fn new(logger: Box<dyn Logger>) -> Self {
+        LogBridge { logger }
+    }
+}
+
+impl log::Log for LogBridge {
+    /// Whatever the logger says, so that `log_enabled!` sees the verdict of
+    /// a `VerbosityFilter`.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger.enabled(metadata.target(), verbosity(metadata.level()))
+    }
+
+    fn log(&self, record: &log::Record) {
+        let verbosity = verbosity(record.level());
+        self.logger.log_record(&Record::new(verbosity, record.target(), record.args()));
+    }
+
+    fn flush(&self) {
+        if let Err(err) = self.logger.flush() {
+            eprintln!("failed to flush the log: {err}");
+        }
+    }
+}
+
+/// Make `logger` the destination of the `log` crate's macros, for records up
+/// to `max_level`. This can only be done once; the logger then lives until
+/// the program exits without being dropped, so call `log::logger().flush()`
+/// before exiting to write what a logger such as an `AsyncLogger` still
+/// holds.
+pub fn install(logger: Box<dyn Logger>, max_level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(Box::leak(Box::new(LogBridge::new(logger))))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Log;
+
+    use super::*;
+    use crate::logging::{AsyncLogger, Directives, MemoryLogger, Overflow, VerbosityFilter};
+
+    #[test]
+    fn levels_map_onto_verbosities() {
+        let memory = MemoryLogger::new();
+        let bridge = LogBridge::new(Box::new(memory.clone()));
+        for level in [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace] {
+            let args = format_args!("{level}");
+            bridge.log(&log::Record::builder().level(level).target("lib").args(args).build());
+        }
+        let logged: Vec<_> =
+            memory.records().into_iter().map(|r| (r.verbosity, r.message)).collect();
+        assert_eq!(
+            logged,
+            [
+                (1, String::from("ERROR")),
+                (2, String::from("WARN")),
+                (3, String::from("INFO")),
+                (4, String::from("DEBUG")),
+                (5, String::from("TRACE")),
+            ]
+        );
+    }
+
+    #[test]
+    fn flushes_and_filters_go_to_the_logger() {
+        let memory = MemoryLogger::new();
+        let directives: Directives = "info,noisy=off".parse().unwrap();
+        let filter = VerbosityFilter::with_directives(directives, Box::new(memory.clone()));
+        let background = AsyncLogger::new(Box::new(filter), 16, Overflow::Block);
+        let bridge = LogBridge::new(Box::new(background));
+        let metadata = |level, target| Metadata::builder().level(level).target(target).build();
+        assert!(bridge.enabled(&metadata(Level::Info, "lib")));
+        assert!(!bridge.enabled(&metadata(Level::Debug, "lib")));
+        assert!(!bridge.enabled(&metadata(Level::Error, "noisy")));
+        for i in 0..10 {
+            let args = format_args!("record {i}");
+            bridge.log(&log::Record::builder().level(Level::Warn).target("lib").args(args).build());
+        }
+        bridge.flush();
+        assert_eq!(memory.records().len(), 10);
+    }
+
+    /// The only test that installs a global logger, which can only happen
+    /// once per process.
+    #[test]
+    fn installed_filters_see_module_paths() {
+        let memory = MemoryLogger::new();
+        let directives: Directives = "warn,day2::logging::bridge=debug".parse().unwrap();
+        let filter = VerbosityFilter::with_directives(directives, Box::new(memory.clone()));
+        install(Box::new(filter), LevelFilter::Trace).unwrap();
+        assert!(install(Box::new(MemoryLogger::new()), LevelFilter::Trace).is_err());
+
+        log::debug!("parsed {} lines", 3);
+        log::trace!("too detailed");
+        log::info!(target: "elsewhere", "filtered out");
+        log::warn!(target: "elsewhere", "kept");
+        assert!(log::log_enabled!(log::Level::Debug));
+        assert!(!log::log_enabled!(target: "elsewhere", log::Level::Info));
+        log::logger().flush();
+        let logged: Vec<_> = memory.records().into_iter().map(|r| (r.target, r.message)).collect();
+        assert_eq!(
+            logged,
+            [
+                (String::from("day2::logging::bridge::tests"), String::from("parsed 3 lines")),
+                (String::from("elsewhere"), String::from("kept")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::env;
+use std::ffi::OsString;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DirectiveError {
+    #[error("unknown level `{level}` in directive `{segment}`")]
+    UnknownLevel { segment: String, level: String },
+    #[error("missing target in directive `{segment}`")]
+    MissingTarget { segment: String },
+    #[error("`{var}` is not valid Unicode")]
+    NotUnicode { var: String },
+}
+
+/// The maximum verbosity to log for each target, parsed from directives
+/// such as `info,parser=debug,net=off`.
+///
+/// A directive is either a level on its own, which applies to every target
+/// without a directive of its own, or `target=level`. The levels are `off`,
+/// `error`, `warn`, `info`, `debug` and `trace`, which allow verbosities up
+/// to 1, 2, 3, 4 and 5, or a verbosity as a number. Targets without any
+/// directive that applies get `error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directives {
+    default: Option<u8>,
+    targets: Vec<(String, Option<u8>)>,
+}
+
+impl Default for Directives {
+    fn default() -> Self {
+        Directives { default: Some(1), targets: Vec::new() }
+    }
+}
+
+impl Directives {
+    /// Every target up to `max_verbosity`.
+    pub fn all(max_verbosity: u8) -> Self {
+        Directives { default: Some(max_verbosity), targets: Vec::new() }
+    }
+
+    /// The directives in the environment variable `var`, or `default` if it
+    /// isn't set.
+    pub fn from_env(var: &str, default: &str) -> Result<Self, DirectiveError> {
+        Directives::from_value(var, env::var_os(var), default)
+    }
+
+    /// The directives in `value`, the value of the variable `var`, or
+    /// `default` if there is none.
+    fn from_value(
+        var: &str,
+        value: Option<OsString>,
+        default: &str,
+    ) -> Result<Self, DirectiveError> {
+        match value.map(OsString::into_string) {
+            Some(Ok(directives)) => directives.parse(),
+            None => default.parse(),
+            Some(Err(_)) => Err(DirectiveError::NotUnicode { var: var.to_string() }),
+        }
+    }
+
+    /// The maximum verbosity logged for `target`, or `None` if it is off.
+    ///
+    /// The directive with the longest target that `target` is in applies:
+    /// `net` covers `net` and `net::http` but not `network`. Of several
+    /// directives for the same target, the last one applies.
+    pub fn max_verbosity(&self, target: &str) -> Option<u8> {
+        let mut best: Option<&(String, Option<u8>)> = None;
+        for directive in &self.targets {
+            let prefix = &directive.0;
+            let covers = target
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+            if covers && best.is_none_or(|(best, _)| prefix.len() >= best.len()) {
+                best = Some(directive);
+            }
+        }
+        best.map_or(self.default, |(_, max)| *max)
+    }
+
+    /// Whether a message at `verbosity` for `target` should be logged.
+    pub fn allows(&self, target: &str, verbosity: u8) -> bool {
+        self.max_verbosity(target).is_some_and(|max| verbosity <= max)
+    }
+}
+
+impl FromStr for Directives {
+    type Err = DirectiveError;
+
+    /// Directives are separated by commas; spaces around them and empty
+    /// directives are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut directives = Directives::default();
+        for segment in s.split(',').map(str::trim).filter(|segment| !segment.is_empty()) {
+            let unknown = |level: &str| DirectiveError::UnknownLevel {
+                segment: segment.to_string(),
+                level: level.to_string(),
+            };
+            match segment.split_once('=') {
+                None => directives.default = level(segment).ok_or_else(|| unknown(segment))?,
+                Some((target, max)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(DirectiveError::MissingTarget { segment: segment.to_string() });
+                    }
+                    let max = max.trim();
+                    let max = level(max).ok_or_else(|| unknown(max))?;
+                    directives.targets.push((target.to_string(), max));
+                }
+            }
+        }
+        Ok(directives)
+    }
+}
+
+/// The maximum verbosity for a level name or number: `Some(None)` for `off`.
+fn level(name: &str) -> Option<Option<u8>> {
+    Some(Some(match name.to_ascii_lowercase().as_str() {
+        "off" => return Some(None),
+        "error" => 1,
+        "warn" => 2,
+        "info" => 3,
+        "debug" => 4,
+        "trace" => 5,
+        number => return number.parse().ok().map(Some),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix_wins() {
+        let directives: Directives = "info, parser=debug,net=off,net::http=trace".parse().unwrap();
+        assert_eq!(directives.max_verbosity(""), Some(3));
+        assert_eq!(directives.max_verbosity("db"), Some(3));
+        assert_eq!(directives.max_verbosity("parser"), Some(4));
+        assert_eq!(directives.max_verbosity("parser::lexer"), Some(4));
+        assert_eq!(directives.max_verbosity("parsers"), Some(3));
+        assert_eq!(directives.max_verbosity("net"), None);
+        assert_eq!(directives.max_verbosity("net::tcp"), None);
+        assert_eq!(directives.max_verbosity("net::http::client"), Some(5));
+        assert!(directives.allows("parser", 4));
+        assert!(!directives.allows("parser", 5));
+        assert!(!directives.allows("net", 0));
+    }
+
+    #[test]
+    fn levels() {
+        let directives: Directives = "a=OFF,b=Error,c=warn,d=7,a=debug,,".parse().unwrap();
+        assert_eq!(directives.max_verbosity("a"), Some(4));
+        assert_eq!(directives.max_verbosity("b"), Some(1));
+        assert_eq!(directives.max_verbosity("c"), Some(2));
+        assert_eq!(directives.max_verbosity("d"), Some(7));
+        // Without a level of its own, everything else gets `error`.
+        assert_eq!(directives.max_verbosity("e"), Some(1));
+        assert_eq!("".parse(), Ok(Directives::default()));
+        assert_eq!("trace".parse(), Ok(Directives::all(5)));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            "info,parser=loud".parse::<Directives>(),
+            Err(DirectiveError::UnknownLevel {
+                segment: String::from("parser=loud"),
+                level: String::from("loud"),
+            })
+        );
+        assert_eq!(
+            "info,parser".parse::<Directives>().unwrap_err().to_string(),
+            "unknown level `parser` in directive `parser`"
+        );
+        assert_eq!(
+            "=debug".parse::<Directives>(),
+            Err(DirectiveError::MissingTarget { segment: String::from("=debug") })
+        );
+        assert_eq!(
+            "a=b=c".parse::<Directives>().unwrap_err().to_string(),
+            "unknown level `b=c` in directive `a=b=c`"
+        );
+        assert!("a=256".parse::<Directives>().is_err());
+    }
+
+    #[test]
+    fn from_value() {
+        let var = "DAY2_LOG";
+        let from =
+            |value: Option<&str>| Directives::from_value(var, value.map(OsString::from), "debug");
+        assert_eq!(from(None), Ok(Directives::all(4)));
+        assert_eq!(from(Some("warn,net=off")).unwrap().max_verbosity("net"), None);
+        assert_eq!(from(Some("")), Ok(Directives::default()));
+        assert!(matches!(
+            from(Some("net=everything")),
+            Err(DirectiveError::UnknownLevel { segment, .. }) if segment == "net=everything"
+        ));
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            let invalid = OsString::from_vec(vec![b'i', b'n', b'f', 0xff]);
+            assert_eq!(
+                Directives::from_value(var, Some(invalid), "debug"),
+                Err(DirectiveError::NotUnicode { var: var.to_string() })
+            );
+        }
+    }
+
+    #[test]
+    fn from_env() {
+        // Unlikely to be set; setting it would race with other threads.
+        let var = "DAY2_TEST_DIRECTIVES_NEVER_SET";
+        assert_eq!(Directives::from_env(var, "debug"), Ok(Directives::all(4)));
+    }
+}
@@ -0,0 +1,359 @@
+use std::fmt::Display;
+use std::io;
+
+mod background;
+mod bridge;
+mod directive;
+mod record;
+mod sink;
+pub use background::{AsyncLogger, Overflow};
+pub use bridge::install;
+pub use directive::{DirectiveError, Directives};
+pub use record::{Clock, Format, Record, SystemClock, Value};
+pub use sink::{FileLogger, MemoryLogger, RotatingFileLogger, StderrLogger, Style, Styled};
+
+/// Something that messages can be logged to.
+///
+/// Messages are passed as `&dyn Display`, so the trait can be used as
+/// `Box<dyn Logger>` and loggers can be put together at runtime. Use
+/// `format_args!` to log a formatted message without allocating. Loggers
+/// can be shared between threads.
+pub trait Logger: Send + Sync {
+    /// Log a message at the given verbosity level, failing if it could not
+    /// be written.
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()>;
+
+    /// Log a message at the given verbosity level. A message that could not
+    /// be written is reported on standard error instead.
+    fn log(&self, verbosity: u8, message: &dyn Display) {
+        if let Err(err) = self.try_log(verbosity, message) {
+            eprintln!("failed to log `{message}`: {err}");
+        }
+    }
+
+    /// Log a structured record, failing if it could not be written.
+    ///
+    /// Loggers that only write plain messages get the record's target,
+    /// message and fields as one message.
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        self.try_log(record.verbosity, record)
+    }
+
+    /// Log a structured record, reporting a failure on standard error.
+    fn log_record(&self, record: &Record) {
+        if let Err(err) = self.try_log_record(record) {
+            eprintln!("failed to log `{record}`: {err}");
+        }
+    }
+
+    /// Write out whatever has been logged but not written yet. Loggers that
+    /// write each message as it comes have nothing to do.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether a message at `verbosity` for `target` would be logged, so
+    /// that one that wouldn't needn't be put together. Only filters say no.
+    fn enabled(&self, _target: &str, _verbosity: u8) -> bool {
+        true
+    }
+}
+
+impl<L: Logger + ?Sized> Logger for &L {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        (**self).try_log(verbosity, message)
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        (**self).try_log_record(record)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        (**self).enabled(target, verbosity)
+    }
+}
+
+impl<L: Logger + ?Sized> Logger for Box<L> {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        (**self).try_log(verbosity, message)
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        (**self).try_log_record(record)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        (**self).enabled(target, verbosity)
+    }
+}
+
+/// Passes on the messages up to a maximum verbosity, which can depend on the
+/// target of a record.
+pub struct VerbosityFilter {
+    directives: Directives,
+    inner: Box<dyn Logger>,
+}
+
+impl VerbosityFilter {
+    /// Pass on everything up to `max_verbosity`, whatever the target.
+    pub fn new(max_verbosity: u8, inner: Box<dyn Logger>) -> Self {
+        VerbosityFilter::with_directives(Directives::all(max_verbosity), inner)
+    }
+
+    /// Pass on what `directives` allow. Plain messages have no target, so
+    /// they get the level that applies to every target.
+    pub fn with_directives(directives: Directives, inner: Box<dyn Logger>) -> Self {
+        VerbosityFilter { directives, inner }
+    }
+}
+
+impl Logger for VerbosityFilter {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        if self.directives.allows("", verbosity) {
+            self.inner.try_log(verbosity, message)?;
+        }
+        Ok(())
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        if self.directives.allows(&record.target, record.verbosity) {
+            self.inner.try_log_record(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        self.directives.allows(target, verbosity) && self.inner.enabled(target, verbosity)
+    }
+}
+
+/// Puts a fixed prefix in front of every message, such as the name of the
+/// component logging it.
+pub struct Prefixed {
+    prefix: String,
+    inner: Box<dyn Logger>,
+}
+
+impl Prefixed {
+    pub fn new(prefix: impl Into<String>, inner: Box<dyn Logger>) -> Self {
+        Prefixed { prefix: prefix.into(), inner }
+    }
+}
+
+impl Logger for Prefixed {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.inner.try_log(verbosity, &format_args!("{}{message}", self.prefix))
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        let message = format!("{}{}", self.prefix, record.message);
+        self.inner.try_log_record(&Record { message, ..record.clone() })
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        self.inner.enabled(target, verbosity)
+    }
+}
+
+/// Passes every message on to several loggers.
+#[derive(Default)]
+pub struct Tee {
+    outputs: Vec<Box<dyn Logger>>,
+}
+
+impl Tee {
+    pub fn new(outputs: Vec<Box<dyn Logger>>) -> Self {
+        Tee { outputs }
+    }
+
+    /// Add another logger to pass messages on to.
+    pub fn with(mut self, output: Box<dyn Logger>) -> Self {
+        self.outputs.push(output);
+        self
+    }
+}
+
+impl Logger for Tee {
+    /// Every logger gets the message even if an earlier one fails; the
+    /// first failure is returned.
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        first_error(self.outputs.iter().map(|output| output.try_log(verbosity, message)))
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        first_error(self.outputs.iter().map(|output| output.try_log_record(record)))
+    }
+
+    /// Every logger is flushed even if an earlier one fails.
+    fn flush(&self) -> io::Result<()> {
+        first_error(self.outputs.iter().map(|output| output.flush()))
+    }
+
+    fn enabled(&self, target: &str, verbosity: u8) -> bool {
+        self.outputs.iter().any(|output| output.enabled(target, verbosity))
+    }
+}
+
+/// Runs every write and returns the first error, if any.
+fn first_error(written: impl Iterator<Item = io::Result<()>>) -> io::Result<()> {
+    let mut result = Ok(());
+    for written in written {
+        if result.is_ok() {
+            result = written;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails to log anything.
+    struct Broken;
+
+    impl Logger for Broken {
+        fn try_log(&self, _: u8, _: &dyn Display) -> io::Result<()> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    #[test]
+    fn filters_by_verbosity() {
+        let memory = MemoryLogger::new();
+        let logger = VerbosityFilter::new(3, Box::new(memory.clone()));
+        logger.log(5, &"FYI");
+        logger.log(2, &"Uhoh");
+        logger.log(3, &format_args!("{} left", 7));
+        assert_eq!(memory.messages(), [(2, String::from("Uhoh")), (3, String::from("7 left"))]);
+        // Filtered messages don't fail.
+        assert!(VerbosityFilter::new(1, Box::new(Broken)).try_log(2, &"quiet").is_ok());
+    }
+
+    #[test]
+    fn pipelines_are_chosen_at_runtime() {
+        let memory = MemoryLogger::new();
+        for verbose in [false, true] {
+            let mut logger: Box<dyn Logger> = Box::new(memory.clone());
+            if !verbose {
+                logger = Box::new(VerbosityFilter::new(1, logger));
+            }
+            let logger = Prefixed::new(if verbose { "[v] " } else { "[q] " }, logger);
+            logger.log(0, &"start");
+            logger.log(4, &"details");
+        }
+        assert_eq!(
+            memory.messages(),
+            [
+                (0, String::from("[q] start")),
+                (0, String::from("[v] start")),
+                (4, String::from("[v] details")),
+            ]
+        );
+    }
+
+    #[test]
+    fn tee_reaches_every_output() {
+        let (first, second) = (MemoryLogger::new(), MemoryLogger::new());
+        let tee = Tee::new(vec![Box::new(first.clone()), Box::new(Broken)])
+            .with(Box::new(VerbosityFilter::new(2, Box::new(second.clone()))));
+        let err = tee.try_log(1, &"both").unwrap_err();
+        assert_eq!(err.to_string(), "broken");
+        tee.log(3, &"first only");
+        assert_eq!(first.messages(), [(1, String::from("both")), (3, String::from("first only"))]);
+        assert_eq!(second.messages(), [(1, String::from("both"))]);
+        assert!(Tee::default().try_log(0, &"nowhere").is_ok());
+    }
+
+    #[test]
+    fn records_pass_through_pipelines() {
+        let memory = MemoryLogger::new();
+        let tee = Tee::new(vec![Box::new(VerbosityFilter::new(2, Box::new(memory.clone())))]);
+        let logger = Prefixed::new("[day2] ", Box::new(tee));
+        logger.log_record(&Record::new(1, "net", "connected").with("port", 8080));
+        logger.log_record(&Record::new(3, "net", "sent").with("bytes", 12));
+        assert_eq!(
+            memory.records(),
+            [Record::new(1, "net", "[day2] connected").with("port", 8080)]
+        );
+    }
+
+    #[test]
+    fn filters_by_target() {
+        let memory = MemoryLogger::new();
+        let directives = "warn,parser=debug,net=off".parse().unwrap();
+        let logger = VerbosityFilter::with_directives(directives, Box::new(memory.clone()));
+        logger.log(3, &"plain");
+        logger.log(2, &"plain warning");
+        for target in ["parser", "parser::lexer", "net", "net::http", "db"] {
+            logger.log_record(&Record::new(3, target, "info"));
+        }
+        logger.log_record(&Record::new(5, "parser", "trace"));
+        let logged: Vec<_> = memory.records().into_iter().map(|r| r.target).collect();
+        assert_eq!(logged, ["", "parser", "parser::lexer"]);
+    }
+
+    #[test]
+    fn filters_say_what_is_enabled() {
+        let directives = "warn,parser=debug,net=off".parse().unwrap();
+        let filter = VerbosityFilter::with_directives(directives, Box::new(MemoryLogger::new()));
+        let logger = Prefixed::new("[day2] ", Box::new(filter));
+        assert!(logger.enabled("parser::lexer", 4));
+        assert!(!logger.enabled("parser", 5));
+        assert!(!logger.enabled("net", 1));
+        assert!(logger.enabled("", 2));
+        let tee =
+            Tee::new(vec![Box::new(logger), Box::new(VerbosityFilter::new(1, Box::new(Broken)))]);
+        assert!(tee.enabled("db", 1));
+        assert!(!tee.enabled("net", 3));
+        assert!(!Tee::default().enabled("db", 5));
+        assert!(MemoryLogger::new().enabled("anything", u8::MAX));
+    }
+
+    #[test]
+    fn flushes_pass_through_pipelines() {
+        let memory = MemoryLogger::new();
+        assert!(Prefixed::new("[day2] ", Box::new(memory.clone())).flush().is_ok());
+        let filtered = VerbosityFilter::new(0, Box::new(Broken));
+        assert_eq!(filtered.flush().unwrap_err().to_string(), "broken");
+        let tee = Tee::new(vec![Box::new(Broken), Box::new(memory)]);
+        assert_eq!(Box::new(&tee).flush().unwrap_err().to_string(), "broken");
+    }
+
+    #[test]
+    fn plain_loggers_get_records_as_messages() {
+        struct Plain(MemoryLogger);
+        impl Logger for Plain {
+            fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+                self.0.try_log(verbosity, message)
+            }
+        }
+        let memory = MemoryLogger::new();
+        let record = Record::new(2, "db", "slow query").with("ms", 250).with("sql", "SELECT 1");
+        Plain(memory.clone()).log_record(&record);
+        assert_eq!(
+            memory.messages(),
+            [(2, String::from("db: slow query ms=250 sql=\"SELECT 1\""))]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::borrow::Cow;
+use std::fmt::{self, Display, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The value of a field in a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Self {
+        Value::Int(value.into())
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => f.write_str(s),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// A message together with where it comes from and key-value fields
+/// describing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub verbosity: u8,
+    /// The component that logged the record, or empty if unknown.
+    pub target: String,
+    pub message: String,
+    pub fields: Vec<(String, Value)>,
+    /// When the record was made. Records without one are stamped by the
+    /// logger that writes them.
+    pub timestamp: Option<SystemTime>,
+}
+
+impl Record {
+    pub fn new(verbosity: u8, target: impl Into<String>, message: impl Display) -> Self {
+        Record {
+            verbosity,
+            target: target.into(),
+            message: message.to_string(),
+            fields: Vec::new(),
+            timestamp: None,
+        }
+    }
+
+    /// Add a field. Fields are written in the order they were added.
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+}
+
+/// The target, message and fields in logfmt style on a single line, for
+/// loggers that only take plain messages.
+impl Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.target.is_empty() {
+            write_line(f, &self.target)?;
+            f.write_str(": ")?;
+        }
+        write_line(f, &self.message)?;
+        for (key, value) in &self.fields {
+            f.write_char(' ')?;
+            write_logfmt(f, key)?;
+            f.write_char('=')?;
+            match value {
+                Value::Str(s) => write_logfmt(f, s)?,
+                value => write!(f, "{value}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where records get their timestamps from, so that tests can fix the time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+impl<F: Fn() -> SystemTime + Send + Sync> Clock for F {
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
+
+/// How records are written out, one line each.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `verbosity=2: target: message key=value`, for people to read. The
+    /// timestamp is left out.
+    #[default]
+    Text,
+    /// A JSON object with `ts`, `verbosity`, `target` and `msg` keys
+    /// followed by the fields. Fields named like those keys are prefixed
+    /// with `fields.`, so that no key appears twice.
+    JsonLines,
+    /// `ts=... verbosity=2 target=... msg=... key=value`, with fields renamed
+    /// as for `JsonLines`.
+    Logfmt,
+}
+
+impl Format {
+    /// The line for `record`, newline included. `timestamp` is used if the
+    /// record has none, and is written in UTC as RFC 3339 with milliseconds.
+    pub fn render(self, record: &Record, timestamp: SystemTime) -> String {
+        let ts = rfc3339(record.timestamp.unwrap_or(timestamp));
+        let mut line = String::new();
+        // Writing to a `String` cannot fail.
+        let _ = match self {
+            Format::Text => write!(line, "verbosity={}: {record}", record.verbosity),
+            Format::JsonLines => json(&mut line, record, &ts),
+            Format::Logfmt => logfmt(&mut line, record, &ts),
+        };
+        line.push('\n');
+        line
+    }
+}
+
+/// The keys `json` and `logfmt` write before the fields.
+const RESERVED_KEYS: [&str; 4] = ["ts", "verbosity", "target", "msg"];
+
+/// The key to write for the field `key`, prefixed if it is reserved.
+fn field_key(key: &str) -> Cow<'_, str> {
+    if RESERVED_KEYS.contains(&key) {
+        Cow::Owned(format!("fields.{key}"))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+fn json(out: &mut String, record: &Record, ts: &str) -> fmt::Result {
+    write!(out, "{{\"ts\":\"{ts}\",\"verbosity\":{},\"target\":", record.verbosity)?;
+    write_json(out, &record.target)?;
+    out.push_str(",\"msg\":");
+    write_json(out, &record.message)?;
+    for (key, value) in &record.fields {
+        out.push(',');
+        write_json(out, &field_key(key))?;
+        out.push(':');
+        match value {
+            Value::Str(s) => write_json(out, s)?,
+            value => write!(out, "{value}")?,
+        }
+    }
+    out.push('}');
+    Ok(())
+}
+
+fn logfmt(out: &mut String, record: &Record, ts: &str) -> fmt::Result {
+    write!(out, "ts={ts} verbosity={} target=", record.verbosity)?;
+    write_logfmt(out, &record.target)?;
+    out.push_str(" msg=");
+    write_logfmt(out, &record.message)?;
+    for (key, value) in &record.fields {
+        out.push(' ');
+        write_logfmt(out, &field_key(key))?;
+        out.push('=');
+        match value {
+            Value::Str(s) => write_logfmt(out, s)?,
+            value => write!(out, "{value}")?,
+        }
+    }
+    Ok(())
+}
+
+/// `s` as a JSON string literal.
+fn write_json(out: &mut impl Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// `s` as a logfmt key or value, quoted if it is empty or contains spaces,
+/// `=`, quotes or control characters.
+fn write_logfmt(out: &mut impl Write, s: &str) -> fmt::Result {
+    let bare = !s.is_empty()
+        && !s.chars().any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+    if bare {
+        out.write_str(s)
+    } else {
+        write_json(out, s)
+    }
+}
+
+/// `s` with line breaks escaped as `\n` and `\r`, so that it stays on one
+/// line.
+fn write_line(out: &mut impl Write, s: &str) -> fmt::Result {
+    for c in s.chars() {
+        match c {
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+/// `time` as `2024-03-01T12:30:05.250Z`. Times before 1970 are written as
+/// the epoch.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The Gregorian date `days` days after 1970-01-01, following Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn clock() -> impl Clock {
+        // 2024-02-29T23:59:58.250Z
+        || UNIX_EPOCH + Duration::from_millis(1_709_251_198_250)
+    }
+
+    fn record() -> Record {
+        Record::new(3, "parser", "read \"input\"")
+            .with("bytes", 120)
+            .with("path", "a b.txt")
+            .with("cached", false)
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(rfc3339(clock().now()), "2024-02-29T23:59:58.250Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_868_800)),
+            "2000-03-01T00:00:00.000Z"
+        );
+        assert_eq!(rfc3339(UNIX_EPOCH - Duration::from_secs(1)), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn json_lines() {
+        assert_eq!(
+            Format::JsonLines.render(&record(), clock().now()),
+            "{\"ts\":\"2024-02-29T23:59:58.250Z\",\"verbosity\":3,\"target\":\"parser\",\
+             \"msg\":\"read \\\"input\\\"\",\"bytes\":120,\"path\":\"a b.txt\",\"cached\":false}\n"
+        );
+        let record = Record { timestamp: Some(UNIX_EPOCH), ..Record::new(0, "", "tab\there\u{1}") };
+        assert_eq!(
+            Format::JsonLines.render(&record, clock().now()),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"verbosity\":0,\"target\":\"\",\
+             \"msg\":\"tab\\there\\u0001\"}\n"
+        );
+        let record = Record::new(1, "", "done").with("msg", "clash").with("ts", 5);
+        assert_eq!(
+            Format::JsonLines.render(&record, UNIX_EPOCH),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"verbosity\":1,\"target\":\"\",\
+             \"msg\":\"done\",\"fields.msg\":\"clash\",\"fields.ts\":5}\n"
+        );
+    }
+
+    #[test]
+    fn logfmt() {
+        assert_eq!(
+            Format::Logfmt.render(&record(), clock().now()),
+            "ts=2024-02-29T23:59:58.250Z verbosity=3 target=parser msg=\"read \\\"input\\\"\" \
+             bytes=120 path=\"a b.txt\" cached=false\n"
+        );
+        let record = Record::new(1, "", "done").with("mode", "a=b").with("user", "");
+        assert_eq!(
+            Format::Logfmt.render(&record, UNIX_EPOCH),
+            "ts=1970-01-01T00:00:00.000Z verbosity=1 target=\"\" msg=done mode=\"a=b\" user=\"\"\n"
+        );
+        let record = Record::new(1, "", "done").with("a b", 1).with("x=1 y", 2).with("msg", 3);
+        assert_eq!(
+            Format::Logfmt.render(&record, UNIX_EPOCH),
+            "ts=1970-01-01T00:00:00.000Z verbosity=1 target=\"\" msg=done \"a b\"=1 \
+             \"x=1 y\"=2 fields.msg=3\n"
+        );
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            Format::Text.render(&record(), clock().now()),
+            "verbosity=3: parser: read \"input\" bytes=120 path=\"a b.txt\" cached=false\n"
+        );
+        assert_eq!(
+            Format::Text.render(&Record::new(2, "", "Uhoh"), UNIX_EPOCH),
+            "verbosity=2: Uhoh\n"
+        );
+        let record = Record::new(2, "", "one\ntwo\r\nthree").with("a b", "c\nd");
+        assert_eq!(
+            Format::Text.render(&record, UNIX_EPOCH),
+            "verbosity=2: one\\ntwo\\r\\nthree \"a b\"=\"c\\nd\"\n"
+        );
+    }
+}
@@ -0,0 +1,423 @@
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::{Clock, Format, Logger, Record, SystemClock};
+
+/// How a sink turns records into lines: the format, and the clock that
+/// timestamps records without a time of their own.
+pub struct Style {
+    format: Format,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style { format: Format::default(), clock: Box::new(SystemClock) }
+    }
+}
+
+impl Style {
+    /// The line for a record, newline included. Records without a timestamp
+    /// get the current time of the clock.
+    fn line(&self, record: &Record) -> String {
+        self.format.render(record, self.clock.now())
+    }
+}
+
+/// A sink that writes records in a [`Style`], which can be chosen when it is
+/// built.
+pub trait Styled: Sized {
+    fn style_mut(&mut self) -> &mut Style;
+
+    fn with_format(mut self, format: Format) -> Self {
+        self.style_mut().format = format;
+        self
+    }
+
+    /// Take timestamps from `clock` instead of the system clock.
+    fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.style_mut().clock = Box::new(clock);
+        self
+    }
+}
+
+/// Writes every message to standard error, as text unless another format is
+/// chosen.
+#[derive(Default)]
+pub struct StderrLogger {
+    style: Style,
+}
+
+impl StderrLogger {
+    pub fn new() -> Self {
+        StderrLogger::default()
+    }
+}
+
+impl Styled for StderrLogger {
+    fn style_mut(&mut self) -> &mut Style {
+        &mut self.style
+    }
+}
+
+impl Logger for StderrLogger {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.try_log_record(&Record::new(verbosity, "", message))
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        io::stderr().lock().write_all(self.style.line(record).as_bytes())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Appends every message to a file, one line each, as text unless another
+/// format is chosen.
+pub struct FileLogger {
+    file: File,
+    style: Style,
+}
+
+impl FileLogger {
+    /// Open `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileLogger { file, style: Style::default() })
+    }
+}
+
+impl Styled for FileLogger {
+    fn style_mut(&mut self) -> &mut Style {
+        &mut self.style
+    }
+}
+
+impl Logger for FileLogger {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.try_log_record(&Record::new(verbosity, "", message))
+    }
+
+    /// Each line is written with a single call, so lines from several
+    /// loggers appending to the same file don't interleave.
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        (&self.file).write_all(self.style.line(record).as_bytes())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (&self.file).flush()
+    }
+}
+
+/// Appends to a file like [`FileLogger`], moving the file aside before it
+/// would grow past a size limit.
+///
+/// On rotation `app.log` becomes `app.log.1`, `app.log.1` becomes `app.log.2`
+/// and so on, and the file that would be numbered past the retention count
+/// is deleted. A line longer than the limit gets a file of its own. Threads
+/// sharing the logger take turns writing and rotating; separate loggers or
+/// processes writing to the same file are not coordinated.
+pub struct RotatingFileLogger {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    current: Mutex<Current>,
+    style: Style,
+}
+
+/// The file being written to and its size.
+struct Current {
+    file: File,
+    len: u64,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Ignores `NotFound`, for files that are not there yet.
+fn missing_is_fine(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+impl RotatingFileLogger {
+    /// Open `path` for appending, creating it if needed. It is rotated before
+    /// it would grow past `max_bytes`, and `keep` rotated files are kept.
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let len = file.metadata()?.len();
+        Ok(RotatingFileLogger {
+            path,
+            max_bytes,
+            keep,
+            current: Mutex::new(Current { file, len }),
+            style: Style::default(),
+        })
+    }
+
+    /// The path of the `n`th rotated file, such as `app.log.2`.
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        if self.keep == 0 {
+            missing_is_fine(fs::remove_file(&self.path))?;
+        } else {
+            missing_is_fine(fs::remove_file(self.rotated(self.keep)))?;
+            for n in (1..self.keep).rev() {
+                missing_is_fine(fs::rename(self.rotated(n), self.rotated(n + 1)))?;
+            }
+            missing_is_fine(fs::rename(&self.path, self.rotated(1)))?;
+        }
+        *current = Current { file: open_append(&self.path)?, len: 0 };
+        Ok(())
+    }
+}
+
+impl Styled for RotatingFileLogger {
+    fn style_mut(&mut self) -> &mut Style {
+        &mut self.style
+    }
+}
+
+impl Logger for RotatingFileLogger {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.try_log_record(&Record::new(verbosity, "", message))
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        let line = self.style.line(record);
+        let len = line.len() as u64;
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        if current.len > 0 && current.len + len > self.max_bytes {
+            self.rotate(&mut current)?;
+        }
+        current.file.write_all(line.as_bytes())?;
+        current.len += len;
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner).file.flush()
+    }
+}
+
+/// Keeps the records logged to it, so that they can be inspected. Plain
+/// messages are kept as records without a target or fields.
+///
+/// Clones share the same records: keep one and hand the other to the code
+/// under test.
+#[derive(Clone, Default)]
+pub struct MemoryLogger {
+    records: Arc<Mutex<Vec<Record>>>,
+}
+
+impl MemoryLogger {
+    pub fn new() -> Self {
+        MemoryLogger::default()
+    }
+
+    /// The verbosity and text of every message logged so far, oldest first.
+    pub fn messages(&self) -> Vec<(u8, String)> {
+        let records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+        records.iter().map(|record| (record.verbosity, record.message.clone())).collect()
+    }
+
+    /// Every record logged so far, oldest first.
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+impl Logger for MemoryLogger {
+    fn try_log(&self, verbosity: u8, message: &dyn Display) -> io::Result<()> {
+        self.try_log_record(&Record::new(verbosity, "", message))
+    }
+
+    fn try_log_record(&self, record: &Record) -> io::Result<()> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner).push(record.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    /// A fresh directory for a test to put files in.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("day2-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_appends_lines() {
+        let dir = temp_dir("file-sink");
+        let path = dir.join("app.log");
+        fs::write(&path, "earlier\n").unwrap();
+        let logger = FileLogger::open(&path).unwrap();
+        logger.try_log(2, &"Uhoh").unwrap();
+        logger.try_log(5, &format_args!("{} bytes", 12)).unwrap();
+        // Another logger on the same file appends after them.
+        FileLogger::open(&path).unwrap().try_log(0, &"again").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "earlier\nverbosity=2: Uhoh\nverbosity=5: 12 bytes\nverbosity=0: again\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_failures_are_reported() {
+        let dir = temp_dir("file-errors");
+        let missing = dir.join("missing").join("app.log");
+        assert_eq!(
+            FileLogger::open(missing).err().map(|err| err.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        fs::remove_dir_all(dir).unwrap();
+        // Writing to /dev/full always fails.
+        if cfg!(target_os = "linux") {
+            let full = FileLogger::open("/dev/full").unwrap();
+            let err = full.try_log(1, &"lost").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        }
+    }
+
+    #[test]
+    fn file_writes_records_in_the_chosen_format() {
+        let dir = temp_dir("file-format");
+        let path = dir.join("app.jsonl");
+        let clock = || UNIX_EPOCH + Duration::from_secs(86_400);
+        let logger =
+            FileLogger::open(&path).unwrap().with_format(Format::JsonLines).with_clock(clock);
+        logger.try_log_record(&Record::new(2, "net", "retrying").with("attempt", 3)).unwrap();
+        logger.try_log(4, &"plain").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"ts\":\"1970-01-02T00:00:00.000Z\",\"verbosity\":2,\"target\":\"net\",\
+             \"msg\":\"retrying\",\"attempt\":3}\n\
+             {\"ts\":\"1970-01-02T00:00:00.000Z\",\"verbosity\":4,\"target\":\"\",\"msg\":\"plain\"}\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The names of the files in `dir`, sorted.
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotation_keeps_the_newest_files() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("app.log");
+        // Each line is 20 bytes, so two fit under the limit.
+        let logger = RotatingFileLogger::open(&path, 50, 2).unwrap();
+        for i in 0..10 {
+            logger.try_log(1, &format_args!("line {i}")).unwrap();
+        }
+        assert_eq!(names(&dir), ["app.log", "app.log.1", "app.log.2"]);
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("app.log"), "verbosity=1: line 8\nverbosity=1: line 9\n");
+        assert_eq!(read("app.log.1"), "verbosity=1: line 6\nverbosity=1: line 7\n");
+        assert_eq!(read("app.log.2"), "verbosity=1: line 4\nverbosity=1: line 5\n");
+
+        // Reopening picks up the size of the existing file.
+        drop(logger);
+        let logger = RotatingFileLogger::open(&path, 50, 2).unwrap();
+        logger.try_log(2, &"reopened").unwrap();
+        // A line over the limit still gets written, to a file of its own.
+        logger.try_log(2, &"x".repeat(60)).unwrap();
+        logger.try_log(2, &"after").unwrap();
+        assert_eq!(names(&dir), ["app.log", "app.log.1", "app.log.2"]);
+        assert_eq!(read("app.log"), "verbosity=2: after\n");
+        assert_eq!(read("app.log.1"), format!("verbosity=2: {}\n", "x".repeat(60)));
+        assert_eq!(read("app.log.2"), "verbosity=2: reopened\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_without_retention() {
+        let dir = temp_dir("rotate-none");
+        let logger = RotatingFileLogger::open(dir.join("app.jsonl"), 100, 0)
+            .unwrap()
+            .with_format(Format::JsonLines)
+            .with_clock(|| UNIX_EPOCH);
+        for verbosity in 1..=3 {
+            logger.try_log(verbosity, &"hi").unwrap();
+        }
+        assert_eq!(names(&dir), ["app.jsonl"]);
+        assert_eq!(
+            fs::read_to_string(dir.join("app.jsonl")).unwrap(),
+            "{\"ts\":\"1970-01-01T00:00:00.000Z\",\"verbosity\":3,\"target\":\"\",\"msg\":\"hi\"}\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_is_shared_by_threads() {
+        const THREADS: usize = 4;
+        const LINES: usize = 100;
+        let dir = temp_dir("rotate-threads");
+        let path = dir.join("app.log");
+        // Five 20-byte lines per file, and room to keep every file.
+        let logger = RotatingFileLogger::open(&path, 100, THREADS * LINES / 5).unwrap();
+        std::thread::scope(|scope| {
+            for t in 0..THREADS {
+                let logger = &logger;
+                scope.spawn(move || {
+                    for i in 0..LINES {
+                        logger.try_log(1, &format_args!("t{t}-{i:03}")).unwrap();
+                    }
+                });
+            }
+        });
+        let files = names(&dir).len();
+        assert_eq!(files, THREADS * LINES / 5);
+        // Oldest first, every file full and every line whole and in order.
+        let mut seen = vec![0; THREADS];
+        for n in (0..files).rev() {
+            let name = if n == 0 { String::from("app.log") } else { format!("app.log.{n}") };
+            let contents = fs::read_to_string(dir.join(&name)).unwrap();
+            assert_eq!(contents.len(), 100, "{name}");
+            for line in contents.lines() {
+                let (t, i) = line.strip_prefix("verbosity=1: t").unwrap().split_once('-').unwrap();
+                let t: usize = t.parse().unwrap();
+                assert_eq!(i, format!("{:03}", seen[t]), "{name}");
+                seen[t] += 1;
+            }
+        }
+        assert_eq!(seen, [LINES; THREADS]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_is_shared_by_clones() {
+        let memory = MemoryLogger::new();
+        let clone = memory.clone();
+        clone.try_log(1, &"one").unwrap();
+        std::thread::spawn(move || clone.log(2, &"two")).join().unwrap();
+        assert_eq!(memory.messages(), [(1, String::from("one")), (2, String::from("two"))]);
+    }
+}
@@ -1,128 +1,10 @@
-use std::fmt::Display;
+use day2::expr::{eval, Expression, Operation};
 
-/// An operation to perform on two subexpressions.
-#[derive(Debug)]
-enum Operation {
-    Add,
-    Sub,
-    Mul,
-    Div,
-}
-
-/// An expression, in tree form.
-#[derive(Debug)]
-enum Expression {
-    /// An operation on two subexpressions.
-    Op { op: Operation, left: Box<Expression>, right: Box<Expression> },
-
-    /// A literal value
-    Value(i64),
-}
-
-fn eval(e: Expression) -> Result<i64, String> {
-    match e {
-        Expression::Value(v) => Ok(v),
-        Expression::Op { op, left, right } => {
-            match op {
-                Operation::Add => Ok(eval(*left)? + eval(*right)?),
-                Operation::Sub => Ok(eval(*left)? - eval(*right)?),
-                Operation::Mul => Ok(eval(*left)? * eval(*right)?),
-                Operation::Div => {
-                    let right = eval(*right)?;
-                    if right == 0 {
-                        Err(String::from("division by zero"))
-                    } else {
-                        Ok(eval(*left)? / right)
-                    }
-                }
-            }
-        }
-    }
-}
-
-#[test]
-fn test_value() {
-    assert_eq!(eval(Expression::Value(19)), Ok(19));
-}
-
-#[test]
-fn test_sum() {
-    assert_eq!(
-        eval(Expression::Op {
-            op: Operation::Add,
-            left: Box::new(Expression::Value(10)),
-            right: Box::new(Expression::Value(20)),
-        }),
-        Ok(30)
-    );
-}
-
-#[test]
-fn test_recursion() {
-    let term1 = Expression::Op {
-        op: Operation::Mul,
-        left: Box::new(Expression::Value(10)),
-        right: Box::new(Expression::Value(9)),
-    };
-    let term2 = Expression::Op {
-        op: Operation::Mul,
-        left: Box::new(Expression::Op {
-            op: Operation::Sub,
-            left: Box::new(Expression::Value(3)),
-            right: Box::new(Expression::Value(4)),
-        }),
-        right: Box::new(Expression::Value(5)),
-    };
-    assert_eq!(
-        eval(Expression::Op {
-            op: Operation::Add,
-            left: Box::new(term1),
-            right: Box::new(term2),
-        }),
-        Ok(85)
-    );
-}
-
-#[test]
-fn test_error() {
-    assert_eq!(
-        eval(Expression::Op {
-            op: Operation::Div,
-            left: Box::new(Expression::Value(99)),
-            right: Box::new(Expression::Value(0)),
-        }),
-        Err(String::from("division by zero"))
-    );
-}
-
-pub trait Logger {
-    /// Log a message at the given verbosity level.
-    fn log(&self, verbosity: u8, message: impl Display);
-}
-
-struct StderrLogger;
-struct VerbosityFilter {
-    max_verbosity: u8,
-    inner: StderrLogger,
-}
-
-impl Logger for VerbosityFilter {
-    fn log(&self, verbosity: u8, message: impl Display) {
-        if self.max_verbosity >= verbosity {
-            self.inner.log(verbosity, message);
-        }
-    }
-}
-
-impl Logger for StderrLogger {
-    fn log(&self, verbosity: u8, message: impl Display) {
-        eprintln!("verbosity={verbosity}: {message}");
-    }
-}
+use day2::logging::{Logger, StderrLogger, VerbosityFilter};
 
-fn do_things(logger: &impl Logger) {
-    logger.log(5, "FYI");
-    logger.log(2, "Uhoh");
+fn do_things(logger: &dyn Logger) {
+    logger.log(5, &"FYI");
+    logger.log(2, &"Uhoh");
 }
 trait LessThan {
     /// Return true if self is less than other.
@@ -235,14 +117,14 @@ mod test {
 }
 
 fn main() {
-    println!("{}", eval(Expression::Op { op: (Operation::Add), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
-    println!("{}", eval(Expression::Op { op: (Operation::Sub), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
-    println!("{}", eval(Expression::Op { op: (Operation::Mul), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
-    println!("{}", eval(Expression::Op { op: (Operation::Div), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
+    println!("{}", eval(&Expression::Op { op: (Operation::Add), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
+    println!("{}", eval(&Expression::Op { op: (Operation::Sub), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
+    println!("{}", eval(&Expression::Op { op: (Operation::Mul), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
+    println!("{}", eval(&Expression::Op { op: (Operation::Div), left: (Box::new(Expression::Value(11))), right: (Box::new(Expression::Value(11))) }).unwrap());
 
     // TODO: Define and implement `VerbosityFilter`.
     // Methods and Traits
-    let l = VerbosityFilter { max_verbosity: 3, inner: StderrLogger };
+    let l = VerbosityFilter::new(3, Box::new(StderrLogger::new()));
     do_things(&l);
 
     // Generic
@@ -280,3 +162,17 @@ fn main() {
     rot.read_to_string(&mut result).unwrap();
     println!("{}", result);
 }
+
+#[cfg(test)]
+mod logger_test {
+    use day2::logging::MemoryLogger;
+
+    use super::*;
+
+    #[test]
+    fn things_are_logged() {
+        let memory = MemoryLogger::new();
+        do_things(&VerbosityFilter::new(3, Box::new(memory.clone())));
+        assert_eq!(memory.messages(), [(2, String::from("Uhoh"))]);
+    }
+}
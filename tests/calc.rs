@@ -0,0 +1,43 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Pipe `input` into `calc` and wait for it to exit.
+fn run(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_calc"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Pipe `tests/fixtures/calc/<name>.in` into `calc` and check that it prints
+/// exactly `<name>.out` and exits with the given status.
+fn check_fixture(name: &str, success: bool) {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/calc");
+    let input = fs::read_to_string(format!("{dir}/{name}.in")).unwrap();
+    let expected = fs::read_to_string(format!("{dir}/{name}.out")).unwrap();
+    let output = run(&input);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected, "{name}.in");
+    assert_eq!(output.status.success(), success, "{name}.in");
+}
+
+#[test]
+fn session() {
+    check_fixture("session", true);
+}
+
+#[test]
+fn errors() {
+    check_fixture("errors", false);
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let output = run(&format!("{}\n", "(".repeat(200_000)));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("^ error: expression nested more than 256 deep at offset 256\n"));
+    assert!(!output.status.success());
+}